@@ -1,4 +1,5 @@
 use std::io;
+use std::path::PathBuf;
 
 use config::ConfigError;
 use reqwest::Error as ReqErr;
@@ -42,6 +43,42 @@ pub enum Error {
     },
     #[snafu(display("Unable to create interval period"))]
     PeriodError,
+    #[snafu(display("invalid speed table {}: {message}", path.display()))]
+    InvalidSpeedTable { path: PathBuf, message: String },
+    #[snafu(display("node location cache error"))]
+    NodeCache { source: io::Error },
+    #[snafu(display("node location cache is shared and cannot be updated"))]
+    SharedNodeCache,
+    #[snafu(display("file not found: {}", path.display()))]
+    FileNotFound { path: PathBuf, source: io::Error },
+    #[snafu(display("unable to read {}", path.display()))]
+    FileRead { path: PathBuf, source: io::Error },
+    #[snafu(display("pbf decode error in blob at byte offset {offset}"))]
+    PbfDecode { offset: u64, source: osmpbfreader::Error },
+    #[snafu(display("way {way_id} references missing node {node_id}"))]
+    MissingNode { way_id: i64, node_id: i64 },
+    #[snafu(display("osm xml error"))]
+    OsmXml { source: quick_xml::Error },
+    #[snafu(display("invalid osm xml: {message}"))]
+    InvalidOsmXml { message: String },
+    #[snafu(display("unrecognised osm input format: {}", path.display()))]
+    UnknownInputFormat { path: PathBuf },
+    #[snafu(display("unable to write {}", path.display()))]
+    FileWrite { path: PathBuf, source: io::Error },
+    #[snafu(display("invalid replication state: {message}"))]
+    InvalidReplicationState { message: String },
+    #[snafu(display("pbf write error"))]
+    PbfWrite { source: io::Error },
+    #[snafu(display("pbf {kind} of {size} bytes exceeds the {limit} byte limit"))]
+    PbfTooLarge { kind: String, size: usize, limit: usize },
+    #[snafu(display("invalid tag filter: {message}"))]
+    InvalidTagFilter { message: String },
+    #[snafu(display("invalid postgres identifier {name:?}"))]
+    InvalidPgIdentifier { name: String },
+    #[snafu(display("invalid postgis roads query: {message}"))]
+    InvalidPgRoads { message: String },
+    #[snafu(display("invalid clip area: {message}"))]
+    InvalidClipArea { message: String },
 }
 
 impl Error {
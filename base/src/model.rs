@@ -1,2 +1,12 @@
+pub mod clip_model;
 pub mod config_model;
-pub mod osm_model;
\ No newline at end of file
+pub mod multipolygon_model;
+pub mod node_location_model;
+pub mod osm_change_model;
+pub mod osm_file_model;
+pub mod osm_model;
+pub mod pbf_model;
+pub mod pg_model;
+pub mod relation_tree_model;
+pub mod replication_model;
+pub mod tag_filter_model;
\ No newline at end of file
@@ -0,0 +1,30 @@
+use geo_types::{MultiPolygon, Rect};
+
+/// Area an extract is clipped to.
+#[derive(Debug, Clone)]
+pub enum ClipArea {
+	BoundingBox(Rect<f64>),
+	Polygon {
+		polygon: MultiPolygon<f64>,
+		/// Bounding box of `polygon`, checked first to skip the exact test
+		bounds: Rect<f64>,
+	},
+}
+
+/// How ways crossing the clip boundary are treated.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClipStrategy {
+	/// Keep every way with at least one node inside, including its outside nodes
+	#[default]
+	CompleteWays,
+	/// Keep only the parts of ways between inside nodes, splitting ways that leave and re-enter
+	Cut,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClipOptions {
+	pub area: ClipArea,
+	pub strategy: ClipStrategy,
+	/// Keep every member of a relation that has at least one member inside
+	pub complete_relations: bool,
+}
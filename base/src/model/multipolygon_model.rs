@@ -0,0 +1,54 @@
+use geo_types::MultiPolygon;
+use osmpbfreader::OsmId;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RingRole {
+	Outer,
+	Inner,
+}
+
+/// Problems found while assembling a multipolygon relation.
+///
+/// Broken parts are dropped from the geometry, so a report with issues may still carry
+/// a usable but incomplete polygon.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "issue", rename_all = "snake_case")]
+pub enum MultipolygonIssue {
+	/// Member way is not present in the loaded data
+	MissingWay { way_id: i64 },
+	/// Member way has fewer than two nodes
+	DegenerateWay { way_id: i64 },
+	/// Member is not a way, or has a role other than `outer`, `inner`, empty or one of the
+	/// boundary roles without geometry (`admin_centre`, `label`, `subarea`)
+	UnexpectedMember { member: OsmId, role: String },
+	/// Member ways could not be joined into a closed ring
+	UnclosedRing {
+		role: RingRole,
+		way_ids: Vec<i64>,
+		start_node: i64,
+		end_node: i64,
+	},
+	/// Closed ring with fewer than three distinct points
+	DegenerateRing { role: RingRole, way_ids: Vec<i64> },
+	/// Ring references nodes whose location is unknown
+	MissingNodes {
+		role: RingRole,
+		way_ids: Vec<i64>,
+		missing: usize,
+	},
+	/// Inner ring that lies inside none of the outer rings
+	InnerWithoutOuter { way_ids: Vec<i64> },
+	/// Relation produced no outer ring at all
+	NoOuterRing,
+}
+
+/// Geometry of a multipolygon relation together with any problems found while building it.
+#[derive(Debug, Clone)]
+pub struct MultipolygonReport {
+	pub relation_id: i64,
+	/// Exterior rings are counter-clockwise and interior rings clockwise
+	pub polygon: MultiPolygon<f64>,
+	pub issues: Vec<MultipolygonIssue>,
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use memmap2::MmapMut;
+use tempfile::NamedTempFile;
+
+/// Limits for the node-location cache used during streaming ingestion.
+#[derive(Debug, Clone)]
+pub struct NodeCacheConfig {
+	/// Bytes the in-memory cache may use before spilling to a file in `spill_dir`
+	pub memory_limit: usize,
+	/// Directory for the sparse file holding one fixed-size slot per node id once spilled;
+	/// the file gets a unique name and is removed when the store is dropped
+	pub spill_dir: PathBuf,
+}
+
+/// Node coordinates keyed by node id, stored as decimicro `(lat, lon)` pairs.
+///
+/// Starts as a hash map and switches to a memory-mapped dense array once the
+/// configured memory limit is reached.
+#[derive(Debug)]
+pub struct NodeLocationStore {
+	pub(crate) config: NodeCacheConfig,
+	pub(crate) storage: NodeLocationStorage,
+	pub(crate) len: usize,
+}
+
+#[derive(Debug)]
+pub(crate) enum NodeLocationStorage {
+	Memory(HashMap<i64, (i32, i32)>),
+	Dense {
+		/// Declared before `file` so the mapping is dropped before the file is removed
+		mmap: MmapMut,
+		file: NamedTempFile,
+		/// Number of slots currently mapped
+		capacity: u64,
+		/// Negative ids (locally created objects) cannot index the array
+		negative: HashMap<i64, (i32, i32)>,
+	},
+}
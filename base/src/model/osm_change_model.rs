@@ -0,0 +1,27 @@
+use std::collections::HashSet;
+
+use osmpbfreader::OsmObj;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeAction {
+	Create,
+	Modify,
+	Delete,
+}
+
+/// Contents of an OsmChange (`.osc`) file, in document order.
+#[derive(Debug, Clone, Default)]
+pub struct OsmChange {
+	pub changes: Vec<(ChangeAction, OsmObj)>,
+}
+
+/// Ids touched by applying an `OsmChange` to an `Osm` store.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeSummary {
+	pub nodes: HashSet<i64>,
+	pub ways: HashSet<i64>,
+	pub relations: HashSet<i64>,
+	pub created: usize,
+	pub modified: usize,
+	pub deleted: usize,
+}
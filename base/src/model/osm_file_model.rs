@@ -0,0 +1,37 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+use osmpbfreader::OsmObj;
+
+use crate::model::pbf_model::PbfSource;
+use crate::model::tag_filter_model::TagFilter;
+use crate::utils::Result;
+
+/// Encoding of an OSM input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+	Pbf,
+	Xml,
+	XmlGz,
+	XmlBz2,
+}
+
+/// An opened OSM input file of either format.
+pub enum OsmFile {
+	Pbf(PbfSource<BufReader<File>>),
+	/// Decompressed OSM XML
+	Xml(Box<dyn BufRead>),
+}
+
+/// An OSM file read through a `TagFilter`; the file is read once per object type.
+#[derive(Debug, Clone)]
+pub struct FilteredOsmFile {
+	pub path: PathBuf,
+	pub filter: TagFilter,
+}
+
+/// Anything that yields OSM objects in file order.
+pub trait OsmSource {
+	fn for_each_obj(&mut self, f: &mut dyn FnMut(OsmObj) -> Result<()>) -> Result<()>;
+}
@@ -1,11 +1,17 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use osmpbfreader::{Node, Relation, Way};
 use serde::{Deserialize, Serialize};
 
+use crate::model::node_location_model::NodeLocationStore;
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Osm {
 	pub nodes: HashMap<i64, Node>,
 	pub ways: HashMap<i64, Way>,
 	pub relations: HashMap<i64, Relation>,
+	/// Locations of nodes not kept in `nodes`, filled by streaming ingestion
+	#[serde(skip)]
+	pub locations: Option<Arc<NodeLocationStore>>,
 }
\ No newline at end of file
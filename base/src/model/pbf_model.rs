@@ -0,0 +1,27 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use osmpbfreader::OsmPbfReader;
+
+/// Byte reader that counts how much has been consumed from the underlying stream.
+#[derive(Debug)]
+pub struct OffsetReader<R> {
+	pub(crate) inner: R,
+	pub(crate) offset: Rc<Cell<u64>>,
+}
+
+/// A PBF reader that knows the byte offset of the blob it is reading, so decode
+/// errors can point at the broken part of the file.
+pub struct PbfSource<R> {
+	pub(crate) reader: OsmPbfReader<OffsetReader<R>>,
+	pub(crate) offset: Rc<Cell<u64>>,
+}
+
+/// Streams OSM objects into a `.osm.pbf` file using DenseNodes and zlib-compressed blobs.
+pub struct PbfWriter<W: std::io::Write> {
+	pub(crate) writer: W,
+	/// Objects of one type waiting to be written as the next block
+	pub(crate) pending: Vec<osmpbfreader::OsmObj>,
+	/// Upper bound on the encoded size of `pending`, in bytes
+	pub(crate) pending_size: usize,
+}
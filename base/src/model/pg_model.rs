@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+/// Where to import an `Osm` store into PostGIS.
+#[derive(Debug, Clone)]
+pub struct PgImportConfig {
+	/// Connection string, e.g. `host=localhost user=postgres dbname=osm` or `postgres://user@localhost/osm`
+	pub connection: String,
+	/// Schema holding the `points`, `lines`, `polygons` and `roads` tables, which are replaced on import
+	pub schema: String,
+}
+
+/// Rows written to each table by an import.
+#[derive(Debug, Clone, Default)]
+pub struct PgImportSummary {
+	pub points: u64,
+	pub lines: u64,
+	pub polygons: u64,
+	pub roads: u64,
+}
+
+/// PostGIS geometry in EWKB with SRID 4326, as accepted by binary `COPY` into a `geometry` column.
+#[derive(Debug, Clone)]
+pub struct Ewkb(pub(crate) Vec<u8>);
+
+/// A routing network as pgRouting `ways` and `ways_vertices_pgr` tables, in the layout
+/// written by osm2pgrouting.
+#[derive(Debug, Clone, Default)]
+pub struct PgRouting {
+	pub vertices: Vec<PgRoutingVertex>,
+	pub edges: Vec<PgRoutingEdge>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PgRoutingVertex {
+	pub id: i64,
+	pub osm_id: i64,
+	pub lon: f64,
+	pub lat: f64,
+}
+
+/// One undirected `ways` row; a `cost` or `reverse_cost` of `-1` marks a direction that
+/// cannot be travelled, as pgRouting expects.
+#[derive(Debug, Clone)]
+pub struct PgRoutingEdge {
+	pub gid: i64,
+	pub osm_id: i64,
+	pub source: i64,
+	pub target: i64,
+	pub cost: f64,
+	pub reverse_cost: f64,
+	pub length_m: f64,
+	pub x1: f64,
+	pub y1: f64,
+	pub x2: f64,
+	pub y2: f64,
+}
+
+/// A SQL query over PostGIS road geometries to build an `Osm` store from, for road data that
+/// lives in the database rather than in OSM.
+#[derive(Debug, Clone)]
+pub struct PgRoadsConfig {
+	pub connection: String,
+	/// Query returning an integer `id`, a LineString or MultiLineString `geom` with an SRID, and
+	/// attribute columns that become tags, e.g. `highway`, `oneway`, `maxspeed`, `name`; a
+	/// jsonb `tags` column is merged in as well
+	pub query: String,
+	/// Further attempts after the connection fails or drops
+	pub retries: u32,
+	pub retry_delay: Duration,
+}
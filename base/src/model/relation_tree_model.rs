@@ -0,0 +1,85 @@
+use osmpbfreader::{OsmId, Tags};
+use serde::Serialize;
+
+/// Limits for resolving nested relations.
+#[derive(Debug, Clone)]
+pub struct ResolveOptions {
+	/// Levels of nested relations followed below the root relation
+	pub max_depth: usize,
+}
+
+impl Default for ResolveOptions {
+	fn default() -> Self {
+		Self { max_depth: 32 }
+	}
+}
+
+/// A relation with its members resolved, nested relations included.
+#[derive(Debug, Clone)]
+pub struct RelationTree {
+	pub id: i64,
+	pub tags: Tags,
+	pub members: Vec<RelationMember>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RelationMember {
+	pub role: String,
+	pub kind: MemberKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum MemberKind {
+	Node(i64),
+	Way(i64),
+	Relation(RelationTree),
+	/// Member is not present in the loaded data
+	Missing(OsmId),
+	/// Relation already on the path from the root, not followed again
+	Cycle(i64),
+	/// Relation nested deeper than `ResolveOptions::max_depth`, not followed
+	DepthExceeded(i64),
+	/// Relation already expanded earlier in the tree at the same or a shallower depth,
+	/// not expanded again
+	Repeated(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Coordinate {
+	pub lon: f64,
+	pub lat: f64,
+}
+
+/// A relation member that is not an outer ring, with its tags and geometry.
+///
+/// Serializes with a `type` of `node`, `way` or `relation` next to the fields below. The
+/// untagged shape `get_not_outer_coordinates_by_relation_id` used to return is available
+/// through [`ResolvedMember::legacy`].
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ResolvedMember {
+	Node {
+		id: i64,
+		role: String,
+		tags: Tags,
+		coordinate: Coordinate,
+	},
+	Way {
+		id: i64,
+		role: String,
+		tags: Tags,
+		coordinates: Vec<Coordinate>,
+	},
+	/// Nested relation, with the coordinates of its outer members
+	Relation {
+		id: i64,
+		role: String,
+		tags: Tags,
+		coordinates: Vec<Coordinate>,
+	},
+}
+
+/// Serializes a [`ResolvedMember`] in the legacy untagged shape: nodes carry `coordinate`,
+/// ways and relations carry `coordinates`, and only relations include their `role`.
+#[derive(Debug, Clone, Copy)]
+pub struct LegacyResolvedMember<'a>(pub &'a ResolvedMember);
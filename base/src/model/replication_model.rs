@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+
+/// Where to follow an OSM replication feed from and where to remember progress.
+#[derive(Debug, Clone)]
+pub struct ReplicationConfig {
+	/// Directory holding `state.txt` and the `AAA/BBB/CCC.osc.gz` diffs, e.g.
+	/// `https://planet.openstreetmap.org/replication/minute` or a local static file server
+	pub base_url: String,
+	/// Local file recording the last applied sequence number, in `state.txt` format
+	pub state_path: PathBuf,
+}
+
+/// Contents of a replication `state.txt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicationState {
+	pub sequence: u64,
+	pub timestamp: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplicationClient {
+	pub(crate) config: ReplicationConfig,
+	pub(crate) client: reqwest::Client,
+}
@@ -0,0 +1,43 @@
+/// Tag-filter expressions selecting which objects to read, e.g.
+/// `w/highway=primary,secondary n/amenity r/type=multipolygon`.
+///
+/// An object is selected when any expression for its type matches. Nodes and ways
+/// referenced by selected ways and relations are read as well.
+#[derive(Debug, Clone, Default)]
+pub struct TagFilter {
+	pub(crate) expressions: Vec<FilterExpression>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct FilterExpression {
+	pub(crate) nodes: bool,
+	pub(crate) ways: bool,
+	pub(crate) relations: bool,
+	pub(crate) key: String,
+	pub(crate) condition: Condition,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Condition {
+	/// `key`
+	Present,
+	/// `!key`
+	Absent,
+	/// `key=a,b`
+	In(Vec<ValuePattern>),
+	/// `key!=a,b`
+	NotIn(Vec<ValuePattern>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ValuePattern {
+	/// `*`
+	Any,
+	Exact(String),
+	/// `value*`
+	Prefix(String),
+	/// `*value`
+	Suffix(String),
+	/// `*value*`
+	Contains(String),
+}
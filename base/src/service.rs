@@ -1 +1,14 @@
-pub mod osm_data;
\ No newline at end of file
+pub mod clip;
+pub mod multipolygon;
+pub mod node_location;
+pub mod osm_change;
+pub mod osm_data;
+pub mod osm_file;
+pub mod osm_xml;
+pub mod pbf_decoder;
+pub mod pbf_writer;
+pub mod pg;
+pub mod pg_roads;
+pub mod pgrouting;
+pub mod replication;
+pub mod tag_filter;
\ No newline at end of file
@@ -0,0 +1,592 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use geo::{BoundingRect, Contains};
+use geo_types::{coord, Coord, LineString, MultiPolygon, Point, Polygon, Rect};
+use osmpbfreader::{NodeId, OsmId, Ref, Relation, RelationId, Way, WayId};
+use serde_json::Value;
+use snafu::ResultExt;
+
+use crate::error::{Error, FileReadSnafu, SerdeJsonSnafu};
+use crate::model::clip_model::{ClipArea, ClipOptions, ClipStrategy};
+use crate::model::osm_model::Osm;
+use crate::utils::Result;
+
+impl ClipArea {
+	/// Parses `minlon,minlat,maxlon,maxlat`.
+	pub fn from_bbox_str(text: &str) -> Result<ClipArea> {
+		let values = text
+			.split(',')
+			.map(|v| v.trim().parse::<f64>())
+			.collect::<std::result::Result<Vec<_>, _>>()
+			.map_err(|_| invalid(format!("bbox {:?} is not four numbers", text)))?;
+		let [min_lon, min_lat, max_lon, max_lat] = values[..] else {
+			return Err(invalid(format!("bbox {:?} is not four numbers", text)));
+		};
+		if min_lon >= max_lon || min_lat >= max_lat {
+			return Err(invalid(format!("bbox {:?} has its minimum above its maximum", text)));
+		}
+		Ok(ClipArea::BoundingBox(Rect::new(
+			coord! { x: min_lon, y: min_lat },
+			coord! { x: max_lon, y: max_lat },
+		)))
+	}
+
+	/// Reads a clip polygon from an Osmosis `.poly` file or from GeoJSON (a Polygon or
+	/// MultiPolygon geometry, Feature or FeatureCollection).
+	pub fn from_path<P: AsRef<Path>>(path: P) -> Result<ClipArea> {
+		let path = path.as_ref();
+		let text = std::fs::read_to_string(path).context(FileReadSnafu { path })?;
+		let polygon = if path.extension().is_some_and(|ext| ext == "poly") {
+			parse_poly(&text)?
+		} else {
+			let value: Value = serde_json::from_str(&text).context(SerdeJsonSnafu)?;
+			MultiPolygon::new(geojson_polygons(&value)?)
+		};
+		ClipArea::from_polygon(polygon)
+	}
+
+	pub fn from_polygon(polygon: MultiPolygon<f64>) -> Result<ClipArea> {
+		let bounds = polygon
+			.bounding_rect()
+			.ok_or_else(|| invalid("clip polygon is empty".to_string()))?;
+		Ok(ClipArea::Polygon { polygon, bounds })
+	}
+
+	pub fn contains(&self, lon: f64, lat: f64) -> bool {
+		match self {
+			ClipArea::BoundingBox(rect) => rect_contains(rect, lon, lat),
+			ClipArea::Polygon { polygon, bounds } => {
+				rect_contains(bounds, lon, lat) && polygon.contains(&Point::new(lon, lat))
+			}
+		}
+	}
+}
+
+/// Unlike `Contains`, counts nodes exactly on the edge of the box as inside.
+fn rect_contains(rect: &Rect<f64>, lon: f64, lat: f64) -> bool {
+	let (min, max) = (rect.min(), rect.max());
+	(min.x..=max.x).contains(&lon) && (min.y..=max.y).contains(&lat)
+}
+
+impl ClipOptions {
+	/// Whether nodes outside the area can end up in the clipped data, as members of
+	/// complete ways or complete relations. When not, readers may drop them right away.
+	pub fn keeps_outside_nodes(&self) -> bool {
+		self.strategy == ClipStrategy::CompleteWays || self.complete_relations
+	}
+
+	/// Node lists to keep for `way` under the clip strategy; empty when the way is dropped.
+	///
+	/// `inside` tells whether a node lies in the clip area; nodes with unknown locations
+	/// count as outside.
+	pub fn clip_way<F: FnMut(NodeId) -> bool>(&self, way: &Way, mut inside: F) -> Vec<Vec<NodeId>> {
+		match self.strategy {
+			ClipStrategy::CompleteWays => {
+				if way.nodes.iter().any(|&id| inside(id)) {
+					vec![way.nodes.clone()]
+				} else {
+					Vec::new()
+				}
+			}
+			ClipStrategy::Cut => {
+				let mut pieces = Vec::new();
+				let mut run = Vec::new();
+				for &id in &way.nodes {
+					if inside(id) {
+						run.push(id);
+					} else if !run.is_empty() {
+						pieces.push(std::mem::take(&mut run));
+					}
+				}
+				pieces.push(run);
+				pieces.retain(|piece| piece.len() >= 2);
+				pieces
+			}
+		}
+	}
+}
+
+impl Osm {
+	/// Drops everything outside `options.area`.
+	///
+	/// Ways cut into several pieces keep their id for the first piece; the others get
+	/// new negative ids and are added next to the original in every relation referencing it.
+	/// Turn restrictions only keep the `from` and `to` pieces that touch their `via` members.
+	pub fn clip(&mut self, options: &ClipOptions) {
+		let mut inside_cache: HashMap<i64, bool> = HashMap::new();
+		self.clip_with(options, |osm, id| {
+			*inside_cache.entry(id.0).or_insert_with(|| {
+				osm.get_coordinate_by_node_id(id.0)
+					.is_some_and(|(lon, lat)| options.area.contains(lon, lat))
+			})
+		});
+	}
+
+	/// Like `clip`, with `inside` deciding whether a node lies in the clip area, such as a
+	/// lookup of the nodes found inside while reading.
+	pub fn clip_with<F: FnMut(&Osm, NodeId) -> bool>(&mut self, options: &ClipOptions, mut inside: F) {
+
+		// Ways, split into pieces under the cut strategy
+		let mut next_id = self.ways.keys().copied().min().unwrap_or(0).min(0);
+		let mut pieces: HashMap<i64, Vec<i64>> = HashMap::new();
+		let mut ways = HashMap::new();
+		for way in self.ways.values() {
+			let kept = options.clip_way(way, |id| inside(self, id));
+			for (i, nodes) in kept.into_iter().enumerate() {
+				let id = if i == 0 {
+					way.id.0
+				} else {
+					next_id -= 1;
+					next_id
+				};
+				pieces.entry(way.id.0).or_default().push(id);
+				ways.insert(
+					id,
+					Way {
+						id: WayId(id),
+						tags: way.tags.clone(),
+						nodes,
+					},
+				);
+			}
+		}
+
+		// Relations with at least one kept member, nested relations included
+		let mut relations: HashSet<i64> = HashSet::new();
+		loop {
+			let before = relations.len();
+			for relation in self.relations.values() {
+				if relations.contains(&relation.id.0) {
+					continue;
+				}
+				let any_inside = relation.refs.iter().any(|member| match member.member {
+					OsmId::Node(id) => inside(self, id),
+					OsmId::Way(WayId(id)) => pieces.contains_key(&id),
+					OsmId::Relation(RelationId(id)) => relations.contains(&id),
+				});
+				if any_inside {
+					relations.insert(relation.id.0);
+				}
+			}
+			if relations.len() == before {
+				break;
+			}
+		}
+
+		let mut extra_nodes = HashSet::new();
+		if options.complete_relations {
+			// Pull in every member of kept relations, whole and unclipped
+			let mut pending = relations.iter().copied().collect::<Vec<_>>();
+			while let Some(id) = pending.pop() {
+				let Some(relation) = self.relations.get(&id) else {
+					continue;
+				};
+				for member in &relation.refs {
+					match member.member {
+						OsmId::Node(NodeId(id)) => {
+							extra_nodes.insert(id);
+						}
+						OsmId::Way(WayId(id)) => {
+							if let Some(way) = self.ways.get(&id) {
+								for extra in pieces.remove(&id).unwrap_or_default() {
+									ways.remove(&extra);
+								}
+								pieces.insert(id, vec![id]);
+								ways.insert(id, way.clone());
+							}
+						}
+						OsmId::Relation(RelationId(id)) => {
+							if relations.insert(id) {
+								pending.push(id);
+							}
+						}
+					}
+				}
+			}
+		}
+
+		let mut kept_relations = HashMap::new();
+		for id in &relations {
+			let Some(relation) = self.relations.get(id) else {
+				continue;
+			};
+			let mut relation = relation.clone();
+			let via_nodes = restriction_via_nodes(self, &relation);
+			relation.refs = relation
+				.refs
+				.iter()
+				.flat_map(|member| match member.member {
+					OsmId::Way(WayId(id)) => pieces
+						.get(&id)
+						.map(|ids| {
+							ids.iter()
+								.filter(|&piece| match (&via_nodes, member.role.as_str()) {
+									// A restriction's `from` and `to` meet the `via` members
+									(Some(via_nodes), "from" | "to") if ids.len() > 1 => ways[piece]
+										.nodes
+										.iter()
+										.any(|node| via_nodes.contains(&node.0)),
+									_ => true,
+								})
+								.map(|&piece| Ref {
+									member: OsmId::Way(WayId(piece)),
+									role: member.role.clone(),
+								})
+								.collect::<Vec<_>>()
+						})
+						.unwrap_or_default(),
+					OsmId::Node(id) if extra_nodes.contains(&id.0) || inside(self, id) => {
+						vec![member.clone()]
+					}
+					OsmId::Relation(RelationId(id)) if relations.contains(&id) => vec![member.clone()],
+					_ => Vec::new(),
+				})
+				.collect();
+			kept_relations.insert(*id, relation);
+		}
+
+		let mut kept_nodes: HashSet<i64> = ways
+			.values()
+			.flat_map(|way| way.nodes.iter().map(|id| id.0))
+			.collect();
+		kept_nodes.extend(extra_nodes);
+		let node_ids = self.nodes.keys().copied().collect::<Vec<_>>();
+		for id in node_ids {
+			if !kept_nodes.contains(&id) && !inside(self, NodeId(id)) {
+				self.nodes.remove(&id);
+			}
+		}
+		tracing::info!(
+			"clipped extract to {} nodes, {} ways and {} relations",
+			self.nodes.len(),
+			ways.len(),
+			kept_relations.len()
+		);
+		self.ways = ways;
+		self.relations = kept_relations;
+	}
+}
+
+/// Nodes of the `via` members of a turn restriction, `None` for other relations.
+fn restriction_via_nodes(osm: &Osm, relation: &Relation) -> Option<HashSet<i64>> {
+	if relation.tags.get("type").map(|v| v.as_str()) != Some("restriction") {
+		return None;
+	}
+	let mut nodes = HashSet::new();
+	for member in relation.refs.iter().filter(|member| member.role == "via") {
+		match member.member {
+			OsmId::Node(NodeId(id)) => {
+				nodes.insert(id);
+			}
+			OsmId::Way(WayId(id)) => {
+				if let Some(way) = osm.ways.get(&id) {
+					nodes.extend(way.nodes.iter().map(|node| node.0));
+				}
+			}
+			OsmId::Relation(_) => {}
+		}
+	}
+	Some(nodes)
+}
+
+/// Parses the Osmosis polygon filter format: a name line, then rings of `lon lat` lines
+/// each closed by `END`, with hole rings named `!…`, and a final `END`.
+fn parse_poly(text: &str) -> Result<MultiPolygon<f64>> {
+	let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+	lines.next();
+	let mut polygons: Vec<Polygon<f64>> = Vec::new();
+	while let Some(section) = lines.next() {
+		if section == "END" {
+			break;
+		}
+		let mut coords = Vec::new();
+		for line in lines.by_ref() {
+			if line == "END" {
+				break;
+			}
+			let mut parts = line.split_whitespace().map(str::parse::<f64>);
+			match (parts.next(), parts.next()) {
+				(Some(Ok(x)), Some(Ok(y))) => coords.push(Coord { x, y }),
+				_ => return Err(invalid(format!("invalid .poly coordinate line {:?}", line))),
+			}
+		}
+		let ring = LineString::new(coords);
+		if section.starts_with('!') {
+			let outer = polygons
+				.last_mut()
+				.ok_or_else(|| invalid(format!("hole {:?} before any outer ring", section)))?;
+			outer.interiors_push(ring);
+		} else {
+			polygons.push(Polygon::new(ring, Vec::new()));
+		}
+	}
+	Ok(MultiPolygon::new(polygons))
+}
+
+fn geojson_polygons(value: &Value) -> Result<Vec<Polygon<f64>>> {
+	match value.get("type").and_then(Value::as_str) {
+		Some("FeatureCollection") => {
+			let mut polygons = Vec::new();
+			for feature in value.get("features").and_then(Value::as_array).into_iter().flatten() {
+				polygons.extend(geojson_polygons(feature)?);
+			}
+			Ok(polygons)
+		}
+		Some("Feature") => geojson_polygons(value.get("geometry").unwrap_or(&Value::Null)),
+		Some("Polygon") => Ok(vec![geojson_polygon(&value["coordinates"])?]),
+		Some("MultiPolygon") => value["coordinates"]
+			.as_array()
+			.ok_or_else(|| invalid("MultiPolygon without coordinates".to_string()))?
+			.iter()
+			.map(geojson_polygon)
+			.collect(),
+		other => Err(invalid(format!("unsupported GeoJSON type {:?}", other))),
+	}
+}
+
+fn geojson_polygon(rings: &Value) -> Result<Polygon<f64>> {
+	let mut rings = rings
+		.as_array()
+		.ok_or_else(|| invalid("polygon without rings".to_string()))?
+		.iter()
+		.map(|ring| {
+			ring.as_array()
+				.ok_or_else(|| invalid("ring is not an array".to_string()))?
+				.iter()
+				.map(|position| match (position.get(0).and_then(Value::as_f64), position.get(1).and_then(Value::as_f64)) {
+					(Some(x), Some(y)) => Ok(Coord { x, y }),
+					_ => Err(invalid(format!("invalid position {}", position))),
+				})
+				.collect::<Result<Vec<_>>>()
+				.map(LineString::new)
+		})
+		.collect::<Result<Vec<_>>>()?;
+	if rings.is_empty() {
+		return Err(invalid("polygon without rings".to_string()));
+	}
+	let exterior = rings.remove(0);
+	Ok(Polygon::new(exterior, rings))
+}
+
+fn invalid(message: String) -> Error {
+	Error::InvalidClipArea { message }
+}
+
+#[cfg(test)]
+mod tests {
+	use osmpbfreader::{Node, Tags};
+
+	use super::*;
+	use crate::model::osm_file_model::OsmFile;
+
+	fn tags(pairs: &[(&str, &str)]) -> Tags {
+		let mut tags = Tags::new();
+		for (key, value) in pairs {
+			tags.insert((*key).into(), (*value).into());
+		}
+		tags
+	}
+
+	/// Way 10 leaves the area at node 3 and comes back, way 20 lies outside, way 30 turns
+	/// off way 10 at node 4 under a restriction, and route 200 holds ways 10 and 20.
+	const NODES: [(i64, f64, f64); 7] =
+		[(1, 0.0, 0.0), (2, 1.0, 0.0), (3, 2.0, 5.0), (4, 3.0, 0.0), (5, 4.0, 0.0), (6, 2.0, 6.0), (7, 3.0, 0.2)];
+
+	fn fixture() -> Osm {
+		let mut osm = Osm::default();
+		for (id, lon, lat) in NODES {
+			osm.add_node(Node {
+				id: NodeId(id),
+				tags: Tags::new(),
+				decimicro_lat: (lat * 1e7) as i32,
+				decimicro_lon: (lon * 1e7) as i32,
+			});
+		}
+		for (id, nodes) in [(10, vec![1, 2, 3, 4, 5]), (20, vec![3, 6]), (30, vec![4, 7])] {
+			osm.add_way(Way {
+				id: WayId(id),
+				tags: tags(&[("highway", "residential")]),
+				nodes: nodes.into_iter().map(NodeId).collect(),
+			});
+		}
+		let member = |member, role: &str| Ref {
+			member,
+			role: role.into(),
+		};
+		osm.add_relation(Relation {
+			id: RelationId(100),
+			tags: tags(&[("type", "restriction"), ("restriction", "no_left_turn")]),
+			refs: vec![
+				member(OsmId::Way(WayId(10)), "from"),
+				member(OsmId::Node(NodeId(4)), "via"),
+				member(OsmId::Way(WayId(30)), "to"),
+			],
+		});
+		osm.add_relation(Relation {
+			id: RelationId(200),
+			tags: tags(&[("type", "route")]),
+			refs: vec![member(OsmId::Way(WayId(10)), ""), member(OsmId::Way(WayId(20)), "")],
+		});
+		osm
+	}
+
+	fn options(strategy: ClipStrategy, complete_relations: bool) -> ClipOptions {
+		ClipOptions {
+			area: ClipArea::from_bbox_str("-0.5,-0.5,4.5,0.5").unwrap(),
+			strategy,
+			complete_relations,
+		}
+	}
+
+	fn way_nodes(osm: &Osm, id: i64) -> Vec<i64> {
+		osm.ways[&id].nodes.iter().map(|node| node.0).collect()
+	}
+
+	fn member_ways(osm: &Osm, id: i64) -> Vec<i64> {
+		osm.relations[&id]
+			.refs
+			.iter()
+			.filter_map(|member| match member.member {
+				OsmId::Way(WayId(id)) => Some(id),
+				_ => None,
+			})
+			.collect()
+	}
+
+	fn node_ids(osm: &Osm) -> Vec<i64> {
+		let mut ids = osm.nodes.keys().copied().collect::<Vec<_>>();
+		ids.sort();
+		ids
+	}
+
+	#[test]
+	fn parses_bbox() {
+		let ClipArea::BoundingBox(rect) = ClipArea::from_bbox_str(" 100.1, 13.5,100.9,14.2").unwrap() else {
+			panic!("expected a bounding box");
+		};
+		assert_eq!((rect.min().x, rect.min().y, rect.max().x, rect.max().y), (100.1, 13.5, 100.9, 14.2));
+
+		for text in ["100.1,13.5,100.9", "100.1,13.5,100.9,14.2,1", "a,b,c,d", "100.9,13.5,100.1,14.2", "1,1,1,1"] {
+			assert!(matches!(ClipArea::from_bbox_str(text), Err(Error::InvalidClipArea { .. })), "{}", text);
+		}
+	}
+
+	#[test]
+	fn parses_poly_with_hole() {
+		let text = "area\n1\n  0 0\n  10 0\n  10 10\n  0 10\n  0 0\nEND\n!1\n  4 4\n  6 4\n  6 6\n  4 6\n  4 4\nEND\nEND\n";
+		let area = ClipArea::from_polygon(parse_poly(text).unwrap()).unwrap();
+		assert!(area.contains(2.0, 2.0));
+		assert!(!area.contains(5.0, 5.0));
+		assert!(!area.contains(11.0, 2.0));
+
+		assert!(parse_poly("area\n!1\n 0 0\nEND\nEND\n").is_err());
+		assert!(parse_poly("area\n1\n 0 x\nEND\nEND\n").is_err());
+	}
+
+	#[test]
+	fn reads_poly_and_geojson_files() {
+		let dir = std::env::temp_dir();
+		let poly = dir.join(format!("clip_{}.poly", std::process::id()));
+		std::fs::write(&poly, "area\n1\n 0 0\n 2 0\n 2 2\n 0 2\nEND\nEND\n").unwrap();
+		let geojson = dir.join(format!("clip_{}.geojson", std::process::id()));
+		std::fs::write(
+			&geojson,
+			r#"{"type": "FeatureCollection", "features": [
+				{"type": "Feature", "geometry": {"type": "Polygon", "coordinates": [[[0, 0], [2, 0], [2, 2], [0, 2], [0, 0]]]}},
+				{"type": "Feature", "geometry": {"type": "MultiPolygon", "coordinates": [[[[5, 5], [6, 5], [6, 6], [5, 5]]]]}}
+			]}"#,
+		)
+		.unwrap();
+		let from_poly = ClipArea::from_path(&poly);
+		let from_geojson = ClipArea::from_path(&geojson);
+		std::fs::remove_file(&poly).unwrap();
+		std::fs::remove_file(&geojson).unwrap();
+
+		let from_poly = from_poly.unwrap();
+		assert!(from_poly.contains(1.0, 1.0) && !from_poly.contains(3.0, 1.0));
+		let from_geojson = from_geojson.unwrap();
+		assert!(from_geojson.contains(1.0, 1.0) && from_geojson.contains(5.9, 5.1));
+		assert!(!from_geojson.contains(3.0, 3.0));
+	}
+
+	#[test]
+	fn complete_ways_keeps_crossing_ways_whole() {
+		let mut osm = fixture();
+		osm.clip(&options(ClipStrategy::CompleteWays, false));
+
+		assert_eq!(way_nodes(&osm, 10), vec![1, 2, 3, 4, 5]);
+		assert!(!osm.ways.contains_key(&20));
+		assert_eq!(node_ids(&osm), vec![1, 2, 3, 4, 5, 7]);
+		assert_eq!(member_ways(&osm, 100), vec![10, 30]);
+		assert_eq!(member_ways(&osm, 200), vec![10]);
+	}
+
+	#[test]
+	fn cut_splits_ways_and_remaps_restrictions() {
+		let mut osm = fixture();
+		osm.clip(&options(ClipStrategy::Cut, false));
+
+		assert_eq!(way_nodes(&osm, 10), vec![1, 2]);
+		assert_eq!(way_nodes(&osm, -1), vec![4, 5]);
+		assert_eq!(node_ids(&osm), vec![1, 2, 4, 5, 7]);
+		// Only the piece reaching the via node is the restriction's `from` way
+		assert_eq!(member_ways(&osm, 100), vec![-1, 30]);
+		assert_eq!(member_ways(&osm, 200), vec![10, -1]);
+	}
+
+	#[test]
+	fn complete_relations_keeps_members_whole() {
+		let mut osm = fixture();
+		osm.clip(&options(ClipStrategy::Cut, true));
+
+		assert_eq!(way_nodes(&osm, 10), vec![1, 2, 3, 4, 5]);
+		assert_eq!(way_nodes(&osm, 20), vec![3, 6]);
+		assert!(!osm.ways.contains_key(&-1));
+		assert_eq!(member_ways(&osm, 200), vec![10, 20]);
+		assert_eq!(node_ids(&osm), vec![1, 2, 3, 4, 5, 6, 7]);
+	}
+
+	#[test]
+	fn clips_while_reading() {
+		let mut xml = String::from("<osm version=\"0.6\">\n");
+		for (id, lon, lat) in NODES {
+			xml.push_str(&format!("<node id=\"{}\" lat=\"{}\" lon=\"{}\"/>\n", id, lat, lon));
+		}
+		let osm = fixture();
+		let mut ids = osm.ways.keys().copied().collect::<Vec<_>>();
+		ids.sort();
+		for id in ids {
+			xml.push_str(&format!("<way id=\"{}\">", id));
+			for node in &osm.ways[&id].nodes {
+				xml.push_str(&format!("<nd ref=\"{}\"/>", node.0));
+			}
+			xml.push_str("<tag k=\"highway\" v=\"residential\"/></way>\n");
+		}
+		xml.push_str(
+			"<relation id=\"100\"><member type=\"way\" ref=\"10\" role=\"from\"/><member type=\"node\" ref=\"4\" role=\"via\"/>\
+			 <member type=\"way\" ref=\"30\" role=\"to\"/><tag k=\"type\" v=\"restriction\"/><tag k=\"restriction\" v=\"no_left_turn\"/></relation>\n",
+		);
+		xml.push_str("</osm>\n");
+		let path = std::env::temp_dir().join(format!("clip_read_{}.osm", std::process::id()));
+		std::fs::write(&path, xml).unwrap();
+
+		for strategy in [ClipStrategy::CompleteWays, ClipStrategy::Cut] {
+			let clipped = Osm::read_osm_source(OsmFile::open(&path).unwrap(), None, Some(&options(strategy, false))).unwrap();
+			let mut expected = Osm::from_osm_source(OsmFile::open(&path).unwrap()).unwrap();
+			expected.clip(&options(strategy, false));
+
+			assert_eq!(node_ids(&clipped), node_ids(&expected));
+			let mut ways = clipped.ways.keys().copied().collect::<Vec<_>>();
+			ways.sort();
+			let mut expected_ways = expected.ways.keys().copied().collect::<Vec<_>>();
+			expected_ways.sort();
+			assert_eq!(ways, expected_ways);
+			for id in ways {
+				assert_eq!(way_nodes(&clipped, id), way_nodes(&expected, id));
+			}
+			assert_eq!(member_ways(&clipped, 100), member_ways(&expected, 100));
+		}
+		std::fs::remove_file(&path).unwrap();
+	}
+}
@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+
+use geo::orient::{Direction, Orient};
+use geo::{Area, Contains};
+use geo_types::{Coord, LineString, MultiPolygon, Polygon};
+use osmpbfreader::{OsmId, Relation};
+
+use crate::model::multipolygon_model::{MultipolygonIssue, MultipolygonReport, RingRole};
+use crate::model::osm_model::Osm;
+
+/// Boundary member roles that carry no ring geometry: the admin centre and label nodes,
+/// and the relations of lower-level areas.
+const NON_RING_ROLES: &[&str] = &["admin_centre", "label", "subarea"];
+
+/// Extensions tried while stitching one ring before giving up on closing it, bounding
+/// the backtracking on pathological relations.
+const MAX_STITCH_STEPS: usize = 100_000;
+
+/// Segments joined into a ring, each with whether it is walked backwards.
+type Chain = Vec<(usize, bool)>;
+
+/// A chain of node ids and the member ways it was built from.
+struct Ring {
+	nodes: Vec<i64>,
+	way_ids: Vec<i64>,
+}
+
+impl MultipolygonReport {
+	pub fn is_valid(&self) -> bool {
+		self.issues.is_empty()
+	}
+}
+
+impl Osm {
+	pub fn get_multipolygon_by_relation_id(&self, id: i64) -> Option<MultipolygonReport> {
+		self.get_relation_by_id(id)
+			.map(|relation| self.get_multipolygon_by_relation(relation))
+	}
+
+	/// Stitches the member ways of a multipolygon or boundary relation into closed rings
+	/// and assigns each inner ring to the smallest outer ring containing it.
+	///
+	/// Members may be split across several ways and point in either direction. Members
+	/// with an empty role are treated as outer, as in older OSM data, and boundary members
+	/// in [`NON_RING_ROLES`] are skipped.
+	pub fn get_multipolygon_by_relation(&self, relation: &Relation) -> MultipolygonReport {
+		let mut issues = Vec::new();
+		let mut outer_ways = Vec::new();
+		let mut inner_ways = Vec::new();
+		for member in &relation.refs {
+			let role = member.role.to_lowercase();
+			let ways = match (&member.member, role.as_str()) {
+				(OsmId::Way(_), "outer" | "") => &mut outer_ways,
+				(OsmId::Way(_), "inner") => &mut inner_ways,
+				(_, role) if NON_RING_ROLES.contains(&role) => continue,
+				_ => {
+					issues.push(MultipolygonIssue::UnexpectedMember {
+						member: member.member,
+						role: member.role.to_string(),
+					});
+					continue;
+				}
+			};
+			let way_id = member.member.inner_id();
+			match self.get_way_by_id(way_id) {
+				Some(way) if way.nodes.len() >= 2 => {
+					ways.push(Ring {
+						nodes: way.nodes.iter().map(|id| id.0).collect(),
+						way_ids: vec![way_id],
+					});
+				}
+				Some(_) => issues.push(MultipolygonIssue::DegenerateWay { way_id }),
+				None => issues.push(MultipolygonIssue::MissingWay { way_id }),
+			}
+		}
+
+		let outers = self.build_rings(outer_ways, RingRole::Outer, &mut issues);
+		let inners = self.build_rings(inner_ways, RingRole::Inner, &mut issues);
+		if outers.is_empty() {
+			issues.push(MultipolygonIssue::NoOuterRing);
+		}
+
+		let shells = outers
+			.into_iter()
+			.map(|(exterior, _)| Polygon::new(exterior, Vec::new()))
+			.collect::<Vec<_>>();
+		let mut holes = vec![Vec::new(); shells.len()];
+		for (inner, way_ids) in inners {
+			let container = shells
+				.iter()
+				.enumerate()
+				.filter(|(_, shell)| shell.contains(&inner))
+				.min_by(|(_, a), (_, b)| a.unsigned_area().total_cmp(&b.unsigned_area()))
+				.map(|(i, _)| i);
+			match container {
+				Some(i) => holes[i].push(inner),
+				None => issues.push(MultipolygonIssue::InnerWithoutOuter { way_ids }),
+			}
+		}
+
+		let polygons = shells
+			.into_iter()
+			.zip(holes)
+			.map(|(shell, interiors)| Polygon::new(shell.into_inner().0, interiors))
+			.collect::<Vec<_>>();
+		MultipolygonReport {
+			relation_id: relation.id.0,
+			polygon: MultiPolygon::new(polygons).orient(Direction::Default),
+			issues,
+		}
+	}
+
+	/// Joins way chains end to end into closed rings and resolves their coordinates,
+	/// returning each ring with the ids of the ways it was built from.
+	///
+	/// Where several ways meet at a ring's open end, each is tried in turn and dead ends
+	/// are backed out of, so a stray way at a junction does not break the ring it touches.
+	fn build_rings(
+		&self,
+		segments: Vec<Ring>,
+		role: RingRole,
+		issues: &mut Vec<MultipolygonIssue>,
+	) -> Vec<(LineString<f64>, Vec<i64>)> {
+		let mut by_endpoint: HashMap<i64, Vec<usize>> = HashMap::new();
+		for (i, segment) in segments.iter().enumerate() {
+			let (first, last) = (segment.nodes[0], segment.nodes[segment.nodes.len() - 1]);
+			by_endpoint.entry(first).or_default().push(i);
+			if last != first {
+				by_endpoint.entry(last).or_default().push(i);
+			}
+		}
+
+		let mut used = vec![false; segments.len()];
+		let mut rings = Vec::new();
+		for start in 0..segments.len() {
+			if used[start] {
+				continue;
+			}
+			let (chain, closed) = match close_ring(&segments, &by_endpoint, &mut used, start) {
+				Ok(chain) => (chain, true),
+				Err(chain) => (chain, false),
+			};
+			let mut ring = Ring {
+				nodes: segments[start].nodes.clone(),
+				way_ids: segments[start].way_ids.clone(),
+			};
+			for &(next, reversed) in &chain[1..] {
+				let segment = &segments[next];
+				if reversed {
+					ring.nodes.extend(segment.nodes.iter().rev().skip(1));
+				} else {
+					ring.nodes.extend_from_slice(&segment.nodes[1..]);
+				}
+				ring.way_ids.extend_from_slice(&segment.way_ids);
+			}
+			if !closed {
+				issues.push(MultipolygonIssue::UnclosedRing {
+					role,
+					way_ids: ring.way_ids,
+					start_node: ring.nodes[0],
+					end_node: ring.nodes[ring.nodes.len() - 1],
+				});
+				continue;
+			}
+			if ring.nodes.len() < 4 {
+				issues.push(MultipolygonIssue::DegenerateRing {
+					role,
+					way_ids: ring.way_ids,
+				});
+				continue;
+			}
+
+			let coords = ring
+				.nodes
+				.iter()
+				.filter_map(|&id| self.get_coordinate_by_node_id(id))
+				.map(|(x, y)| Coord { x, y })
+				.collect::<Vec<_>>();
+			let missing = ring.nodes.len() - coords.len();
+			if missing > 0 {
+				issues.push(MultipolygonIssue::MissingNodes {
+					role,
+					way_ids: ring.way_ids,
+					missing,
+				});
+				continue;
+			}
+			rings.push((LineString::new(coords), ring.way_ids));
+		}
+		rings
+	}
+}
+
+/// Searches depth first for a chain of unused segments from `start` back to its first
+/// node, marking the segments it returns as used. When no ring closes, the longest chain
+/// found is returned instead.
+fn close_ring(
+	segments: &[Ring],
+	by_endpoint: &HashMap<i64, Vec<usize>>,
+	used: &mut [bool],
+	start: usize,
+) -> Result<Chain, Chain> {
+	let first = segments[start].nodes[0];
+	let end_of = |i: usize, reversed: bool| {
+		let nodes = &segments[i].nodes;
+		if reversed { nodes[0] } else { nodes[nodes.len() - 1] }
+	};
+
+	// Segments on the chain, whether each is reversed, and how many candidates at its end
+	// have been tried
+	let mut chain = vec![(start, false, 0)];
+	let mut longest: Chain = vec![(start, false)];
+	used[start] = true;
+	let mut steps = 0;
+	while let Some(&(last, reversed, tried)) = chain.last() {
+		let end = end_of(last, reversed);
+		if end == first {
+			return Ok(chain.into_iter().map(|(i, reversed, _)| (i, reversed)).collect());
+		}
+		steps += 1;
+		if steps > MAX_STITCH_STEPS {
+			break;
+		}
+		let candidates = by_endpoint.get(&end).map_or(&[][..], Vec::as_slice);
+		match candidates[tried..].iter().position(|&i| !used[i]) {
+			Some(offset) => {
+				let next = candidates[tried + offset];
+				if let Some(top) = chain.last_mut() {
+					top.2 = tried + offset + 1;
+				}
+				used[next] = true;
+				chain.push((next, segments[next].nodes[0] != end, 0));
+				if chain.len() > longest.len() {
+					longest = chain.iter().map(|&(i, reversed, _)| (i, reversed)).collect();
+				}
+			}
+			None => {
+				used[last] = false;
+				chain.pop();
+			}
+		}
+	}
+
+	for (i, _, _) in chain {
+		used[i] = false;
+	}
+	for &(i, _) in &longest {
+		used[i] = true;
+	}
+	Err(longest)
+}
+
+#[cfg(test)]
+mod tests {
+	use osmpbfreader::{Node, NodeId, Ref, RelationId, Tags, Way, WayId};
+
+	use super::*;
+
+	/// Nodes on a 10 x 10 grid of 0.001° steps, with id `x * 10 + y + 1`.
+	fn grid() -> Osm {
+		let mut osm = Osm::default();
+		for x in 0..10 {
+			for y in 0..10 {
+				osm.add_node(Node {
+					id: NodeId(x * 10 + y + 1),
+					tags: Tags::new(),
+					decimicro_lat: y as i32 * 10_000,
+					decimicro_lon: x as i32 * 10_000,
+				});
+			}
+		}
+		osm
+	}
+
+	fn at(x: i64, y: i64) -> i64 {
+		x * 10 + y + 1
+	}
+
+	fn add_way(osm: &mut Osm, id: i64, points: &[(i64, i64)]) {
+		osm.add_way(Way {
+			id: WayId(id),
+			tags: Tags::new(),
+			nodes: points.iter().map(|&(x, y)| NodeId(at(x, y))).collect(),
+		});
+	}
+
+	fn relation(members: &[(OsmId, &str)]) -> Relation {
+		Relation {
+			id: RelationId(1),
+			tags: Tags::new(),
+			refs: members
+				.iter()
+				.map(|&(member, role)| Ref {
+					member,
+					role: role.into(),
+				})
+				.collect(),
+		}
+	}
+
+	fn way(id: i64) -> OsmId {
+		OsmId::Way(WayId(id))
+	}
+
+	/// Area in square grid steps, so that a 4 x 4 square has area 16.
+	fn area(report: &MultipolygonReport) -> f64 {
+		report.polygon.unsigned_area() / 1e-6
+	}
+
+	#[test]
+	fn joins_split_and_reversed_ways_into_a_ring() {
+		let mut osm = grid();
+		add_way(&mut osm, 1, &[(0, 0), (4, 0), (4, 4)]);
+		// Reversed relative to the first way
+		add_way(&mut osm, 2, &[(0, 0), (0, 4), (4, 4)]);
+
+		let report = osm.get_multipolygon_by_relation(&relation(&[(way(1), "outer"), (way(2), "outer")]));
+		assert!(report.is_valid(), "{:?}", report.issues);
+		assert_eq!(report.polygon.0.len(), 1);
+		assert!((area(&report) - 16.0).abs() < 1e-6);
+		assert_eq!(report.polygon.0[0].exterior().0.len(), 5);
+	}
+
+	#[test]
+	fn assigns_holes_to_the_smallest_containing_outer() {
+		let mut osm = grid();
+		add_way(&mut osm, 1, &[(0, 0), (8, 0), (8, 8), (0, 8), (0, 0)]);
+		add_way(&mut osm, 2, &[(9, 0), (9, 1), (8, 1), (9, 0)]);
+		add_way(&mut osm, 3, &[(2, 2), (4, 2), (4, 4)]);
+		add_way(&mut osm, 4, &[(4, 4), (2, 4), (2, 2)]);
+		add_way(&mut osm, 5, &[(6, 6), (7, 6), (7, 7), (6, 6)]);
+
+		let report = osm.get_multipolygon_by_relation(&relation(&[
+			(way(1), "outer"),
+			(way(2), ""),
+			(way(3), "inner"),
+			(way(4), "inner"),
+			(way(5), "Inner"),
+		]));
+		assert!(report.is_valid(), "{:?}", report.issues);
+		assert_eq!(report.polygon.0.len(), 2);
+		let holes = report.polygon.0.iter().map(|polygon| polygon.interiors().len()).collect::<Vec<_>>();
+		assert_eq!(holes, vec![2, 0]);
+		// The 8 x 8 square less holes of 4 and 0.5, plus the triangle of 0.5
+		assert!((area(&report) - 60.0).abs() < 1e-6, "{}", area(&report));
+	}
+
+	#[test]
+	fn reports_unclosed_rings_and_missing_data() {
+		let mut osm = grid();
+		add_way(&mut osm, 1, &[(0, 0), (4, 0), (4, 4)]);
+		add_way(&mut osm, 2, &[(4, 4), (0, 4)]);
+		osm.add_way(Way {
+			id: WayId(3),
+			tags: Tags::new(),
+			nodes: vec![NodeId(at(6, 6)), NodeId(1000), NodeId(at(7, 7)), NodeId(at(6, 6))],
+		});
+
+		let report = osm.get_multipolygon_by_relation(&relation(&[
+			(way(1), "outer"),
+			(way(2), "outer"),
+			(way(3), "outer"),
+			(way(9), "outer"),
+		]));
+		assert_eq!(
+			report.issues,
+			vec![
+				MultipolygonIssue::MissingWay { way_id: 9 },
+				MultipolygonIssue::UnclosedRing {
+					role: RingRole::Outer,
+					way_ids: vec![1, 2],
+					start_node: at(0, 0),
+					end_node: at(0, 4),
+				},
+				MultipolygonIssue::MissingNodes {
+					role: RingRole::Outer,
+					way_ids: vec![3],
+					missing: 1,
+				},
+				MultipolygonIssue::NoOuterRing,
+			]
+		);
+		assert!(report.polygon.0.is_empty());
+	}
+
+	#[test]
+	fn backtracks_out_of_a_dead_end_at_a_junction() {
+		let mut osm = grid();
+		add_way(&mut osm, 1, &[(0, 0), (4, 0), (4, 4)]);
+		// A stray spur listed before the way that closes the ring
+		add_way(&mut osm, 2, &[(4, 4), (6, 6)]);
+		add_way(&mut osm, 3, &[(4, 4), (0, 4), (0, 0)]);
+
+		let report =
+			osm.get_multipolygon_by_relation(&relation(&[(way(1), "outer"), (way(2), "outer"), (way(3), "outer")]));
+		assert!((area(&report) - 16.0).abs() < 1e-6, "{}", area(&report));
+		assert_eq!(
+			report.issues,
+			vec![MultipolygonIssue::UnclosedRing {
+				role: RingRole::Outer,
+				way_ids: vec![2],
+				start_node: at(4, 4),
+				end_node: at(6, 6),
+			}]
+		);
+	}
+
+	#[test]
+	fn skips_boundary_members_without_ring_geometry() {
+		let mut osm = grid();
+		add_way(&mut osm, 1, &[(0, 0), (4, 0), (4, 4), (0, 4), (0, 0)]);
+
+		let report = osm.get_multipolygon_by_relation(&relation(&[
+			(way(1), "outer"),
+			(OsmId::Node(NodeId(at(2, 2))), "admin_centre"),
+			(OsmId::Node(NodeId(at(1, 1))), "label"),
+			(OsmId::Relation(RelationId(7)), "subarea"),
+		]));
+		assert!(report.is_valid(), "{:?}", report.issues);
+		assert!((area(&report) - 16.0).abs() < 1e-6);
+
+		let report = osm.get_multipolygon_by_relation(&relation(&[
+			(way(1), "outer"),
+			(OsmId::Node(NodeId(at(2, 2))), "outer"),
+		]));
+		assert_eq!(
+			report.issues,
+			vec![MultipolygonIssue::UnexpectedMember {
+				member: OsmId::Node(NodeId(at(2, 2))),
+				role: "outer".into(),
+			}]
+		);
+	}
+}
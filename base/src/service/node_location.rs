@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+
+use memmap2::MmapMut;
+use snafu::ResultExt;
+
+use crate::error::NodeCacheSnafu;
+use crate::model::node_location_model::{NodeCacheConfig, NodeLocationStorage, NodeLocationStore};
+use crate::utils::Result;
+
+/// Bytes per dense slot: offset decimicro latitude and longitude as `u32`.
+const SLOT_SIZE: u64 = 8;
+/// Approximate heap cost of one hash map entry, used against the memory limit.
+const MEMORY_ENTRY_SIZE: usize = 32;
+/// Added to stored decimicro values so that an all-zero slot (an untouched sparse page) means empty.
+const SLOT_OFFSET: i64 = 1_000_000_000;
+/// Slots mapped when the dense array is first created.
+const INITIAL_CAPACITY: u64 = 1 << 24;
+
+impl NodeLocationStore {
+	pub fn new(config: NodeCacheConfig) -> Self {
+		NodeLocationStore {
+			config,
+			storage: NodeLocationStorage::Memory(HashMap::new()),
+			len: 0,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	pub fn is_spilled(&self) -> bool {
+		matches!(self.storage, NodeLocationStorage::Dense { .. })
+	}
+
+	/// Stores the location of node `id`, spilling to the dense file when over the memory limit.
+	pub fn insert(&mut self, id: i64, decimicro_lat: i32, decimicro_lon: i32) -> Result<()> {
+		if let NodeLocationStorage::Memory(map) = &self.storage {
+			if map.len() * MEMORY_ENTRY_SIZE >= self.config.memory_limit {
+				self.spill()?;
+			}
+		}
+		let inserted = match &mut self.storage {
+			NodeLocationStorage::Memory(map) => map.insert(id, (decimicro_lat, decimicro_lon)).is_none(),
+			NodeLocationStorage::Dense { negative, .. } if id < 0 => {
+				negative.insert(id, (decimicro_lat, decimicro_lon)).is_none()
+			}
+			NodeLocationStorage::Dense { .. } => {
+				self.ensure_capacity(id as u64 + 1)?;
+				let NodeLocationStorage::Dense { mmap, .. } = &mut self.storage else {
+					unreachable!("storage switched while writing a dense slot");
+				};
+				let offset = (id as u64 * SLOT_SIZE) as usize;
+				let slot = &mut mmap[offset..offset + SLOT_SIZE as usize];
+				let was_empty = slot.iter().all(|b| *b == 0);
+				slot[..4].copy_from_slice(&encode(decimicro_lat).to_le_bytes());
+				slot[4..].copy_from_slice(&encode(decimicro_lon).to_le_bytes());
+				was_empty
+			}
+		};
+		if inserted {
+			self.len += 1;
+		}
+		Ok(())
+	}
+
+	/// Forgets the location of node `id`, returning whether it was stored.
+	pub fn remove(&mut self, id: i64) -> bool {
+		let removed = match &mut self.storage {
+			NodeLocationStorage::Memory(map) => map.remove(&id).is_some(),
+			NodeLocationStorage::Dense { negative, .. } if id < 0 => negative.remove(&id).is_some(),
+			NodeLocationStorage::Dense { mmap, capacity, .. } => {
+				if id as u64 >= *capacity {
+					return false;
+				}
+				let offset = (id as u64 * SLOT_SIZE) as usize;
+				let slot = &mut mmap[offset..offset + SLOT_SIZE as usize];
+				let was_set = slot.iter().any(|b| *b != 0);
+				slot.fill(0);
+				was_set
+			}
+		};
+		if removed {
+			self.len -= 1;
+		}
+		removed
+	}
+
+	/// Location of node `id` as decimicro `(lat, lon)`.
+	pub fn get_decimicro(&self, id: i64) -> Option<(i32, i32)> {
+		match &self.storage {
+			NodeLocationStorage::Memory(map) => map.get(&id).copied(),
+			NodeLocationStorage::Dense { negative, .. } if id < 0 => negative.get(&id).copied(),
+			NodeLocationStorage::Dense { mmap, capacity, .. } => {
+				if id as u64 >= *capacity {
+					return None;
+				}
+				let offset = (id as u64 * SLOT_SIZE) as usize;
+				let slot = &mmap[offset..offset + SLOT_SIZE as usize];
+				let lat = u32::from_le_bytes(slot[..4].try_into().ok()?);
+				let lon = u32::from_le_bytes(slot[4..].try_into().ok()?);
+				(lat != 0).then(|| (decode(lat), decode(lon)))
+			}
+		}
+	}
+
+	/// Location of node `id` as `(lon, lat)` degrees, matching `Osm::get_coordinate_by_node`.
+	pub fn get(&self, id: i64) -> Option<(f64, f64)> {
+		let micro = 10_000_000.0;
+		self.get_decimicro(id)
+			.map(|(lat, lon)| (lon as f64 / micro, lat as f64 / micro))
+	}
+
+	/// Moves the in-memory entries into the memory-mapped dense file.
+	fn spill(&mut self) -> Result<()> {
+		let file = tempfile::Builder::new()
+			.prefix("node-locations-")
+			.suffix(".cache")
+			.tempfile_in(&self.config.spill_dir)
+			.context(NodeCacheSnafu)?;
+		tracing::info!(
+			"node location cache reached {} bytes, spilling to {:?}",
+			self.config.memory_limit,
+			file.path()
+		);
+		let mmap = map_slots(file.as_file(), INITIAL_CAPACITY)?;
+		let previous = std::mem::replace(
+			&mut self.storage,
+			NodeLocationStorage::Dense {
+				mmap,
+				file,
+				capacity: INITIAL_CAPACITY,
+				negative: HashMap::new(),
+			},
+		);
+		self.len = 0;
+		if let NodeLocationStorage::Memory(map) = previous {
+			for (id, (lat, lon)) in map {
+				self.insert(id, lat, lon)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Grows the dense file so that it holds at least `slots` slots.
+	fn ensure_capacity(&mut self, slots: u64) -> Result<()> {
+		if let NodeLocationStorage::Dense {
+			file,
+			mmap,
+			capacity,
+			..
+		} = &mut self.storage
+		{
+			if slots > *capacity {
+				let new_capacity = slots.next_power_of_two();
+				mmap.flush().context(NodeCacheSnafu)?;
+				*mmap = map_slots(file.as_file(), new_capacity)?;
+				*capacity = new_capacity;
+			}
+		}
+		Ok(())
+	}
+}
+
+fn encode(decimicro: i32) -> u32 {
+	(decimicro as i64 + SLOT_OFFSET) as u32
+}
+
+fn decode(stored: u32) -> i32 {
+	(stored as i64 - SLOT_OFFSET) as i32
+}
+
+/// Resizes `file` to `slots` slots and maps it; new slots read as zero, i.e. empty.
+fn map_slots(file: &std::fs::File, slots: u64) -> Result<MmapMut> {
+	file.set_len(slots * SLOT_SIZE).context(NodeCacheSnafu)?;
+	// SAFETY: the file is private to this store and only accessed through this mapping
+	unsafe { MmapMut::map_mut(file) }.context(NodeCacheSnafu)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::{Path, PathBuf};
+
+	use super::*;
+
+	/// A fresh spill directory per test, so that leftover files can be detected.
+	fn spill_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("node_location_{}_{}", name, std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	fn files_in(dir: &Path) -> usize {
+		std::fs::read_dir(dir).unwrap().count()
+	}
+
+	fn store(dir: &Path, entries: usize) -> NodeLocationStore {
+		NodeLocationStore::new(NodeCacheConfig {
+			memory_limit: entries * MEMORY_ENTRY_SIZE,
+			spill_dir: dir.to_path_buf(),
+		})
+	}
+
+	#[test]
+	fn keeps_locations_in_memory_below_the_limit() {
+		let dir = spill_dir("memory");
+		let mut locations = store(&dir, 100);
+		locations.insert(1, 135_000_000, 1_005_000_000).unwrap();
+		locations.insert(-7, -1, 2).unwrap();
+		locations.insert(1, 136_000_000, 1_006_000_000).unwrap();
+
+		assert!(!locations.is_spilled());
+		assert_eq!(locations.len(), 2);
+		assert_eq!(locations.get_decimicro(1), Some((136_000_000, 1_006_000_000)));
+		assert_eq!(locations.get(1), Some((100.6, 13.6)));
+		assert_eq!(locations.get_decimicro(-7), Some((-1, 2)));
+		assert_eq!(locations.get(2), None);
+
+		assert!(locations.remove(-7));
+		assert!(!locations.remove(-7));
+		assert_eq!(locations.len(), 1);
+		assert_eq!(files_in(&dir), 0);
+		std::fs::remove_dir(&dir).unwrap();
+	}
+
+	#[test]
+	fn spills_to_a_dense_file_and_keeps_every_location() {
+		let dir = spill_dir("spill");
+		let mut locations = store(&dir, 3);
+		for id in 1..=3 {
+			locations.insert(id, id as i32 * 10, id as i32 * 20).unwrap();
+		}
+		assert!(!locations.is_spilled());
+		locations.insert(4, 40, 80).unwrap();
+		assert!(locations.is_spilled());
+		assert_eq!(files_in(&dir), 1);
+
+		// Negative ids stay in a side map; ids past the initial mapping grow the file
+		let far = INITIAL_CAPACITY as i64 + 5;
+		locations.insert(-3, -30, -60).unwrap();
+		locations.insert(far, 50, 100).unwrap();
+		locations.insert(2, 21, 41).unwrap();
+		assert_eq!(locations.len(), 6);
+		let expected = [
+			(1, (10, 20)),
+			(2, (21, 41)),
+			(3, (30, 60)),
+			(4, (40, 80)),
+			(-3, (-30, -60)),
+			(far, (50, 100)),
+		];
+		for (id, expected) in expected {
+			assert_eq!(locations.get_decimicro(id), Some(expected), "node {}", id);
+		}
+		assert_eq!(locations.get_decimicro(5), None);
+		assert_eq!(locations.get_decimicro(-4), None);
+		assert_eq!(locations.get_decimicro(far * 2), None);
+
+		assert!(locations.remove(2));
+		assert!(locations.remove(-3));
+		assert!(!locations.remove(2));
+		assert!(!locations.remove(far * 2));
+		assert_eq!(locations.get_decimicro(2), None);
+		assert_eq!(locations.len(), 4);
+		locations.insert(2, 22, 42).unwrap();
+		assert_eq!(locations.len(), 5);
+		assert_eq!(locations.get_decimicro(2), Some((22, 42)));
+
+		drop(locations);
+		assert_eq!(files_in(&dir), 0);
+		std::fs::remove_dir(&dir).unwrap();
+	}
+
+	#[test]
+	fn encodes_the_extremes_of_the_coordinate_range() {
+		let dir = spill_dir("extremes");
+		let mut locations = store(&dir, 0);
+		let corners = [
+			(1, 900_000_000, 1_800_000_000),
+			(2, -900_000_000, -1_800_000_000),
+			(3, 900_000_000, -1_800_000_000),
+			(4, -900_000_000, 1_800_000_000),
+			(5, 0, 0),
+			// Stores as all-zero longitude bytes, which must not read as an empty slot
+			(6, 1, -1_000_000_000),
+		];
+		for (id, lat, lon) in corners {
+			locations.insert(id, lat, lon).unwrap();
+		}
+		assert!(locations.is_spilled());
+		assert_eq!(locations.len(), corners.len());
+		for (id, lat, lon) in corners {
+			assert_eq!(locations.get_decimicro(id), Some((lat, lon)), "node {}", id);
+		}
+		assert_eq!(locations.get(2), Some((-180.0, -90.0)));
+		assert_eq!(locations.get(1), Some((180.0, 90.0)));
+		assert!(locations.remove(6));
+		assert_eq!(locations.get_decimicro(6), None);
+		drop(locations);
+		std::fs::remove_dir(&dir).unwrap();
+	}
+
+	#[test]
+	fn spill_files_are_unique_per_store() {
+		let dir = spill_dir("unique");
+		let mut first = store(&dir, 0);
+		let mut second = store(&dir, 0);
+		first.insert(1, 10, 20).unwrap();
+		second.insert(1, 30, 40).unwrap();
+		assert_eq!(files_in(&dir), 2);
+		assert_eq!(first.get_decimicro(1), Some((10, 20)));
+		assert_eq!(second.get_decimicro(1), Some((30, 40)));
+
+		drop(first);
+		assert_eq!(files_in(&dir), 1);
+		drop(second);
+		assert_eq!(files_in(&dir), 0);
+		std::fs::remove_dir(&dir).unwrap();
+	}
+
+	#[test]
+	fn reports_an_unusable_spill_directory() {
+		let dir = std::env::temp_dir().join(format!("node_location_missing_{}", std::process::id()));
+		let mut locations = store(&dir, 0);
+		assert!(matches!(
+			locations.insert(1, 10, 20),
+			Err(crate::error::Error::NodeCache { .. })
+		));
+	}
+}
@@ -0,0 +1,159 @@
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use flate2::read::GzDecoder;
+use osmpbfreader::{Node, OsmObj};
+use snafu::OptionExt;
+
+use crate::error::SharedNodeCacheSnafu;
+use crate::model::osm_change_model::{ChangeAction, ChangeSummary, OsmChange};
+use crate::model::osm_model::Osm;
+use crate::service::osm_xml::{invalid, read_osm_xml};
+use crate::utils::{open_file, Result};
+
+impl OsmChange {
+	/// Reads an OsmChange file, gunzipping it when the name ends in `.gz`.
+	pub fn from_path<P: AsRef<Path>>(path: P) -> Result<OsmChange> {
+		let path = path.as_ref();
+		tracing::debug!("reading osm change {:?}", path);
+		let file = open_file(path)?;
+		if path.extension().is_some_and(|ext| ext == "gz") {
+			OsmChange::from_reader(BufReader::new(GzDecoder::new(file)))
+		} else {
+			OsmChange::from_reader(BufReader::new(file))
+		}
+	}
+
+	pub fn from_reader<R: BufRead>(reader: R) -> Result<OsmChange> {
+		let mut change = OsmChange::default();
+		read_osm_xml(reader, |action, obj| {
+			let action = action.ok_or_else(|| {
+				invalid(format!("{:?} outside of a create, modify or delete block", obj.id()))
+			})?;
+			change.changes.push((action, obj));
+			Ok(())
+		})?;
+		Ok(change)
+	}
+}
+
+impl Osm {
+	/// Applies the changes in document order and reports which objects they touched.
+	///
+	/// With a node-location cache, untagged nodes are kept only in the cache, as during
+	/// ingestion, so the store matches a fresh import of the updated data. Fails with
+	/// `SharedNodeCache` when the cache is shared through its `Arc`.
+	pub fn apply_change(&mut self, change: &OsmChange) -> Result<ChangeSummary> {
+		// Checked up front so a shared cache never leaves the change half applied
+		if let Some(locations) = self.locations.as_mut() {
+			Arc::get_mut(locations).context(SharedNodeCacheSnafu)?;
+		}
+		let mut summary = ChangeSummary::default();
+		for (action, obj) in &change.changes {
+			match action {
+				ChangeAction::Create => summary.created += 1,
+				ChangeAction::Modify => summary.modified += 1,
+				ChangeAction::Delete => summary.deleted += 1,
+			}
+			match obj {
+				OsmObj::Node(node) => {
+					summary.nodes.insert(node.id.0);
+					self.apply_node_change(*action, node)?;
+				}
+				OsmObj::Way(way) => {
+					summary.ways.insert(way.id.0);
+					match action {
+						ChangeAction::Delete => {
+							self.ways.remove(&way.id.0);
+						}
+						_ => self.add_way(way.clone()),
+					}
+				}
+				OsmObj::Relation(relation) => {
+					summary.relations.insert(relation.id.0);
+					match action {
+						ChangeAction::Delete => {
+							self.relations.remove(&relation.id.0);
+						}
+						_ => self.add_relation(relation.clone()),
+					}
+				}
+			}
+		}
+		tracing::debug!(
+			"applied osm change: {} created, {} modified, {} deleted",
+			summary.created,
+			summary.modified,
+			summary.deleted
+		);
+		Ok(summary)
+	}
+
+	fn apply_node_change(&mut self, action: ChangeAction, node: &Node) -> Result<()> {
+		let id = node.id.0;
+		let locations = match self.locations.as_mut() {
+			Some(locations) => Some(Arc::get_mut(locations).context(SharedNodeCacheSnafu)?),
+			None => None,
+		};
+		match (action, locations) {
+			(ChangeAction::Delete, Some(locations)) => {
+				locations.remove(id);
+				self.nodes.remove(&id);
+			}
+			(ChangeAction::Delete, None) => {
+				self.nodes.remove(&id);
+			}
+			(_, Some(locations)) => {
+				locations.insert(id, node.decimicro_lat, node.decimicro_lon)?;
+				if node.tags.is_empty() {
+					self.nodes.remove(&id);
+				} else {
+					self.add_node(node.clone());
+				}
+			}
+			(_, None) => self.add_node(node.clone()),
+		}
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::error::Error;
+	use crate::model::node_location_model::{NodeCacheConfig, NodeLocationStore};
+
+	const CHANGE: &str = r#"<osmChange version="0.6">
+  <modify><node id="1" lat="13.5" lon="100.5"/></modify>
+</osmChange>"#;
+
+	fn cached_osm() -> Osm {
+		let mut osm = Osm::default();
+		let mut locations = NodeLocationStore::new(NodeCacheConfig {
+			memory_limit: 1024,
+			spill_dir: std::env::temp_dir(),
+		});
+		locations.insert(1, 130_000_000, 1_000_000_000).unwrap();
+		osm.locations = Some(Arc::new(locations));
+		osm
+	}
+
+	#[test]
+	fn updates_the_node_cache() {
+		let mut osm = cached_osm();
+		let change = OsmChange::from_reader(CHANGE.as_bytes()).unwrap();
+		osm.apply_change(&change).unwrap();
+		assert_eq!(osm.locations.as_ref().unwrap().get_decimicro(1), Some((135_000_000, 1_005_000_000)));
+	}
+
+	#[test]
+	fn refuses_a_shared_node_cache() {
+		let mut osm = cached_osm();
+		let shared = osm.locations.clone();
+		let change = OsmChange::from_reader(CHANGE.as_bytes()).unwrap();
+		assert!(matches!(osm.apply_change(&change), Err(Error::SharedNodeCache)));
+		assert_eq!(shared.unwrap().get_decimicro(1), Some((130_000_000, 1_000_000_000)));
+	}
+}
@@ -1,8 +1,24 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::sync::Arc;
+
 use nonempty::NonEmpty;
 use num_traits::ToPrimitive;
-use osmpbfreader::{Node, NodeId, Relation, RelationId, Way, WayId};
+use osmpbfreader::{Node, NodeId, OsmId, Relation, RelationId, Tags, Way, WayId};
+use serde::{Serialize, Serializer};
+use snafu::OptionExt;
 
+use crate::error::MissingNodeSnafu;
+use crate::model::clip_model::ClipOptions;
+use crate::model::node_location_model::{NodeCacheConfig, NodeLocationStore};
+use crate::model::osm_file_model::OsmSource;
 use crate::model::osm_model::Osm;
+use crate::model::pbf_model::PbfSource;
+use crate::model::relation_tree_model::{
+	Coordinate, LegacyResolvedMember, MemberKind, RelationMember, RelationTree, ResolveOptions,
+	ResolvedMember,
+};
+use crate::utils::Result as BaseResult;
 
 impl Osm {
 	pub fn add_node(&mut self, node: Node) {
@@ -29,9 +45,14 @@ impl Osm {
 		self.relations.get(&id)
 	}
 
-	pub fn from_osm_pbf_file(mut pbf: osmpbfreader::OsmPbfReader<std::fs::File>) -> Osm {
+	pub fn from_osm_pbf_file<R: Read>(pbf: PbfSource<R>) -> BaseResult<Osm> {
+		Osm::from_osm_source(pbf)
+	}
+
+	/// Reads every object from a PBF or OSM XML source into memory.
+	pub fn from_osm_source<S: OsmSource>(mut source: S) -> BaseResult<Osm> {
 		let mut osm_data = Osm::default();
-		for obj in pbf.iter().map(Result::unwrap) {
+		source.for_each_obj(&mut |obj| {
 			match obj {
 				osmpbfreader::OsmObj::Node(node) => {
 					osm_data.add_node(node);
@@ -43,9 +64,83 @@ impl Osm {
 					osm_data.add_relation(relation);
 				}
 			}
-		}
+			Ok(())
+		})?;
         tracing::debug!("Start xxx : {:?}", osm_data);
-		osm_data
+		Ok(osm_data)
+	}
+
+	/// Reads a PBF, keeping only what `clip` selects.
+	pub fn from_osm_pbf_file_clipped<R: Read>(pbf: PbfSource<R>, clip: &ClipOptions) -> BaseResult<Osm> {
+		Osm::read_osm_source(pbf, None, Some(clip))
+	}
+
+	pub fn from_osm_pbf_file_with_node_cache<R: Read>(
+		pbf: PbfSource<R>,
+		config: NodeCacheConfig,
+	) -> BaseResult<Osm> {
+		Osm::from_osm_source_with_node_cache(pbf, config)
+	}
+
+	/// Streams the source keeping only tagged nodes resident; every node location goes to
+	/// an on-disk capable cache bounded by `config.memory_limit`.
+	pub fn from_osm_source_with_node_cache<S: OsmSource>(source: S, config: NodeCacheConfig) -> BaseResult<Osm> {
+		Osm::read_osm_source(source, Some(config), None)
+	}
+
+	/// Reads a source, through the node-location cache when `node_cache` is given, and clips
+	/// it to `clip`. Nodes are tested against the clip area as they are read, and dropped
+	/// there when the clip can never keep them; ways and relations are clipped once read.
+	pub fn read_osm_source<S: OsmSource>(
+		mut source: S,
+		node_cache: Option<NodeCacheConfig>,
+		clip: Option<&ClipOptions>,
+	) -> BaseResult<Osm> {
+		let mut osm_data = Osm::default();
+		let mut locations = node_cache.map(NodeLocationStore::new);
+		let mut inside = HashSet::new();
+		source.for_each_obj(&mut |obj| {
+			match obj {
+				osmpbfreader::OsmObj::Node(node) => {
+					if let Some(clip) = clip {
+						if clip.area.contains(node.lon(), node.lat()) {
+							inside.insert(node.id.0);
+						} else if !clip.keeps_outside_nodes() {
+							return Ok(());
+						}
+					}
+					match &mut locations {
+						Some(locations) => {
+							locations.insert(node.id.0, node.decimicro_lat, node.decimicro_lon)?;
+							if !node.tags.is_empty() {
+								osm_data.add_node(node);
+							}
+						}
+						None => osm_data.add_node(node),
+					}
+				}
+				osmpbfreader::OsmObj::Way(way) => {
+					osm_data.add_way(way);
+				}
+				osmpbfreader::OsmObj::Relation(relation) => {
+					osm_data.add_relation(relation);
+				}
+			}
+			Ok(())
+		})?;
+		if let Some(locations) = locations {
+			tracing::debug!(
+				"cached {} node locations (spilled: {}), kept {} tagged nodes",
+				locations.len(),
+				locations.is_spilled(),
+				osm_data.nodes.len()
+			);
+			osm_data.locations = Some(Arc::new(locations));
+		}
+		if let Some(clip) = clip {
+			osm_data.clip_with(clip, |_, id| inside.contains(&id.0));
+		}
+		Ok(osm_data)
 	}
 
 	pub fn get_coordinate_by_node(&self, node: &Node) -> Result<(f64, f64), String> {
@@ -66,6 +161,7 @@ impl Osm {
 	pub fn get_coordinate_by_node_id(&self, id: i64) -> Option<(f64, f64)> {
 		self.get_node_by_id(id)
 			.and_then(|node| self.get_coordinate_by_node(node).ok())
+			.or_else(|| self.locations.as_ref().and_then(|locations| locations.get(id)))
 	}
 
 	pub fn get_coordinates_by_way(&self, way: &Way) -> Vec<(f64, f64)> {
@@ -75,6 +171,21 @@ impl Osm {
 			.collect::<Vec<_>>()
 	}
 
+	/// Like `get_coordinates_by_way`, but fails on the first node without a known location
+	/// instead of skipping it.
+	pub fn try_get_coordinates_by_way(&self, way: &Way) -> BaseResult<Vec<(f64, f64)>> {
+		way.nodes
+			.iter()
+			.map(|id| {
+				self.get_coordinate_by_node_id(id.0)
+					.context(MissingNodeSnafu {
+						way_id: way.id.0,
+						node_id: id.0,
+					})
+			})
+			.collect()
+	}
+
 	pub fn get_coordinates_by_way_id(&self, id: i64) -> Option<Vec<(f64, f64)>> {
 		self.get_way_by_id(id)
 			.map(|way| self.get_coordinates_by_way(way))
@@ -89,32 +200,10 @@ impl Osm {
 	}
 
 	pub fn get_outer_coordinates_by_relation_id(&self, id: i64) -> Option<Vec<(f64, f64)>> {
-		let relation = self.get_relation_by_id(id)?;
-		let RelationId(cur_id) = relation.id;
-		let res = relation
-			.refs
-			.iter()
-			.filter_map(|member| {
-				let role = member.role.clone().to_lowercase();
-				if role != "outer" {
-					return None;
-				}
-				match member.member {
-					osmpbfreader::OsmId::Node(NodeId(id)) => {
-						self.get_coordinate_by_node_id(id).map(|c| vec![c])
-					}
-					osmpbfreader::OsmId::Way(WayId(id)) => self.get_coordinates_by_way_id(id),
-					osmpbfreader::OsmId::Relation(RelationId(id)) => {
-						if cur_id == id {
-							return None;
-						}
-						self.get_outer_coordinates_by_relation_id(id)
-					}
-				}
-			})
-			.flatten()
-			.collect::<Vec<_>>();
-		Some(res)
+		let tree = self.resolve_relation(id, &ResolveOptions::default())?;
+		let mut coordinates = Vec::new();
+		self.collect_tree_coordinates(&tree, true, &mut coordinates);
+		Some(coordinates)
 	}
 
 	pub fn get_coordinates_by_relation(&self, relation: &Relation) -> Option<Vec<(f64, f64)>> {
@@ -122,95 +211,153 @@ impl Osm {
 	}
 
 	pub fn get_coordinates_by_relation_id(&self, relation_id: i64) -> Option<Vec<(f64, f64)>> {
-		let relation = self.get_relation_by_id(relation_id)?;
-		let res = relation
+		let tree = self.resolve_relation(relation_id, &ResolveOptions::default())?;
+		let mut coordinates = Vec::new();
+		self.collect_tree_coordinates(&tree, false, &mut coordinates);
+		Some(coordinates)
+	}
+
+	/// Appends member coordinates in member order, descending into nested relations.
+	/// With `outer_only`, only members whose role is `outer` are used at every level.
+	fn collect_tree_coordinates(
+		&self,
+		tree: &RelationTree,
+		outer_only: bool,
+		coordinates: &mut Vec<(f64, f64)>,
+	) {
+		for member in &tree.members {
+			if outer_only && member.role.to_lowercase() != "outer" {
+				continue;
+			}
+			match &member.kind {
+				MemberKind::Node(id) => coordinates.extend(self.get_coordinate_by_node_id(*id)),
+				MemberKind::Way(id) => {
+					coordinates.extend(self.get_coordinates_by_way_id(*id).unwrap_or_default())
+				}
+				MemberKind::Relation(child) => {
+					self.collect_tree_coordinates(child, outer_only, coordinates)
+				}
+				MemberKind::Missing(_)
+				| MemberKind::Cycle(_)
+				| MemberKind::DepthExceeded(_)
+				| MemberKind::Repeated(_) => {}
+			}
+		}
+	}
+
+	/// Resolves a relation and its nested relations into a member tree.
+	///
+	/// A relation that is already on the path from the root is reported as
+	/// `MemberKind::Cycle` instead of being followed, so cycles such as A→B→A terminate.
+	/// A relation reached again through another branch is only expanded the first time
+	/// and reported as `MemberKind::Repeated` after that, so shared sub-relations do not
+	/// multiply; it is expanded once more only when reached closer to the root, where the
+	/// depth limit cuts off less of it.
+	pub fn resolve_relation(&self, id: i64, options: &ResolveOptions) -> Option<RelationTree> {
+		let relation = self.get_relation_by_id(id)?;
+		let mut path = HashSet::new();
+		let mut expanded = HashMap::new();
+		Some(self.resolve_relation_tree(relation, 0, options, &mut path, &mut expanded))
+	}
+
+	fn resolve_relation_tree(
+		&self,
+		relation: &Relation,
+		depth: usize,
+		options: &ResolveOptions,
+		path: &mut HashSet<i64>,
+		expanded: &mut HashMap<i64, usize>,
+	) -> RelationTree {
+		path.insert(relation.id.0);
+		expanded.insert(relation.id.0, depth);
+		let members = relation
 			.refs
 			.iter()
-			.filter_map(|member| match member.member {
-				osmpbfreader::OsmId::Node(NodeId(id)) => {
-					self.get_coordinate_by_node_id(id).map(|c| vec![c])
-				}
-				osmpbfreader::OsmId::Way(WayId(id)) => self.get_coordinates_by_way_id(id),
-				osmpbfreader::OsmId::Relation(RelationId(id)) => {
-					if relation_id == id {
-						return None;
+			.map(|member| {
+				let kind = match member.member {
+					OsmId::Node(NodeId(id)) => match self.get_coordinate_by_node_id(id) {
+						Some(_) => MemberKind::Node(id),
+						None => MemberKind::Missing(member.member),
+					},
+					OsmId::Way(WayId(id)) => match self.get_way_by_id(id) {
+						Some(_) => MemberKind::Way(id),
+						None => MemberKind::Missing(member.member),
+					},
+					OsmId::Relation(RelationId(id)) => {
+						if path.contains(&id) {
+							MemberKind::Cycle(id)
+						} else if depth >= options.max_depth {
+							MemberKind::DepthExceeded(id)
+						} else if expanded.get(&id).is_some_and(|&seen| seen <= depth + 1) {
+							MemberKind::Repeated(id)
+						} else {
+							match self.get_relation_by_id(id) {
+								Some(child) => MemberKind::Relation(
+									self.resolve_relation_tree(child, depth + 1, options, path, expanded),
+								),
+								None => MemberKind::Missing(member.member),
+							}
+						}
 					}
-					self.get_coordinates_by_relation_id(id)
+				};
+				RelationMember {
+					role: member.role.to_string(),
+					kind,
 				}
 			})
-			.flatten()
-			.collect::<Vec<_>>();
-		Some(res)
+			.collect();
+		path.remove(&relation.id.0);
+		RelationTree {
+			id: relation.id.0,
+			tags: relation.tags.clone(),
+			members,
+		}
 	}
 
-	pub fn get_not_outer_coordinates_by_relation_id(
-		&self,
-		id: i64,
-	) -> Option<Vec<serde_json::Value>> {
+	pub fn get_not_outer_coordinates_by_relation_id(&self, id: i64) -> Option<Vec<ResolvedMember>> {
 		let relation = self.get_relation_by_id(id)?;
 		let RelationId(cur_id) = relation.id;
 		let res = relation
 			.refs
 			.iter()
 			.filter_map(|member| {
-				let role = member.role.clone().to_lowercase();
-				if role == "outer" {
+				let role = member.role.to_string();
+				if role.to_lowercase() == "outer" {
 					return None;
 				}
 				match member.member {
 					osmpbfreader::OsmId::Node(NodeId(id)) => {
 						let node = self.get_node_by_id(id)?;
-						let micro = 10_000_000.0;
-						let lat = node.decimicro_lat.to_f64().map(|f| f / micro)?;
-						let lon = node.decimicro_lon.to_f64().map(|f| f / micro)?;
-						Some(serde_json::json!({
-							"id": id,
-							"tags": node.tags.clone(),
-							"coordinate": {
-								"lon": lon,
-								"lat": lat,
-							},
-						}))
+						let (lon, lat) = self.get_coordinate_by_node(node).ok()?;
+						Some(ResolvedMember::Node {
+							id,
+							role,
+							tags: node.tags.clone(),
+							coordinate: Coordinate { lon, lat },
+						})
 					}
 					osmpbfreader::OsmId::Way(WayId(id)) => {
 						let way = self.get_way_by_id(id)?;
-						let x = self.get_coordinates_by_way_id(id)?;
-						let coordinates = x
-							.iter()
-							.map(|(lon, lat)| {
-								serde_json::json!({
-									"lon": lon,
-									"lat": lat,
-								})
-							})
-							.collect::<Vec<_>>();
-						Some(serde_json::json!({
-							"id": id,
-							"tags": way.tags.clone(),
-							"coordinates": coordinates,
-						}))
+						let coordinates = self.get_coordinates_by_way_id(id)?;
+						Some(ResolvedMember::Way {
+							id,
+							role,
+							tags: way.tags.clone(),
+							coordinates: to_coordinates(coordinates),
+						})
 					}
 					osmpbfreader::OsmId::Relation(RelationId(id)) => {
 						if cur_id == id {
 							return None;
 						}
 						let rel = self.get_relation_by_id(id)?;
-						let v = self.get_outer_coordinates_by_relation_id(id)?;
-						let coordinates = v
-							.iter()
-							.map(|(lon, lat)| {
-								serde_json::json!({
-									"lon": lon,
-									"lat": lat,
-								})
-							})
-							.collect::<Vec<_>>();
-						Some(serde_json::json!({
-							"id": id,
-							"role": member.role.clone(),
-							"tags": rel.tags.clone(),
-							"coordinates": coordinates,
-						}))
+						let coordinates = self.get_outer_coordinates_by_relation_id(id)?;
+						Some(ResolvedMember::Relation {
+							id,
+							role,
+							tags: rel.tags.clone(),
+							coordinates: to_coordinates(coordinates),
+						})
 					}
 				}
 			})
@@ -218,3 +365,307 @@ impl Osm {
 		Some(res)
 	}
 }
+
+impl RelationTree {
+	/// Visits every member depth-first in member order, with the nesting depth of the
+	/// relation that contains it (0 for direct members of the root).
+	pub fn walk<F: FnMut(usize, &RelationMember)>(&self, f: &mut F) {
+		self.walk_at(0, f);
+	}
+
+	fn walk_at<F: FnMut(usize, &RelationMember)>(&self, depth: usize, f: &mut F) {
+		for member in &self.members {
+			f(depth, member);
+			if let MemberKind::Relation(child) = &member.kind {
+				child.walk_at(depth + 1, f);
+			}
+		}
+	}
+}
+
+impl ResolvedMember {
+	pub fn id(&self) -> i64 {
+		match self {
+			ResolvedMember::Node { id, .. }
+			| ResolvedMember::Way { id, .. }
+			| ResolvedMember::Relation { id, .. } => *id,
+		}
+	}
+
+	pub fn role(&self) -> &str {
+		match self {
+			ResolvedMember::Node { role, .. }
+			| ResolvedMember::Way { role, .. }
+			| ResolvedMember::Relation { role, .. } => role,
+		}
+	}
+
+	/// Wraps the member to serialize in the legacy untagged shape.
+	pub fn legacy(&self) -> LegacyResolvedMember<'_> {
+		LegacyResolvedMember(self)
+	}
+}
+
+impl Serialize for LegacyResolvedMember<'_> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+		#[derive(Serialize)]
+		#[serde(untagged)]
+		enum Legacy<'a> {
+			Node {
+				id: i64,
+				tags: &'a Tags,
+				coordinate: &'a Coordinate,
+			},
+			Way {
+				id: i64,
+				tags: &'a Tags,
+				coordinates: &'a [Coordinate],
+			},
+			Relation {
+				id: i64,
+				role: &'a str,
+				tags: &'a Tags,
+				coordinates: &'a [Coordinate],
+			},
+		}
+
+		let legacy = match self.0 {
+			ResolvedMember::Node {
+				id, tags, coordinate, ..
+			} => Legacy::Node {
+				id: *id,
+				tags,
+				coordinate,
+			},
+			ResolvedMember::Way {
+				id, tags, coordinates, ..
+			} => Legacy::Way {
+				id: *id,
+				tags,
+				coordinates,
+			},
+			ResolvedMember::Relation {
+				id,
+				role,
+				tags,
+				coordinates,
+			} => Legacy::Relation {
+				id: *id,
+				role,
+				tags,
+				coordinates,
+			},
+		};
+		legacy.serialize(serializer)
+	}
+}
+
+fn to_coordinates(coordinates: Vec<(f64, f64)>) -> Vec<Coordinate> {
+	coordinates
+		.into_iter()
+		.map(|(lon, lat)| Coordinate { lon, lat })
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use osmpbfreader::{Ref, Tags};
+
+	use super::*;
+
+	fn osm_with(relations: &[(i64, &[i64])]) -> Osm {
+		let mut osm = Osm::default();
+		for &(id, children) in relations {
+			osm.add_relation(Relation {
+				id: RelationId(id),
+				tags: Tags::new(),
+				refs: children
+					.iter()
+					.map(|&child| Ref {
+						member: OsmId::Relation(RelationId(child)),
+						role: "".into(),
+					})
+					.collect(),
+			});
+		}
+		osm
+	}
+
+	fn kinds(tree: &RelationTree) -> Vec<String> {
+		let mut kinds = Vec::new();
+		tree.walk(&mut |depth, member| {
+			let kind = match member.kind {
+				MemberKind::Relation(ref child) => format!("relation {}", child.id),
+				MemberKind::Cycle(id) => format!("cycle {}", id),
+				MemberKind::DepthExceeded(id) => format!("depth {}", id),
+				MemberKind::Repeated(id) => format!("repeated {}", id),
+				ref other => format!("{:?}", other),
+			};
+			kinds.push(format!("{}: {}", depth, kind));
+		});
+		kinds
+	}
+
+	#[test]
+	fn stops_at_cycles() {
+		let osm = osm_with(&[(1, &[2]), (2, &[3]), (3, &[1, 2])]);
+		let tree = osm.resolve_relation(1, &ResolveOptions::default()).unwrap();
+		assert_eq!(kinds(&tree), vec!["0: relation 2", "1: relation 3", "2: cycle 1", "2: cycle 2"]);
+	}
+
+	#[test]
+	fn stops_at_the_depth_limit() {
+		let osm = osm_with(&[(1, &[2]), (2, &[3]), (3, &[4]), (4, &[])]);
+		let tree = osm.resolve_relation(1, &ResolveOptions { max_depth: 1 }).unwrap();
+		assert_eq!(kinds(&tree), vec!["0: relation 2", "1: depth 3"]);
+	}
+
+	#[test]
+	fn expands_shared_relations_once() {
+		// Forty stacked diamonds: relation n holds n + 1 twice, 2^40 paths to the bottom
+		let layers = (1..=40).map(|id| (id, vec![id + 1, id + 1])).collect::<Vec<_>>();
+		let mut relations = layers.iter().map(|(id, children)| (*id, children.as_slice())).collect::<Vec<_>>();
+		relations.push((41, &[]));
+		let osm = osm_with(&relations);
+
+		let tree = osm.resolve_relation(1, &ResolveOptions { max_depth: 64 }).unwrap();
+		let kinds = kinds(&tree);
+		assert_eq!(kinds.iter().filter(|kind| kind.contains("relation")).count(), 40);
+		assert_eq!(kinds.iter().filter(|kind| kind.contains("repeated")).count(), 40);
+		assert_eq!(kinds[..3], ["0: relation 2", "1: relation 3", "2: relation 4"]);
+	}
+
+	#[test]
+	fn expands_again_when_reached_closer_to_the_root() {
+		// 4 is first met at depth 3 where the limit cuts it off, then directly below the root
+		let osm = osm_with(&[(1, &[2, 4]), (2, &[3]), (3, &[4]), (4, &[5]), (5, &[])]);
+		let tree = osm.resolve_relation(1, &ResolveOptions { max_depth: 3 }).unwrap();
+		assert_eq!(
+			kinds(&tree),
+			vec![
+				"0: relation 2",
+				"1: relation 3",
+				"2: relation 4",
+				"3: depth 5",
+				"0: relation 4",
+				"1: relation 5"
+			]
+		);
+	}
+
+	/// Relation 1 with a label node, an outer and an inner way, and a nested subarea.
+	fn boundary() -> Osm {
+		let mut osm = Osm::default();
+		let nodes = [(1, 100.5, 13.5), (2, 100.0, 13.0), (3, 101.0, 13.0), (4, 101.0, 14.0), (5, 100.25, 13.75)];
+		for (id, lon, lat) in nodes {
+			let mut tags = Tags::new();
+			if id == 1 {
+				tags.insert("name".into(), "Centre".into());
+			}
+			osm.add_node(Node {
+				id: NodeId(id),
+				tags,
+				decimicro_lat: (lat * 1e7) as i32,
+				decimicro_lon: (lon * 1e7) as i32,
+			});
+		}
+		for (id, nodes) in [(10, [2, 3]), (11, [3, 4]), (12, [4, 5])] {
+			let mut tags = Tags::new();
+			tags.insert("boundary".into(), "administrative".into());
+			osm.add_way(Way {
+				id: WayId(id),
+				tags,
+				nodes: nodes.iter().map(|&id| NodeId(id)).collect(),
+			});
+		}
+		let refs = |members: &[(OsmId, &str)]| {
+			members
+				.iter()
+				.map(|&(member, role)| Ref {
+					member,
+					role: role.into(),
+				})
+				.collect()
+		};
+		let mut tags = Tags::new();
+		tags.insert("type".into(), "boundary".into());
+		osm.add_relation(Relation {
+			id: RelationId(20),
+			tags: tags.clone(),
+			refs: refs(&[(OsmId::Way(WayId(12)), "outer")]),
+		});
+		osm.add_relation(Relation {
+			id: RelationId(1),
+			tags,
+			refs: refs(&[
+				(OsmId::Node(NodeId(1)), "admin_centre"),
+				(OsmId::Way(WayId(10)), "outer"),
+				(OsmId::Way(WayId(11)), "Inner"),
+				(OsmId::Relation(RelationId(20)), "subarea"),
+				(OsmId::Relation(RelationId(1)), "subarea"),
+			]),
+		});
+		osm
+	}
+
+	#[test]
+	fn serializes_members_tagged_with_their_role() {
+		let members = boundary().get_not_outer_coordinates_by_relation_id(1).unwrap();
+		let roles = members.iter().map(|m| (m.id(), m.role())).collect::<Vec<_>>();
+		assert_eq!(roles, vec![(1, "admin_centre"), (11, "Inner"), (20, "subarea")]);
+		assert_eq!(
+			serde_json::to_value(&members).unwrap(),
+			serde_json::json!([
+				{
+					"type": "node",
+					"id": 1,
+					"role": "admin_centre",
+					"tags": { "name": "Centre" },
+					"coordinate": { "lon": 100.5, "lat": 13.5 },
+				},
+				{
+					"type": "way",
+					"id": 11,
+					"role": "Inner",
+					"tags": { "boundary": "administrative" },
+					"coordinates": [{ "lon": 101.0, "lat": 13.0 }, { "lon": 101.0, "lat": 14.0 }],
+				},
+				{
+					"type": "relation",
+					"id": 20,
+					"role": "subarea",
+					"tags": { "type": "boundary" },
+					"coordinates": [{ "lon": 101.0, "lat": 14.0 }, { "lon": 100.25, "lat": 13.75 }],
+				},
+			])
+		);
+	}
+
+	#[test]
+	fn legacy_serializer_matches_the_old_json_output() {
+		let members = boundary().get_not_outer_coordinates_by_relation_id(1).unwrap();
+		let legacy = members.iter().map(ResolvedMember::legacy).collect::<Vec<_>>();
+		// As the `json!` objects the method built before it returned typed members
+		assert_eq!(
+			serde_json::to_value(&legacy).unwrap(),
+			serde_json::json!([
+				{
+					"id": 1,
+					"tags": { "name": "Centre" },
+					"coordinate": { "lon": 100.5, "lat": 13.5 },
+				},
+				{
+					"id": 11,
+					"tags": { "boundary": "administrative" },
+					"coordinates": [{ "lon": 101.0, "lat": 13.0 }, { "lon": 101.0, "lat": 14.0 }],
+				},
+				{
+					"id": 20,
+					"role": "subarea",
+					"tags": { "type": "boundary" },
+					"coordinates": [{ "lon": 101.0, "lat": 14.0 }, { "lon": 100.25, "lat": 13.75 }],
+				},
+			])
+		);
+	}
+}
@@ -0,0 +1,191 @@
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::GzDecoder;
+use osmpbfreader::OsmObj;
+use snafu::{OptionExt, ResultExt};
+
+use crate::error::{FileReadSnafu, UnknownInputFormatSnafu};
+use crate::model::osm_change_model::ChangeAction;
+use crate::model::osm_file_model::{InputFormat, OsmFile, OsmSource};
+use crate::model::pbf_model::PbfSource;
+use crate::service::osm_xml::read_osm_xml;
+use crate::service::pbf_decoder::for_each_obj_parallel;
+use crate::utils::{open_file, Result};
+
+/// Bytes read from the start of a file to recognise its format.
+const SNIFF_LEN: usize = 64;
+
+impl InputFormat {
+	/// Picks the format from the file extension, falling back to the leading bytes.
+	pub fn detect(path: &Path) -> Result<InputFormat> {
+		let name = path
+			.file_name()
+			.map(|name| name.to_string_lossy().to_lowercase())
+			.unwrap_or_default();
+		if name.ends_with(".pbf") {
+			return Ok(InputFormat::Pbf);
+		}
+		if name.ends_with(".osm") {
+			return Ok(InputFormat::Xml);
+		}
+		if name.ends_with(".osm.gz") {
+			return Ok(InputFormat::XmlGz);
+		}
+		if name.ends_with(".osm.bz2") {
+			return Ok(InputFormat::XmlBz2);
+		}
+
+		let mut head = Vec::with_capacity(SNIFF_LEN);
+		open_file(path)?
+			.take(SNIFF_LEN as u64)
+			.read_to_end(&mut head)
+			.context(FileReadSnafu { path })?;
+		InputFormat::from_magic(&head).context(UnknownInputFormatSnafu { path })
+	}
+
+	fn from_magic(head: &[u8]) -> Option<InputFormat> {
+		if head.starts_with(b"BZh") {
+			Some(InputFormat::XmlBz2)
+		} else if head.starts_with(&[0x1f, 0x8b]) {
+			Some(InputFormat::XmlGz)
+		} else if head.windows(9).any(|w| w == b"OSMHeader") {
+			// A PBF starts with a length-prefixed BlobHeader whose type is `OSMHeader`
+			Some(InputFormat::Pbf)
+		} else {
+			let text = String::from_utf8_lossy(head);
+			let text = text.trim_start_matches('\u{feff}').trim_start();
+			(text.starts_with("<?xml") || text.starts_with("<osm")).then_some(InputFormat::Xml)
+		}
+	}
+}
+
+impl OsmFile {
+	/// Opens `path` as PBF or (optionally compressed) OSM XML, see `InputFormat::detect`.
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<OsmFile> {
+		let path = path.as_ref();
+		let format = InputFormat::detect(path)?;
+		tracing::debug!("reading {:?} as {:?}", path, format);
+		let file = open_file(path)?;
+		Ok(match format {
+			InputFormat::Pbf => OsmFile::Pbf(PbfSource::new(BufReader::new(file))),
+			InputFormat::Xml => OsmFile::Xml(Box::new(BufReader::new(file))),
+			InputFormat::XmlGz => OsmFile::Xml(Box::new(BufReader::new(GzDecoder::new(file)))),
+			InputFormat::XmlBz2 => {
+				OsmFile::Xml(Box::new(BufReader::new(MultiBzDecoder::new(file))))
+			}
+		})
+	}
+}
+
+impl OsmSource for OsmFile {
+	fn for_each_obj(&mut self, f: &mut dyn FnMut(OsmObj) -> Result<()>) -> Result<()> {
+		match self {
+			OsmFile::Pbf(pbf) => pbf.for_each_obj(f),
+			// Objects a JOSM file marks as deleted are not part of the data
+			OsmFile::Xml(reader) => read_osm_xml(reader, |action, obj| match action {
+				Some(ChangeAction::Delete) => Ok(()),
+				_ => f(obj),
+			}),
+		}
+	}
+}
+
+impl<R: Read> OsmSource for PbfSource<R> {
+	fn for_each_obj(&mut self, f: &mut dyn FnMut(OsmObj) -> Result<()>) -> Result<()> {
+		for_each_obj_parallel(self, f)
+	}
+}
+
+impl<S: OsmSource + ?Sized> OsmSource for Box<S> {
+	fn for_each_obj(&mut self, f: &mut dyn FnMut(OsmObj) -> Result<()>) -> Result<()> {
+		(**self).for_each_obj(f)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
+
+	use super::*;
+	use crate::error::Error;
+	use crate::model::osm_model::Osm;
+	use crate::model::pbf_model::PbfWriter;
+
+	fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("osm_file_{}_{}", std::process::id(), name));
+		std::fs::write(&path, contents).unwrap();
+		path
+	}
+
+	#[test]
+	fn detects_format_from_extension() {
+		// Extensions win without the file being opened
+		for (name, format) in [
+			("extract.osm.pbf", InputFormat::Pbf),
+			("EXTRACT.PBF", InputFormat::Pbf),
+			("extract.osm", InputFormat::Xml),
+			("extract.osm.gz", InputFormat::XmlGz),
+			("extract.osm.bz2", InputFormat::XmlBz2),
+		] {
+			assert_eq!(InputFormat::detect(Path::new(name)).unwrap(), format, "{}", name);
+		}
+	}
+
+	#[test]
+	fn detects_format_from_leading_bytes() {
+		let pbf = PbfWriter::new(Vec::new()).unwrap().finish().unwrap();
+		assert_eq!(InputFormat::from_magic(&pbf), Some(InputFormat::Pbf));
+		assert_eq!(InputFormat::from_magic(b"BZh91AY&SY"), Some(InputFormat::XmlBz2));
+		assert_eq!(InputFormat::from_magic(&[0x1f, 0x8b, 0x08, 0x00]), Some(InputFormat::XmlGz));
+		assert_eq!(InputFormat::from_magic(b"<?xml version='1.0'?>"), Some(InputFormat::Xml));
+		assert_eq!(InputFormat::from_magic(b"\xef\xbb\xbf\n  <osm version=\"0.6\">"), Some(InputFormat::Xml));
+		assert_eq!(InputFormat::from_magic(b"{\"type\": \"FeatureCollection\"}"), None);
+		assert_eq!(InputFormat::from_magic(b""), None);
+
+		let xml = temp_file("sniff_xml", b"<osm version=\"0.6\"></osm>");
+		let unknown = temp_file("sniff_unknown", b"name,lat,lon\n");
+		let detected = (InputFormat::detect(&xml), InputFormat::detect(&unknown));
+		std::fs::remove_file(&xml).unwrap();
+		std::fs::remove_file(&unknown).unwrap();
+		assert_eq!(detected.0.unwrap(), InputFormat::Xml);
+		assert!(matches!(detected.1, Err(Error::UnknownInputFormat { .. })));
+		assert!(matches!(
+			InputFormat::detect(Path::new("/nonexistent/extract")),
+			Err(Error::FileNotFound { .. })
+		));
+	}
+
+	#[test]
+	fn skips_objects_josm_marks_deleted() {
+		let path = temp_file(
+			"josm.osm",
+			br#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version='0.6' upload='false' generator='JOSM'>
+  <node id='1' action='delete' timestamp='2024-01-01T00:00:00Z' uid='1' user='a' visible='true' version='2' changeset='1' />
+  <node id='2' timestamp='2024-01-01T00:00:00Z' uid='1' user='a' visible='true' version='1' changeset='1' lat='13.75' lon='100.5' />
+  <node id='-3' action='modify' visible='true' lat='13.76' lon='100.51' />
+  <way id='4' action='delete' timestamp='2024-01-01T00:00:00Z' uid='1' user='a' visible='true' version='3' changeset='1'>
+    <nd ref='1' />
+    <nd ref='2' />
+    <tag k='highway' v='residential' />
+  </way>
+  <way id='-5' action='modify' visible='true'>
+    <nd ref='2' />
+    <nd ref='-3' />
+    <tag k='highway' v='service' />
+  </way>
+</osm>
+"#,
+		);
+		let osm = Osm::from_osm_source(OsmFile::open(&path).unwrap());
+		std::fs::remove_file(&path).unwrap();
+		let osm = osm.unwrap();
+
+		let mut nodes = osm.nodes.keys().copied().collect::<Vec<_>>();
+		nodes.sort();
+		assert_eq!(nodes, vec![-3, 2]);
+		assert_eq!(osm.ways.keys().copied().collect::<Vec<_>>(), vec![-5]);
+	}
+}
@@ -0,0 +1,234 @@
+use std::io::BufRead;
+use std::str::FromStr;
+
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use snafu::ResultExt;
+
+use crate::error::{Error, OsmXmlSnafu};
+use crate::model::osm_change_model::ChangeAction;
+use crate::utils::Result;
+
+/// Streams the objects of an OSM XML document (`<osm>` or `<osmChange>`) in document order.
+///
+/// Each object comes with the action of its enclosing `<create>`, `<modify>` or
+/// `<delete>` block, or else the JOSM-style `action` attribute on the object itself.
+pub(crate) fn read_osm_xml<R, F>(reader: R, mut f: F) -> Result<()>
+where
+	R: BufRead,
+	F: FnMut(Option<ChangeAction>, OsmObj) -> Result<()>,
+{
+	let mut xml = Reader::from_reader(reader);
+	xml.trim_text(true);
+	let mut buf = Vec::new();
+	let mut block = None;
+	let mut current: Option<(Option<ChangeAction>, OsmObj)> = None;
+	loop {
+		match xml.read_event_into(&mut buf).context(OsmXmlSnafu)? {
+			Event::Start(element) => {
+				if let Some(done) = start_element(&element, false, &mut block, &mut current)? {
+					f(done.0, done.1)?;
+				}
+			}
+			Event::Empty(element) => {
+				if let Some(done) = start_element(&element, true, &mut block, &mut current)? {
+					f(done.0, done.1)?;
+				}
+			}
+			Event::End(element) => match element.name().as_ref() {
+				b"create" | b"modify" | b"delete" => block = None,
+				b"node" | b"way" | b"relation" => {
+					if let Some((action, obj)) = current.take() {
+						f(action, obj)?;
+					}
+				}
+				_ => {}
+			},
+			Event::Eof => return Ok(()),
+			_ => {}
+		}
+		buf.clear();
+	}
+}
+
+/// Handles an opening tag, returning an object that is already complete, i.e. written
+/// as an empty element.
+fn start_element(
+	element: &BytesStart,
+	empty: bool,
+	block: &mut Option<ChangeAction>,
+	current: &mut Option<(Option<ChangeAction>, OsmObj)>,
+) -> Result<Option<(Option<ChangeAction>, OsmObj)>> {
+	let action = match element.name().as_ref() {
+		b"node" | b"way" | b"relation" => match block {
+			Some(action) => Some(*action),
+			None => match optional::<String>(element, b"action")?.as_deref() {
+				Some("delete") => Some(ChangeAction::Delete),
+				Some("modify") => Some(ChangeAction::Modify),
+				_ => None,
+			},
+		},
+		_ => None,
+	};
+	let obj = match element.name().as_ref() {
+		b"create" => {
+			*block = Some(ChangeAction::Create);
+			return Ok(None);
+		}
+		b"modify" => {
+			*block = Some(ChangeAction::Modify);
+			return Ok(None);
+		}
+		b"delete" => {
+			*block = Some(ChangeAction::Delete);
+			return Ok(None);
+		}
+		b"node" => {
+			let id = required(element, b"id")?;
+			let (lat, lon) = match (optional::<f64>(element, b"lat")?, optional::<f64>(element, b"lon")?) {
+				(Some(lat), Some(lon)) => (lat, lon),
+				// Deleted nodes are commonly written without a location
+				_ if action == Some(ChangeAction::Delete) => (0.0, 0.0),
+				_ => return Err(invalid(format!("node {id} has no lat/lon"))),
+			};
+			OsmObj::Node(Node {
+				id: NodeId(id),
+				tags: Tags::new(),
+				decimicro_lat: (lat * 1e7).round() as i32,
+				decimicro_lon: (lon * 1e7).round() as i32,
+			})
+		}
+		b"way" => OsmObj::Way(Way {
+			id: WayId(required(element, b"id")?),
+			tags: Tags::new(),
+			nodes: Vec::new(),
+		}),
+		b"relation" => OsmObj::Relation(Relation {
+			id: RelationId(required(element, b"id")?),
+			tags: Tags::new(),
+			refs: Vec::new(),
+		}),
+		b"tag" => {
+			let key: String = required(element, b"k")?;
+			let value: String = required(element, b"v")?;
+			match current {
+				Some((_, OsmObj::Node(node))) => node.tags.insert(key.into(), value.into()),
+				Some((_, OsmObj::Way(way))) => way.tags.insert(key.into(), value.into()),
+				Some((_, OsmObj::Relation(relation))) => relation.tags.insert(key.into(), value.into()),
+				None => None,
+			};
+			return Ok(None);
+		}
+		b"nd" => {
+			if let Some((_, OsmObj::Way(way))) = current {
+				way.nodes.push(NodeId(required(element, b"ref")?));
+			}
+			return Ok(None);
+		}
+		b"member" => {
+			if let Some((_, OsmObj::Relation(relation))) = current {
+				let id = required(element, b"ref")?;
+				let member = match required::<String>(element, b"type")?.as_str() {
+					"node" => OsmId::Node(NodeId(id)),
+					"way" => OsmId::Way(WayId(id)),
+					"relation" => OsmId::Relation(RelationId(id)),
+					other => return Err(invalid(format!("unknown member type {other:?}"))),
+				};
+				let role: String = optional(element, b"role")?.unwrap_or_default();
+				relation.refs.push(Ref {
+					member,
+					role: role.into(),
+				});
+			}
+			return Ok(None);
+		}
+		_ => return Ok(None),
+	};
+
+	if empty {
+		Ok(Some((action, obj)))
+	} else {
+		*current = Some((action, obj));
+		Ok(None)
+	}
+}
+
+fn optional<T: FromStr>(element: &BytesStart, key: &[u8]) -> Result<Option<T>> {
+	for attr in element.attributes() {
+		let attr = attr.map_err(quick_xml::Error::from).context(OsmXmlSnafu)?;
+		if attr.key.as_ref() == key {
+			let value = attr.unescape_value().context(OsmXmlSnafu)?;
+			return value.parse().map(Some).map_err(|_| {
+				invalid(format!(
+					"invalid {} {:?} on <{}>",
+					String::from_utf8_lossy(key),
+					value,
+					String::from_utf8_lossy(element.name().as_ref())
+				))
+			});
+		}
+	}
+	Ok(None)
+}
+
+fn required<T: FromStr>(element: &BytesStart, key: &[u8]) -> Result<T> {
+	optional(element, key)?.ok_or_else(|| {
+		invalid(format!(
+			"missing {} on <{}>",
+			String::from_utf8_lossy(key),
+			String::from_utf8_lossy(element.name().as_ref())
+		))
+	})
+}
+
+pub(crate) fn invalid(message: String) -> Error {
+	Error::InvalidOsmXml { message }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn read(xml: &str) -> Result<Vec<(Option<ChangeAction>, OsmObj)>> {
+		let mut objs = Vec::new();
+		read_osm_xml(xml.as_bytes(), |action, obj| {
+			objs.push((action, obj));
+			Ok(())
+		})?;
+		Ok(objs)
+	}
+
+	#[test]
+	fn reads_objects_with_their_actions() {
+		let objs = read(
+			r#"<osmChange version="0.6">
+  <create><node id="1" lat="13.75" lon="100.5"><tag k="name" v="A &amp; B"/></node></create>
+  <delete><node id="2"/><way id="3"/></delete>
+</osmChange>"#,
+		)
+		.unwrap();
+		assert_eq!(objs.len(), 3);
+		let (action, OsmObj::Node(node)) = &objs[0] else {
+			panic!("expected a node");
+		};
+		assert_eq!(*action, Some(ChangeAction::Create));
+		assert_eq!((node.decimicro_lat, node.decimicro_lon), (137_500_000, 1_005_000_000));
+		assert_eq!(node.tags.get("name").map(|v| v.as_str()), Some("A & B"));
+		assert_eq!(objs[1].0, Some(ChangeAction::Delete));
+		assert_eq!(objs[2].0, Some(ChangeAction::Delete));
+	}
+
+	#[test]
+	fn rejects_nodes_without_location_unless_deleted() {
+		for xml in [
+			r#"<osmChange><modify><node id="1"/></modify></osmChange>"#,
+			r#"<osmChange><create><node id="1" lat="13.75"/></create></osmChange>"#,
+			r#"<osm><node id="1" lon="100.5"/></osm>"#,
+			r#"<osm><node id="1" action="modify"/></osm>"#,
+		] {
+			assert!(matches!(read(xml), Err(Error::InvalidOsmXml { .. })), "{}", xml);
+		}
+		assert!(read(r#"<osm><node id="1" action="delete"/></osm>"#).is_ok());
+	}
+}
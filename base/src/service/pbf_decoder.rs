@@ -0,0 +1,149 @@
+use std::cell::Cell;
+use std::io::Read;
+use std::rc::Rc;
+
+use osmpbfreader::{blocks, primitive_block_from_blob, OsmObj, OsmPbfReader};
+use rayon::prelude::*;
+use snafu::ResultExt;
+
+use crate::error::PbfDecodeSnafu;
+use crate::model::pbf_model::{OffsetReader, PbfSource};
+use crate::utils::Result;
+
+/// Blobs decoded per parallel batch; bounds how many decoded objects are held at once.
+const BATCH_BLOBS: usize = 64;
+
+impl<R: Read> Read for OffsetReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		let read = self.inner.read(buf)?;
+		self.offset.set(self.offset.get() + read as u64);
+		Ok(read)
+	}
+}
+
+impl<R: Read> PbfSource<R> {
+	pub fn new(inner: R) -> Self {
+		let offset = Rc::new(Cell::new(0));
+		PbfSource {
+			reader: OsmPbfReader::new(OffsetReader {
+				inner,
+				offset: offset.clone(),
+			}),
+			offset,
+		}
+	}
+}
+
+/// Decompresses and parses PBF blobs on the rayon pool, calling `f` for every object in
+/// file order.
+///
+/// Blobs are read sequentially in batches, decoded in parallel and merged back in their
+/// original order, so the callback sees exactly the sequence `OsmPbfReader::iter` yields.
+/// Decode errors report the byte offset at which the failing blob starts.
+pub fn for_each_obj_parallel<R, F>(pbf: &mut PbfSource<R>, mut f: F) -> Result<()>
+where
+	R: Read,
+	F: FnMut(OsmObj) -> Result<()>,
+{
+	let offset = pbf.offset.clone();
+	let mut blobs = pbf.reader.blobs();
+	loop {
+		let mut batch = Vec::with_capacity(BATCH_BLOBS);
+		while batch.len() < BATCH_BLOBS {
+			let start = offset.get();
+			match blobs.next() {
+				Some(blob) => batch.push((start, blob.context(PbfDecodeSnafu { offset: start })?)),
+				None => break,
+			}
+		}
+		if batch.is_empty() {
+			return Ok(());
+		}
+		let decoded = batch
+			.into_par_iter()
+			.map(|(start, blob)| {
+				let block = primitive_block_from_blob(&blob).context(PbfDecodeSnafu { offset: start })?;
+				Ok(blocks::iter(&block).collect::<Vec<_>>())
+			})
+			.collect::<Result<Vec<_>>>()?;
+		for obj in decoded.into_iter().flatten() {
+			f(obj)?;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use osmpbfreader::{Node, NodeId, Tags, Way, WayId};
+
+	use super::*;
+	use crate::model::pbf_model::PbfWriter;
+
+	/// Alternates nodes and ways so that the writer starts a new block for every object.
+	fn objects(count: i64) -> Vec<OsmObj> {
+		let mut objects = Vec::new();
+		for id in 1..=count {
+			let mut tags = Tags::new();
+			tags.insert("ref".into(), id.to_string().into());
+			objects.push(OsmObj::Node(Node {
+				id: NodeId(id),
+				tags: tags.clone(),
+				decimicro_lat: id as i32 * 1_000,
+				decimicro_lon: -(id as i32) * 2_000,
+			}));
+			objects.push(OsmObj::Way(Way {
+				id: WayId(id),
+				tags,
+				nodes: vec![NodeId(id), NodeId(id + 1)],
+			}));
+		}
+		objects
+	}
+
+	fn write(objects: &[OsmObj]) -> Vec<u8> {
+		let mut writer = PbfWriter::new(Vec::new()).unwrap();
+		for obj in objects {
+			writer.write(obj.clone()).unwrap();
+		}
+		writer.finish().unwrap()
+	}
+
+	#[test]
+	fn parallel_decoding_matches_sequential_order() {
+		let written = objects(100);
+		let bytes = write(&written);
+		let blobs = OsmPbfReader::new(Cursor::new(&bytes)).blobs().count();
+		assert!(blobs > 2 * BATCH_BLOBS, "only {} blobs", blobs);
+
+		let sequential = OsmPbfReader::new(Cursor::new(&bytes))
+			.iter()
+			.collect::<std::result::Result<Vec<_>, _>>()
+			.unwrap();
+		let mut parallel = Vec::new();
+		for_each_obj_parallel(&mut PbfSource::new(Cursor::new(&bytes)), |obj| {
+			parallel.push(obj);
+			Ok(())
+		})
+		.unwrap();
+
+		assert_eq!(parallel, sequential);
+		assert_eq!(parallel, written);
+	}
+
+	#[test]
+	fn stops_at_the_first_callback_error() {
+		let bytes = write(&objects(100));
+		let mut seen = 0;
+		let result = for_each_obj_parallel(&mut PbfSource::new(Cursor::new(&bytes)), |_| {
+			seen += 1;
+			match seen {
+				70 => Err(crate::error::Error::Overflow),
+				_ => Ok(()),
+			}
+		});
+		assert!(matches!(result, Err(crate::error::Error::Overflow)));
+		assert_eq!(seen, 70);
+	}
+}
@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem::discriminant;
+use std::path::Path;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Relation, Tags, Way};
+use snafu::{ensure, ResultExt};
+
+use crate::error::{FileWriteSnafu, PbfTooLargeSnafu, PbfWriteSnafu};
+use crate::model::osm_model::Osm;
+use crate::model::pbf_model::PbfWriter;
+use crate::utils::Result;
+
+/// Objects per primitive block, as written by osmium and osmosis.
+const BLOCK_SIZE: usize = 8000;
+
+/// Format limits on the uncompressed blob and the `BlobHeader`; readers may reject larger ones.
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+const MAX_BLOB_HEADER_SIZE: usize = 64 * 1024;
+
+/// Encoded size at which a block is flushed early, leaving headroom below `MAX_BLOB_SIZE`.
+const BLOCK_BYTES: usize = 16 * 1024 * 1024;
+
+/// Longest varint, and the most a string table index takes in practice.
+const MAX_VARINT: usize = 10;
+const MAX_INDEX: usize = 5;
+
+// Protobuf wire types
+const VARINT: u32 = 0;
+const LENGTH_DELIMITED: u32 = 2;
+
+impl<W: Write> PbfWriter<W> {
+	/// Starts a PBF stream by writing its `OSMHeader` blob.
+	pub fn new(writer: W) -> Result<Self> {
+		let mut pbf = PbfWriter {
+			writer,
+			pending: Vec::new(),
+			pending_size: 0,
+		};
+		let mut header = Message::default();
+		header.bytes(4, b"OsmSchema-V0.6");
+		header.bytes(4, b"DenseNodes");
+		header.bytes(16, b"open_rust_map");
+		pbf.write_blob("OSMHeader", &header.0)?;
+		Ok(pbf)
+	}
+
+	/// Queues one object. Objects may come in any order, but files read fastest with
+	/// all nodes first, then ways, then relations.
+	///
+	/// A block is written once it holds `BLOCK_SIZE` objects or its encoding could grow
+	/// past `BLOCK_BYTES`, whichever comes first, so heavily tagged data stays within the
+	/// format's blob size limit.
+	pub fn write(&mut self, obj: OsmObj) -> Result<()> {
+		let same_kind = self
+			.pending
+			.first()
+			.is_none_or(|first| discriminant(first) == discriminant(&obj));
+		let size = encoded_size_bound(&obj);
+		if !same_kind || self.pending.len() >= BLOCK_SIZE || self.pending_size + size > BLOCK_BYTES {
+			self.flush_block()?;
+		}
+		self.pending.push(obj);
+		self.pending_size += size;
+		Ok(())
+	}
+
+	/// Writes any queued objects and returns the underlying writer.
+	pub fn finish(mut self) -> Result<W> {
+		self.flush_block()?;
+		self.writer.flush().context(PbfWriteSnafu)?;
+		Ok(self.writer)
+	}
+
+	fn flush_block(&mut self) -> Result<()> {
+		if self.pending.is_empty() {
+			return Ok(());
+		}
+		let objs = std::mem::take(&mut self.pending);
+		self.pending_size = 0;
+		let mut strings = StringTable::default();
+		let group = match &objs[0] {
+			OsmObj::Node(_) => dense_nodes(&objs, &mut strings),
+			OsmObj::Way(_) => repeated(&objs, 3, &mut strings),
+			OsmObj::Relation(_) => repeated(&objs, 4, &mut strings),
+		};
+		let mut block = Message::default();
+		block.message(1, &strings.encode());
+		block.message(2, &group);
+		self.write_blob("OSMData", &block.0)
+	}
+
+	fn write_blob(&mut self, kind: &str, data: &[u8]) -> Result<()> {
+		ensure!(
+			data.len() <= MAX_BLOB_SIZE,
+			PbfTooLargeSnafu {
+				kind: "blob",
+				size: data.len(),
+				limit: MAX_BLOB_SIZE
+			}
+		);
+		let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+		encoder.write_all(data).context(PbfWriteSnafu)?;
+		let compressed = encoder.finish().context(PbfWriteSnafu)?;
+
+		let mut blob = Message::default();
+		blob.varint(2, data.len() as u64);
+		blob.bytes(3, &compressed);
+		let mut header = Message::default();
+		header.bytes(1, kind.as_bytes());
+		header.varint(3, blob.0.len() as u64);
+		ensure!(
+			header.0.len() <= MAX_BLOB_HEADER_SIZE,
+			PbfTooLargeSnafu {
+				kind: "blob header",
+				size: header.0.len(),
+				limit: MAX_BLOB_HEADER_SIZE
+			}
+		);
+
+		self.writer
+			.write_all(&(header.0.len() as u32).to_be_bytes())
+			.and_then(|_| self.writer.write_all(&header.0))
+			.and_then(|_| self.writer.write_all(&blob.0))
+			.context(PbfWriteSnafu)
+	}
+}
+
+impl Osm {
+	/// Writes the store as a PBF sorted by type then id.
+	///
+	/// With a node-location cache, untagged nodes are only known through the ways that
+	/// reference them, so nodes used by no way and carrying no tags are not written.
+	pub fn write_pbf<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+		let path = path.as_ref();
+		let file = File::create(path).context(FileWriteSnafu { path })?;
+		let mut pbf = PbfWriter::new(BufWriter::new(file))?;
+
+		let mut node_ids = self.nodes.keys().copied().collect::<Vec<_>>();
+		if self.locations.is_some() {
+			node_ids.extend(self.ways.values().flat_map(|way| way.nodes.iter().map(|id| id.0)));
+		}
+		node_ids.sort_unstable();
+		node_ids.dedup();
+		for id in node_ids {
+			let node = match self.get_node_by_id(id) {
+				Some(node) => node.clone(),
+				None => match self.locations.as_ref().and_then(|l| l.get_decimicro(id)) {
+					Some((decimicro_lat, decimicro_lon)) => Node {
+						id: NodeId(id),
+						tags: Tags::new(),
+						decimicro_lat,
+						decimicro_lon,
+					},
+					None => continue,
+				},
+			};
+			pbf.write(OsmObj::Node(node))?;
+		}
+
+		let mut way_ids = self.ways.keys().copied().collect::<Vec<_>>();
+		way_ids.sort_unstable();
+		for id in way_ids {
+			pbf.write(OsmObj::Way(self.ways[&id].clone()))?;
+		}
+		let mut relation_ids = self.relations.keys().copied().collect::<Vec<_>>();
+		relation_ids.sort_unstable();
+		for id in relation_ids {
+			pbf.write(OsmObj::Relation(self.relations[&id].clone()))?;
+		}
+		pbf.finish()?;
+		tracing::info!("wrote pbf {:?}", path);
+		Ok(())
+	}
+}
+
+/// Upper bound on the bytes `obj` adds to a primitive block, counting each of its strings
+/// as a new string table entry.
+fn encoded_size_bound(obj: &OsmObj) -> usize {
+	let string = |value: &str| value.len() + MAX_VARINT + MAX_INDEX;
+	let tags = |tags: &Tags| {
+		tags.iter()
+			.map(|(key, value)| string(key) + string(value))
+			.sum::<usize>()
+	};
+	// Message keys and length prefixes of the object and its packed fields
+	let framing = 8 * MAX_VARINT;
+	framing
+		+ match obj {
+			OsmObj::Node(node) => 3 * MAX_VARINT + tags(&node.tags),
+			OsmObj::Way(way) => MAX_VARINT + tags(&way.tags) + way.nodes.len() * MAX_VARINT,
+			OsmObj::Relation(relation) => {
+				MAX_VARINT
+					+ tags(&relation.tags)
+					+ relation
+						.refs
+						.iter()
+						.map(|member| string(&member.role) + MAX_VARINT + 1)
+						.sum::<usize>()
+			}
+		}
+}
+
+/// A `PrimitiveGroup` holding a single `DenseNodes` message.
+fn dense_nodes(objs: &[OsmObj], strings: &mut StringTable) -> Vec<u8> {
+	let nodes = objs.iter().filter_map(|obj| obj.node()).collect::<Vec<_>>();
+	let mut keys_vals = Vec::new();
+	for node in &nodes {
+		for (key, value) in node.tags.iter() {
+			keys_vals.push(strings.index(key) as u64);
+			keys_vals.push(strings.index(value) as u64);
+		}
+		keys_vals.push(0);
+	}
+	let mut dense = Message::default();
+	dense.packed(1, delta(nodes.iter().map(|n| n.id.0)));
+	// Coordinates use the default granularity of 100 nanodegrees, i.e. decimicro degrees
+	dense.packed(8, delta(nodes.iter().map(|n| n.decimicro_lat as i64)));
+	dense.packed(9, delta(nodes.iter().map(|n| n.decimicro_lon as i64)));
+	if keys_vals.iter().any(|&v| v != 0) {
+		dense.packed(10, keys_vals);
+	}
+	let mut group = Message::default();
+	group.message(2, &dense.0);
+	group.0
+}
+
+/// A `PrimitiveGroup` of ways (field 3) or relations (field 4).
+fn repeated(objs: &[OsmObj], field: u32, strings: &mut StringTable) -> Vec<u8> {
+	let mut group = Message::default();
+	for obj in objs {
+		let encoded = match obj {
+			OsmObj::Way(way) => encode_way(way, strings),
+			OsmObj::Relation(relation) => encode_relation(relation, strings),
+			OsmObj::Node(_) => continue,
+		};
+		group.message(field, &encoded);
+	}
+	group.0
+}
+
+fn encode_way(way: &Way, strings: &mut StringTable) -> Vec<u8> {
+	let mut message = Message::default();
+	message.varint(1, way.id.0 as u64);
+	encode_tags(&mut message, &way.tags, strings);
+	message.packed(8, delta(way.nodes.iter().map(|id| id.0)));
+	message.0
+}
+
+fn encode_relation(relation: &Relation, strings: &mut StringTable) -> Vec<u8> {
+	let mut message = Message::default();
+	message.varint(1, relation.id.0 as u64);
+	encode_tags(&mut message, &relation.tags, strings);
+	let roles = relation
+		.refs
+		.iter()
+		.map(|member| strings.index(&member.role) as u64)
+		.collect();
+	message.packed(8, roles);
+	message.packed(9, delta(relation.refs.iter().map(|member| member.member.inner_id())));
+	let types = relation
+		.refs
+		.iter()
+		.map(|member| match member.member {
+			OsmId::Node(_) => 0,
+			OsmId::Way(_) => 1,
+			OsmId::Relation(_) => 2,
+		})
+		.collect();
+	message.packed(10, types);
+	message.0
+}
+
+fn encode_tags(message: &mut Message, tags: &Tags, strings: &mut StringTable) {
+	let (keys, values): (Vec<u64>, Vec<u64>) = tags
+		.iter()
+		.map(|(key, value)| (strings.index(key) as u64, strings.index(value) as u64))
+		.unzip();
+	message.packed(2, keys);
+	message.packed(3, values);
+}
+
+/// Delta-encodes signed values as zigzag varints, as `sint64` packed fields expect.
+fn delta(values: impl Iterator<Item = i64>) -> Vec<u64> {
+	let mut previous = 0;
+	values
+		.map(|value| {
+			let encoded = zigzag(value - previous);
+			previous = value;
+			encoded
+		})
+		.collect()
+}
+
+fn zigzag(value: i64) -> u64 {
+	((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Per-block string table; index 0 is reserved for the empty string.
+#[derive(Default)]
+struct StringTable {
+	indices: HashMap<String, u32>,
+	strings: Vec<String>,
+}
+
+impl StringTable {
+	fn index(&mut self, value: &str) -> u32 {
+		if self.strings.is_empty() {
+			self.strings.push(String::new());
+			self.indices.insert(String::new(), 0);
+		}
+		if let Some(&index) = self.indices.get(value) {
+			return index;
+		}
+		let index = self.strings.len() as u32;
+		self.strings.push(value.to_string());
+		self.indices.insert(value.to_string(), index);
+		index
+	}
+
+	fn encode(&mut self) -> Vec<u8> {
+		self.index("");
+		let mut message = Message::default();
+		for value in &self.strings {
+			message.bytes(1, value.as_bytes());
+		}
+		message.0
+	}
+}
+
+/// Minimal protobuf encoder for the handful of field kinds the PBF format uses.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+	fn key(&mut self, field: u32, wire_type: u32) {
+		put_varint(&mut self.0, ((field << 3) | wire_type) as u64);
+	}
+
+	fn varint(&mut self, field: u32, value: u64) {
+		self.key(field, VARINT);
+		put_varint(&mut self.0, value);
+	}
+
+	fn bytes(&mut self, field: u32, value: &[u8]) {
+		self.key(field, LENGTH_DELIMITED);
+		put_varint(&mut self.0, value.len() as u64);
+		self.0.extend_from_slice(value);
+	}
+
+	fn message(&mut self, field: u32, encoded: &[u8]) {
+		self.bytes(field, encoded);
+	}
+
+	/// Packed repeated varints; omitted entirely when empty.
+	fn packed(&mut self, field: u32, values: Vec<u64>) {
+		if values.is_empty() {
+			return;
+		}
+		let mut encoded = Vec::with_capacity(values.len());
+		for value in values {
+			put_varint(&mut encoded, value);
+		}
+		self.bytes(field, &encoded);
+	}
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+	while value >= 0x80 {
+		buf.push((value as u8) | 0x80);
+		value >>= 7;
+	}
+	buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+	use osmpbfreader::{OsmPbfReader, Ref, RelationId, WayId};
+
+	use super::*;
+
+	fn tags(pairs: &[(&str, &str)]) -> Tags {
+		let mut tags = Tags::new();
+		for (key, value) in pairs {
+			tags.insert((*key).into(), (*value).into());
+		}
+		tags
+	}
+
+	fn node(id: i64, lat: i32, lon: i32, pairs: &[(&str, &str)]) -> Node {
+		Node {
+			id: NodeId(id),
+			tags: tags(pairs),
+			decimicro_lat: lat,
+			decimicro_lon: lon,
+		}
+	}
+
+	fn read_back(path: &Path) -> Vec<OsmObj> {
+		let mut reader = OsmPbfReader::new(File::open(path).unwrap());
+		reader.iter().map(|obj| obj.unwrap()).collect()
+	}
+
+	#[test]
+	fn round_trips_through_osmpbfreader() {
+		let mut osm = Osm::default();
+		osm.add_node(node(1, 137_563_000, 1_005_018_000, &[]));
+		osm.add_node(node(2, 137_564_000, 1_005_019_000, &[("amenity", "cafe"), ("name", "ร้านกาแฟ")]));
+		osm.add_node(node(3, -337_000_000, -705_000_000, &[]));
+		osm.add_way(Way {
+			id: WayId(10),
+			tags: tags(&[("highway", "residential")]),
+			nodes: vec![NodeId(1), NodeId(2), NodeId(3)],
+		});
+		osm.add_way(Way {
+			id: WayId(11),
+			tags: Tags::new(),
+			nodes: vec![NodeId(3), NodeId(1)],
+		});
+		osm.add_relation(Relation {
+			id: RelationId(100),
+			tags: tags(&[("type", "route"), ("route", "bus")]),
+			refs: vec![
+				Ref {
+					member: OsmId::Way(WayId(10)),
+					role: "".into(),
+				},
+				Ref {
+					member: OsmId::Node(NodeId(2)),
+					role: "stop".into(),
+				},
+				Ref {
+					member: OsmId::Relation(RelationId(101)),
+					role: "".into(),
+				},
+			],
+		});
+		osm.add_relation(Relation {
+			id: RelationId(101),
+			tags: Tags::new(),
+			refs: Vec::new(),
+		});
+
+		let path = std::env::temp_dir().join(format!("pbf_round_trip_{}.osm.pbf", std::process::id()));
+		osm.write_pbf(&path).unwrap();
+		let objs = read_back(&path);
+		std::fs::remove_file(&path).unwrap();
+
+		let mut expected = osm.nodes.values().cloned().map(OsmObj::Node).collect::<Vec<_>>();
+		expected.extend(osm.ways.values().cloned().map(OsmObj::Way));
+		expected.extend(osm.relations.values().cloned().map(OsmObj::Relation));
+		expected.sort_by_key(|obj| obj.id());
+		assert_eq!(objs, expected);
+	}
+
+	#[test]
+	fn splits_blocks_by_encoded_size() {
+		// 2000 nodes with 10 KiB of tags each, about 20 MiB in well under `BLOCK_SIZE` objects
+		let value = "x".repeat(10 * 1024);
+		let nodes = (1..=2000)
+			.map(|id| node(id, 0, 0, &[("note", &format!("{}{}", id, value))]))
+			.collect::<Vec<_>>();
+
+		let mut pbf = PbfWriter::new(Vec::new()).unwrap();
+		for node in &nodes {
+			pbf.write(OsmObj::Node(node.clone())).unwrap();
+		}
+		let bytes = pbf.finish().unwrap();
+
+		let mut reader = OsmPbfReader::new(std::io::Cursor::new(&bytes));
+		let blobs = reader.blobs().map(|blob| blob.unwrap()).collect::<Vec<_>>();
+		assert!(blobs.len() >= 2, "expected at least two data blobs, got {}", blobs.len());
+		for blob in &blobs {
+			assert!(blob.get_raw_size() as usize <= MAX_BLOB_SIZE);
+		}
+		let objs = OsmPbfReader::new(std::io::Cursor::new(&bytes))
+			.iter()
+			.map(|obj| obj.unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(objs, nodes.into_iter().map(OsmObj::Node).collect::<Vec<_>>());
+	}
+}
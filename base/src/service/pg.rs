@@ -0,0 +1,315 @@
+use std::pin::Pin;
+
+use bytes::BytesMut;
+use geo::{LineString, MultiPolygon, Polygon};
+use osmpbfreader::{Tags, Way};
+use serde_json::{Map, Value};
+use snafu::ResultExt;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{to_sql_checked, IsNull, ToSql, Type};
+use tokio_postgres::{Client, NoTls};
+
+use crate::error::{Error, TokioPgSnafu};
+use crate::model::osm_model::Osm;
+use crate::model::pg_model::{Ewkb, PgImportConfig, PgImportSummary};
+use crate::utils::Result;
+
+const SRID: u32 = 4326;
+const EWKB_SRID_FLAG: u32 = 0x2000_0000;
+
+/// Keys that make a closed way an area unless it is tagged `area=no`.
+const AREA_KEYS: &[&str] = &[
+	"aeroway", "amenity", "boundary", "building", "historic", "landuse", "leisure", "man_made",
+	"military", "natural", "place", "shop", "tourism", "water",
+];
+
+impl Osm {
+	/// Replaces the `points`, `lines`, `polygons` and `roads` tables in `config.schema` with
+	/// the contents of this store, bulk-loaded through binary `COPY`, and indexes their
+	/// geometries with GiST.
+	///
+	/// Points are tagged nodes, lines are tagged ways that are not areas, polygons are
+	/// closed area ways and assembled multipolygon and boundary relations, and roads are
+	/// the `highway` lines.
+	pub async fn import_pg(&self, config: &PgImportConfig) -> Result<PgImportSummary> {
+		let schema = identifier(&config.schema)?;
+		let client = connect(&config.connection).await?;
+		tracing::info!("creating postgis tables in schema {}", schema);
+		client.batch_execute(&create_tables_sql(schema)).await.context(TokioPgSnafu)?;
+
+		let mut summary = PgImportSummary::default();
+
+		let mut writer = copy_writer(&client, &format!("{schema}.points"), "osm_id, tags, geom").await?;
+		for node in self.nodes.values().filter(|node| !node.tags.is_empty()) {
+			let Ok(coordinate) = self.get_coordinate_by_node(node) else {
+				continue;
+			};
+			writer
+				.as_mut()
+				.write(&[&node.id.0, &tags_json(&node.tags), &Ewkb::point(coordinate)])
+				.await
+				.context(TokioPgSnafu)?;
+		}
+		summary.points = writer.as_mut().finish().await.context(TokioPgSnafu)?;
+
+		// A connection runs one COPY at a time, so each table gets its own pass
+		let mut lines = copy_writer(&client, &format!("{schema}.lines"), "osm_id, tags, geom").await?;
+		for (way, coordinates) in self.line_ways() {
+			lines
+				.as_mut()
+				.write(&[&way.id.0, &tags_json(&way.tags), &Ewkb::line_string(&coordinates)])
+				.await
+				.context(TokioPgSnafu)?;
+		}
+		summary.lines = lines.as_mut().finish().await.context(TokioPgSnafu)?;
+
+		let mut roads = copy_writer(&client, &format!("{schema}.roads"), "osm_id, highway, name, tags, geom").await?;
+		for (way, coordinates) in self.line_ways() {
+			let Some(highway) = way.tags.get("highway") else {
+				continue;
+			};
+			let name = way.tags.get("name").map(|v| v.as_str());
+			roads
+				.as_mut()
+				.write(&[
+					&way.id.0,
+					&highway.as_str(),
+					&name,
+					&tags_json(&way.tags),
+					&Ewkb::line_string(&coordinates),
+				])
+				.await
+				.context(TokioPgSnafu)?;
+		}
+		summary.roads = roads.as_mut().finish().await.context(TokioPgSnafu)?;
+
+		let mut polygons =
+			copy_writer(&client, &format!("{schema}.polygons"), "osm_id, osm_type, tags, geom").await?;
+		for way in self.ways.values().filter(|way| is_area_way(way)) {
+			let coordinates = self.get_coordinates_by_way(way);
+			if coordinates.len() < 4 {
+				continue;
+			}
+			let polygon = Polygon::new(LineString::from(coordinates), vec![]);
+			polygons
+				.as_mut()
+				.write(&[
+					&way.id.0,
+					&"way",
+					&tags_json(&way.tags),
+					&Ewkb::multi_polygon(&MultiPolygon::new(vec![polygon])),
+				])
+				.await
+				.context(TokioPgSnafu)?;
+		}
+		for relation in self.relations.values() {
+			let kind = relation.tags.get("type").map(|v| v.as_str());
+			if !matches!(kind, Some("multipolygon" | "boundary")) {
+				continue;
+			}
+			let report = self.get_multipolygon_by_relation(relation);
+			if report.polygon.0.is_empty() {
+				tracing::debug!(
+					"skipping relation {} without a valid polygon: {:?}",
+					relation.id.0,
+					report.issues
+				);
+				continue;
+			}
+			polygons
+				.as_mut()
+				.write(&[
+					&relation.id.0,
+					&"relation",
+					&tags_json(&relation.tags),
+					&Ewkb::multi_polygon(&report.polygon),
+				])
+				.await
+				.context(TokioPgSnafu)?;
+		}
+		summary.polygons = polygons.as_mut().finish().await.context(TokioPgSnafu)?;
+
+		tracing::info!("building gist indexes in schema {}", schema);
+		client.batch_execute(&create_indexes_sql(schema)).await.context(TokioPgSnafu)?;
+		tracing::info!(
+			"imported {} points, {} lines, {} polygons and {} roads",
+			summary.points,
+			summary.lines,
+			summary.polygons,
+			summary.roads
+		);
+		Ok(summary)
+	}
+
+	/// Tagged ways that are not areas, with the locations of their nodes.
+	fn line_ways(&self) -> impl Iterator<Item = (&Way, Vec<(f64, f64)>)> + '_ {
+		self.ways
+			.values()
+			.filter(|way| !way.tags.is_empty() && !is_area_way(way))
+			.map(|way| (way, self.get_coordinates_by_way(way)))
+			.filter(|(_, coordinates)| coordinates.len() >= 2)
+	}
+}
+
+/// Connects and drives the connection on the current tokio runtime.
+pub(crate) async fn connect(connection: &str) -> Result<Client> {
+	let (client, connection) = tokio_postgres::connect(connection, NoTls)
+		.await
+		.context(TokioPgSnafu)?;
+	tokio::spawn(async move {
+		if let Err(e) = connection.await {
+			tracing::error!("postgres connection error: {}", e);
+		}
+	});
+	Ok(client)
+}
+
+/// Starts a binary `COPY` into `columns` of `table`, taking the column types from the table itself.
+pub(crate) async fn copy_writer(
+	client: &Client,
+	table: &str,
+	columns: &str,
+) -> Result<Pin<Box<BinaryCopyInWriter>>> {
+	let statement = client
+		.prepare(&format!("SELECT {columns} FROM {table} LIMIT 0"))
+		.await
+		.context(TokioPgSnafu)?;
+	let types = statement
+		.columns()
+		.iter()
+		.map(|column| column.type_().clone())
+		.collect::<Vec<_>>();
+	let sink = client
+		.copy_in(format!("COPY {table} ({columns}) FROM STDIN (FORMAT binary)").as_str())
+		.await
+		.context(TokioPgSnafu)?;
+	Ok(Box::pin(BinaryCopyInWriter::new(sink, &types)))
+}
+
+/// Accepts a plain lower-case SQL identifier, so it can be interpolated without quoting.
+pub(crate) fn identifier(name: &str) -> Result<&str> {
+	let mut chars = name.chars();
+	let valid = chars.next().is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+		&& chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+	if valid {
+		Ok(name)
+	} else {
+		Err(Error::InvalidPgIdentifier { name: name.to_string() })
+	}
+}
+
+pub(crate) fn tags_json(tags: &Tags) -> Value {
+	Value::Object(
+		tags.iter()
+			.map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+			.collect::<Map<_, _>>(),
+	)
+}
+
+fn is_area_way(way: &Way) -> bool {
+	way.is_closed() && is_area(&way.tags)
+}
+
+fn is_area(tags: &Tags) -> bool {
+	match tags.get("area").map(|v| v.as_str()) {
+		Some("yes") => true,
+		Some("no") => false,
+		_ => !tags.contains_key("highway") && AREA_KEYS.iter().any(|key| tags.contains_key(*key)),
+	}
+}
+
+fn create_tables_sql(schema: &str) -> String {
+	format!(
+		"CREATE EXTENSION IF NOT EXISTS postgis;
+		CREATE SCHEMA IF NOT EXISTS {schema};
+		DROP TABLE IF EXISTS {schema}.points, {schema}.lines, {schema}.polygons, {schema}.roads;
+		CREATE TABLE {schema}.points (osm_id bigint NOT NULL, tags jsonb NOT NULL, geom geometry(Point, 4326) NOT NULL);
+		CREATE TABLE {schema}.lines (osm_id bigint NOT NULL, tags jsonb NOT NULL, geom geometry(LineString, 4326) NOT NULL);
+		CREATE TABLE {schema}.polygons (osm_id bigint NOT NULL, osm_type text NOT NULL, tags jsonb NOT NULL, geom geometry(MultiPolygon, 4326) NOT NULL);
+		CREATE TABLE {schema}.roads (osm_id bigint NOT NULL, highway text NOT NULL, name text, tags jsonb NOT NULL, geom geometry(LineString, 4326) NOT NULL);"
+	)
+}
+
+fn create_indexes_sql(schema: &str) -> String {
+	["points", "lines", "polygons", "roads"]
+		.iter()
+		.map(|table| {
+			format!(
+				"CREATE INDEX {table}_geom_idx ON {schema}.{table} USING gist (geom);
+				CREATE INDEX {table}_osm_id_idx ON {schema}.{table} (osm_id);
+				ANALYZE {schema}.{table};"
+			)
+		})
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+impl Ewkb {
+	pub fn point((lon, lat): (f64, f64)) -> Ewkb {
+		let mut ewkb = Ewkb::header(1);
+		ewkb.coordinate((lon, lat));
+		ewkb
+	}
+
+	pub fn line_string(coordinates: &[(f64, f64)]) -> Ewkb {
+		let mut ewkb = Ewkb::header(2);
+		ewkb.coordinates(coordinates.iter().copied());
+		ewkb
+	}
+
+	pub fn multi_polygon(multi_polygon: &MultiPolygon<f64>) -> Ewkb {
+		let mut ewkb = Ewkb::header(6);
+		ewkb.count(multi_polygon.0.len());
+		for polygon in &multi_polygon.0 {
+			// members are plain WKB polygons; the SRID is only given once
+			ewkb.0.push(1);
+			ewkb.0.extend_from_slice(&3u32.to_le_bytes());
+			ewkb.count(1 + polygon.interiors().len());
+			for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+				ewkb.coordinates(ring.0.iter().map(|c| (c.x, c.y)));
+			}
+		}
+		ewkb
+	}
+
+	/// Little-endian byte order, geometry type with the SRID flag, then the SRID.
+	fn header(geometry_type: u32) -> Ewkb {
+		let mut bytes = vec![1];
+		bytes.extend_from_slice(&(geometry_type | EWKB_SRID_FLAG).to_le_bytes());
+		bytes.extend_from_slice(&SRID.to_le_bytes());
+		Ewkb(bytes)
+	}
+
+	fn count(&mut self, count: usize) {
+		self.0.extend_from_slice(&(count as u32).to_le_bytes());
+	}
+
+	fn coordinate(&mut self, (x, y): (f64, f64)) {
+		self.0.extend_from_slice(&x.to_le_bytes());
+		self.0.extend_from_slice(&y.to_le_bytes());
+	}
+
+	fn coordinates<I: ExactSizeIterator<Item = (f64, f64)>>(&mut self, coordinates: I) {
+		self.count(coordinates.len());
+		for coordinate in coordinates {
+			self.coordinate(coordinate);
+		}
+	}
+}
+
+impl ToSql for Ewkb {
+	fn to_sql(
+		&self,
+		_ty: &Type,
+		out: &mut BytesMut,
+	) -> std::result::Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+		out.extend_from_slice(&self.0);
+		Ok(IsNull::No)
+	}
+
+	fn accepts(ty: &Type) -> bool {
+		ty.name() == "geometry"
+	}
+
+	to_sql_checked!();
+}
@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::error::Error as _;
+
+use osmpbfreader::{Node, NodeId, Tags, Way, WayId};
+use serde_json::Value;
+use snafu::ResultExt;
+use tokio_postgres::types::Type;
+use tokio_postgres::Row;
+
+use crate::error::{Error, TokioPgSnafu};
+use crate::model::osm_model::Osm;
+use crate::model::pg_model::PgRoadsConfig;
+use crate::service::pg::connect;
+use crate::utils::Result;
+
+impl Osm {
+	/// Builds a store of untagged nodes and tagged ways from the rows of `config.query`, so the
+	/// usual graph builders can run over it. See `roads_osm` for how lines are connected.
+	///
+	/// Connection failures are retried `config.retries` times before giving up with
+	/// `ReconnectError`; SQL, authentication and configuration errors are returned straight away.
+	pub async fn from_pg_roads(config: &PgRoadsConfig) -> Result<Osm> {
+		let rows = fetch_rows(config).await?;
+		let mut roads = Vec::with_capacity(rows.len());
+		let mut skipped = 0;
+		for row in &rows {
+			let id = row
+				.try_get::<_, i64>("id")
+				.or_else(|_| row.try_get::<_, i32>("id").map(i64::from))
+				.map_err(|_| invalid("the query must return an integer id column".to_string()))?;
+			let wkb: Option<Vec<u8>> = row.try_get("geom_wkb").context(TokioPgSnafu)?;
+			match wkb.as_deref().and_then(|mut wkb| read_lines(&mut wkb)) {
+				Some(lines) => roads.push((id, row_tags(row), lines)),
+				None => skipped += 1,
+			}
+		}
+		Ok(roads_osm(roads, skipped))
+	}
+}
+
+/// Line parts of a road row as `(lon, lat)` coordinates.
+type Lines = Vec<Vec<(f64, f64)>>;
+
+/// Turns `(id, tags, lines)` road rows into ways. A vertex, end or interior, at the same
+/// position to 1e-7° as the end of any line is shared, so lines meeting end to end and
+/// T-junctions, where a line ends on another line's interior vertex, both connect. Interior
+/// vertices that only meet other interior vertices, such as crossings at a bridge, stay apart.
+/// Parts of a MultiLineString after the first get new negative way ids.
+fn roads_osm(roads: Vec<(i64, Tags, Lines)>, mut skipped: usize) -> Osm {
+	let mut lines = Vec::new();
+	for (id, tags, parts) in roads {
+		for part in parts {
+			let mut coordinates = part
+				.into_iter()
+				.map(|(lon, lat)| ((lat * 1e7).round() as i32, (lon * 1e7).round() as i32))
+				.collect::<Vec<_>>();
+			coordinates.dedup();
+			if coordinates.len() < 2 {
+				skipped += 1;
+				continue;
+			}
+			lines.push((id, tags.clone(), coordinates));
+		}
+	}
+
+	let mut shared: HashMap<(i32, i32), Option<NodeId>> = HashMap::new();
+	for (_, _, coordinates) in &lines {
+		shared.insert(coordinates[0], None);
+		shared.insert(coordinates[coordinates.len() - 1], None);
+	}
+
+	let mut osm = Osm::default();
+	let mut extra_way_id = 0;
+	let mut previous_id = None;
+	for (id, tags, coordinates) in lines {
+		let nodes = coordinates
+			.into_iter()
+			.map(|coordinate| match shared.get_mut(&coordinate) {
+				Some(node) => *node.get_or_insert_with(|| new_node(&mut osm, coordinate)),
+				None => new_node(&mut osm, coordinate),
+			})
+			.collect();
+		let way_id = if previous_id == Some(id) {
+			extra_way_id -= 1;
+			extra_way_id
+		} else {
+			id
+		};
+		previous_id = Some(id);
+		osm.add_way(Way {
+			id: WayId(way_id),
+			tags,
+			nodes,
+		});
+	}
+	tracing::info!(
+		"read {} road lines from postgis with {} shared nodes, skipped {}",
+		osm.ways.len(),
+		shared.values().filter(|node| node.is_some()).count(),
+		skipped
+	);
+	osm
+}
+
+fn new_node(osm: &mut Osm, (decimicro_lat, decimicro_lon): (i32, i32)) -> NodeId {
+	let id = NodeId(osm.nodes.len() as i64 + 1);
+	osm.add_node(Node {
+		id,
+		tags: Tags::new(),
+		decimicro_lat,
+		decimicro_lon,
+	});
+	id
+}
+
+/// Runs the query, reconnecting after connection errors until `config.retries` is used up.
+async fn fetch_rows(config: &PgRoadsConfig) -> Result<Vec<Row>> {
+	let mut attempt = 0;
+	loop {
+		match query_roads(config).await {
+			Ok(rows) => return Ok(rows),
+			Err(Error::TokioPgError { source }) if is_transient(&source) => {
+				if attempt == config.retries {
+					tracing::error!("giving up on postgres after {} attempts: {}", attempt + 1, source);
+					return Err(Error::ReconnectError);
+				}
+				attempt += 1;
+				tracing::warn!(
+					"postgres unavailable ({}), retrying in {:?} ({}/{})",
+					source,
+					config.retry_delay,
+					attempt,
+					config.retries
+				);
+				tokio::time::sleep(config.retry_delay).await;
+			}
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+/// Whether the connection dropped or could not be reached, as opposed to a bad connection
+/// string, failed authentication or a SQL error, which retrying cannot fix.
+fn is_transient(error: &tokio_postgres::Error) -> bool {
+	error.is_closed() || error.source().is_some_and(|source| source.is::<std::io::Error>())
+}
+
+async fn query_roads(config: &PgRoadsConfig) -> Result<Vec<Row>> {
+	let client = connect(&config.connection).await?;
+	let query = format!(
+		"SELECT src.*, ST_AsBinary(ST_Force2D(ST_Transform(src.geom, 4326))) AS geom_wkb FROM ({}) AS src",
+		config.query.trim().trim_end_matches(';')
+	);
+	client.query(query.as_str(), &[]).await.context(TokioPgSnafu)
+}
+
+/// A column value that can become a tag.
+enum Column {
+	Text(String),
+	Bool(bool),
+	Json(Value),
+}
+
+/// Every text, number or boolean column other than `id` and the geometry, plus the entries
+/// of a jsonb `tags` column.
+fn row_tags(row: &Row) -> Tags {
+	tags_from_columns(
+		row.columns()
+			.iter()
+			.enumerate()
+			.filter_map(|(i, column)| Some((column.name(), column_value(row, i, column.type_())?))),
+	)
+}
+
+fn column_value(row: &Row, i: usize, ty: &Type) -> Option<Column> {
+	if *ty == Type::JSONB || *ty == Type::JSON {
+		row.try_get::<_, Option<Value>>(i).ok().flatten().map(Column::Json)
+	} else if *ty == Type::TEXT || *ty == Type::VARCHAR || *ty == Type::BPCHAR || *ty == Type::NAME {
+		row.try_get::<_, Option<String>>(i).ok().flatten().map(Column::Text)
+	} else if *ty == Type::INT2 {
+		row.try_get::<_, Option<i16>>(i).ok().flatten().map(|v| Column::Text(v.to_string()))
+	} else if *ty == Type::INT4 {
+		row.try_get::<_, Option<i32>>(i).ok().flatten().map(|v| Column::Text(v.to_string()))
+	} else if *ty == Type::INT8 {
+		row.try_get::<_, Option<i64>>(i).ok().flatten().map(|v| Column::Text(v.to_string()))
+	} else if *ty == Type::FLOAT4 {
+		row.try_get::<_, Option<f32>>(i).ok().flatten().map(|v| Column::Text(v.to_string()))
+	} else if *ty == Type::FLOAT8 {
+		row.try_get::<_, Option<f64>>(i).ok().flatten().map(|v| Column::Text(v.to_string()))
+	} else if *ty == Type::BOOL {
+		row.try_get::<_, Option<bool>>(i).ok().flatten().map(Column::Bool)
+	} else {
+		None
+	}
+}
+
+fn tags_from_columns<'a>(columns: impl IntoIterator<Item = (&'a str, Column)>) -> Tags {
+	let mut tags = Tags::new();
+	for (name, value) in columns {
+		let value = match (name, value) {
+			("id" | "geom" | "geom_wkb", _) => continue,
+			("tags", Column::Json(Value::Object(map))) => {
+				for (key, value) in map {
+					let value = match value {
+						Value::String(s) => s,
+						Value::Null => continue,
+						other => other.to_string(),
+					};
+					tags.insert(key.into(), value.into());
+				}
+				continue;
+			}
+			(_, Column::Json(_)) => continue,
+			(_, Column::Text(value)) => value,
+			(_, Column::Bool(value)) => if value { "yes" } else { "no" }.to_string(),
+		};
+		tags.insert(name.into(), value.into());
+	}
+	tags
+}
+
+/// Lines of a WKB LineString or MultiLineString.
+fn read_lines(wkb: &mut &[u8]) -> Option<Lines> {
+	let little_endian = take(wkb, 1)?[0] == 1;
+	match read_u32(wkb, little_endian)? {
+		2 => {
+			let count = read_u32(wkb, little_endian)?;
+			let points = (0..count)
+				.map(|_| Some((read_f64(wkb, little_endian)?, read_f64(wkb, little_endian)?)))
+				.collect::<Option<Vec<_>>>()?;
+			Some(vec![points])
+		}
+		5 => {
+			let count = read_u32(wkb, little_endian)?;
+			let mut lines = Vec::new();
+			for _ in 0..count {
+				lines.extend(read_lines(wkb)?);
+			}
+			Some(lines)
+		}
+		_ => None,
+	}
+}
+
+fn take<'a>(wkb: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+	if wkb.len() < len {
+		return None;
+	}
+	let (head, rest) = wkb.split_at(len);
+	*wkb = rest;
+	Some(head)
+}
+
+fn read_u32(wkb: &mut &[u8], little_endian: bool) -> Option<u32> {
+	let bytes = take(wkb, 4)?.try_into().ok()?;
+	Some(if little_endian {
+		u32::from_le_bytes(bytes)
+	} else {
+		u32::from_be_bytes(bytes)
+	})
+}
+
+fn read_f64(wkb: &mut &[u8], little_endian: bool) -> Option<f64> {
+	let bytes = take(wkb, 8)?.try_into().ok()?;
+	Some(if little_endian {
+		f64::from_le_bytes(bytes)
+	} else {
+		f64::from_be_bytes(bytes)
+	})
+}
+
+fn invalid(message: String) -> Error {
+	Error::InvalidPgRoads { message }
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::*;
+
+	fn linestring(little_endian: bool, points: &[(f64, f64)]) -> Vec<u8> {
+		let mut wkb = vec![little_endian as u8];
+		let u32_bytes = |v: u32| if little_endian { v.to_le_bytes() } else { v.to_be_bytes() };
+		let f64_bytes = |v: f64| if little_endian { v.to_le_bytes() } else { v.to_be_bytes() };
+		wkb.extend(u32_bytes(2));
+		wkb.extend(u32_bytes(points.len() as u32));
+		for &(x, y) in points {
+			wkb.extend(f64_bytes(x));
+			wkb.extend(f64_bytes(y));
+		}
+		wkb
+	}
+
+	#[test]
+	fn reads_linestrings_and_multilinestrings() {
+		let a = [(100.5, 13.75), (100.6, 13.8)];
+		let b = [(1.0, 2.0), (3.0, 4.0), (5.0, 6.0)];
+		assert_eq!(read_lines(&mut linestring(true, &a).as_slice()), Some(vec![a.to_vec()]));
+		assert_eq!(read_lines(&mut linestring(false, &b).as_slice()), Some(vec![b.to_vec()]));
+
+		let mut multi = vec![1];
+		multi.extend(5u32.to_le_bytes());
+		multi.extend(2u32.to_le_bytes());
+		multi.extend(linestring(true, &a));
+		multi.extend(linestring(false, &b));
+		assert_eq!(read_lines(&mut multi.as_slice()), Some(vec![a.to_vec(), b.to_vec()]));
+
+		let truncated = linestring(true, &a);
+		assert_eq!(read_lines(&mut &truncated[..truncated.len() - 1]), None);
+		let mut point = vec![1];
+		point.extend(1u32.to_le_bytes());
+		point.extend(1.0f64.to_le_bytes());
+		point.extend(2.0f64.to_le_bytes());
+		assert_eq!(read_lines(&mut point.as_slice()), None);
+		assert_eq!(read_lines(&mut [].as_slice()), None);
+	}
+
+	#[test]
+	fn turns_columns_into_tags() {
+		let json = serde_json::json!({ "surface": "asphalt", "lanes": 2, "note": null });
+		let tags = tags_from_columns([
+			("id", Column::Text("7".to_string())),
+			("geom", Column::Text("ignored".to_string())),
+			("highway", Column::Text("primary".to_string())),
+			("oneway", Column::Bool(true)),
+			("toll", Column::Bool(false)),
+			("maxspeed", Column::Text("50".to_string())),
+			("tags", Column::Json(json)),
+			("extra", Column::Json(serde_json::json!({ "a": "b" }))),
+		]);
+		let mut tags = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect::<Vec<_>>();
+		tags.sort();
+		assert_eq!(
+			tags,
+			vec![
+				("highway", "primary"),
+				("lanes", "2"),
+				("maxspeed", "50"),
+				("oneway", "yes"),
+				("surface", "asphalt"),
+				("toll", "no"),
+			]
+		);
+	}
+
+	#[test]
+	fn connects_shared_ends_and_t_junctions_only() {
+		let road = |id: i64, lines: Lines| (id, Tags::new(), lines);
+		let osm = roads_osm(
+			vec![
+				// 1 and 2 meet end to end, 3 ends on the interior vertex of 1
+				road(1, vec![vec![(0.0, 0.0), (0.001, 0.0), (0.002, 0.0)]]),
+				road(2, vec![vec![(0.002, 0.0), (0.003, 0.0)]]),
+				road(3, vec![vec![(0.001, 0.001), (0.001, 0.0)]]),
+				// 4 and 5 cross at a shared interior vertex, like a bridge
+				road(4, vec![vec![(1.0, 0.0), (1.0, 0.001), (1.0, 0.002)]]),
+				road(
+					5,
+					vec![
+						vec![(0.999, 0.001), (1.0, 0.001), (1.001, 0.001)],
+						vec![(2.0, 0.0), (2.0, 0.0)],
+						vec![(1.001, 0.001), (1.002, 0.001)],
+					],
+				),
+			],
+			0,
+		);
+		let way = |id: i64| &osm.ways[&id].nodes;
+
+		assert_eq!(osm.ways.len(), 6);
+		assert_eq!(way(1)[2], way(2)[0]);
+		assert_eq!(way(1)[1], way(3)[1]);
+		assert_ne!(way(4)[1], way(5)[1]);
+		// The degenerate part is dropped and the third part continues the second
+		assert_eq!(way(5)[2], way(-1)[0]);
+		assert_eq!(osm.nodes.len(), 3 + 1 + 1 + 3 + 3 + 1);
+		let node = &osm.nodes[&way(3)[0].0];
+		assert_eq!((node.decimicro_lat, node.decimicro_lon), (10_000, 10_000));
+	}
+
+	#[tokio::test]
+	async fn retries_only_connection_failures() {
+		let config = |connection: &str| PgRoadsConfig {
+			connection: connection.to_string(),
+			query: "SELECT 1".to_string(),
+			retries: 2,
+			retry_delay: Duration::from_millis(1),
+		};
+		// Nothing listens on port 1, so every attempt is refused
+		assert!(matches!(
+			fetch_rows(&config("host=127.0.0.1 port=1 connect_timeout=1")).await,
+			Err(Error::ReconnectError)
+		));
+		assert!(matches!(
+			fetch_rows(&config("host=127.0.0.1 port=not-a-port")).await,
+			Err(Error::TokioPgError { .. })
+		));
+	}
+}
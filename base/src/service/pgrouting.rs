@@ -0,0 +1,264 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use snafu::ResultExt;
+
+use crate::error::{FileWriteSnafu, TokioPgSnafu};
+use crate::model::pg_model::{Ewkb, PgImportConfig, PgRouting, PgRoutingEdge, PgRoutingVertex};
+use crate::service::pg::{connect, copy_writer, identifier};
+use crate::utils::Result;
+
+const WAYS_COLUMNS: &str = "gid, osm_id, source, target, cost, reverse_cost, length_m, x1, y1, x2, y2, the_geom";
+const VERTICES_COLUMNS: &str = "id, osm_id, lon, lat, the_geom";
+
+impl PgRouting {
+	/// Replaces `ways` and `ways_vertices_pgr` in `config.schema` through binary `COPY`.
+	pub async fn load_pg(&self, config: &PgImportConfig) -> Result<()> {
+		let schema = identifier(&config.schema)?;
+		let client = connect(&config.connection).await?;
+		client.batch_execute(&create_tables_sql(schema)).await.context(TokioPgSnafu)?;
+
+		let mut writer = copy_writer(&client, &format!("{schema}.ways_vertices_pgr"), VERTICES_COLUMNS).await?;
+		for vertex in &self.vertices {
+			writer
+				.as_mut()
+				.write(&[
+					&vertex.id,
+					&vertex.osm_id,
+					&vertex.lon,
+					&vertex.lat,
+					&Ewkb::point((vertex.lon, vertex.lat)),
+				])
+				.await
+				.context(TokioPgSnafu)?;
+		}
+		writer.as_mut().finish().await.context(TokioPgSnafu)?;
+
+		let mut writer = copy_writer(&client, &format!("{schema}.ways"), WAYS_COLUMNS).await?;
+		for edge in &self.edges {
+			writer
+				.as_mut()
+				.write(&[
+					&edge.gid,
+					&edge.osm_id,
+					&edge.source,
+					&edge.target,
+					&edge.cost,
+					&edge.reverse_cost,
+					&edge.length_m,
+					&edge.x1,
+					&edge.y1,
+					&edge.x2,
+					&edge.y2,
+					&Ewkb::line_string(&[(edge.x1, edge.y1), (edge.x2, edge.y2)]),
+				])
+				.await
+				.context(TokioPgSnafu)?;
+		}
+		writer.as_mut().finish().await.context(TokioPgSnafu)?;
+
+		client.batch_execute(&create_indexes_sql(schema)).await.context(TokioPgSnafu)?;
+		tracing::info!(
+			"loaded {} ways and {} vertices into {}.ways",
+			self.edges.len(),
+			self.vertices.len(),
+			schema
+		);
+		Ok(())
+	}
+
+	/// Writes a SQL script that recreates both tables in `schema` when run through `psql`.
+	pub fn write_sql(&self, path: &Path, schema: &str) -> Result<()> {
+		let schema = identifier(schema)?;
+		write_file(path, |out| {
+			writeln!(out, "BEGIN;")?;
+			writeln!(out, "{}", create_tables_sql(schema))?;
+			writeln!(out, "COPY {schema}.ways_vertices_pgr ({VERTICES_COLUMNS}) FROM stdin;")?;
+			for vertex in &self.vertices {
+				writeln!(out, "{}", vertex_fields(vertex).join("\t"))?;
+			}
+			writeln!(out, "\\.")?;
+			writeln!(out, "COPY {schema}.ways ({WAYS_COLUMNS}) FROM stdin;")?;
+			for edge in &self.edges {
+				writeln!(out, "{}", edge_fields(edge).join("\t"))?;
+			}
+			writeln!(out, "\\.")?;
+			writeln!(out, "{}", create_indexes_sql(schema))?;
+			writeln!(out, "COMMIT;")
+		})
+	}
+
+	/// Writes `ways.csv` and `ways_vertices_pgr.csv` with header rows into `dir`, geometries
+	/// as EWKT, ready for `\copy ... WITH (FORMAT csv, HEADER)`.
+	pub fn write_csv(&self, dir: &Path) -> Result<()> {
+		std::fs::create_dir_all(dir).context(FileWriteSnafu { path: dir })?;
+		write_file(&dir.join("ways_vertices_pgr.csv"), |out| {
+			writeln!(out, "{}", VERTICES_COLUMNS.replace(' ', ""))?;
+			for vertex in &self.vertices {
+				writeln!(out, "{}", csv_row(vertex_fields(vertex)))?;
+			}
+			Ok(())
+		})?;
+		write_file(&dir.join("ways.csv"), |out| {
+			writeln!(out, "{}", WAYS_COLUMNS.replace(' ', ""))?;
+			for edge in &self.edges {
+				writeln!(out, "{}", csv_row(edge_fields(edge)))?;
+			}
+			Ok(())
+		})
+	}
+}
+
+fn vertex_fields(vertex: &PgRoutingVertex) -> Vec<String> {
+	vec![
+		vertex.id.to_string(),
+		vertex.osm_id.to_string(),
+		vertex.lon.to_string(),
+		vertex.lat.to_string(),
+		format!("SRID=4326;POINT({} {})", vertex.lon, vertex.lat),
+	]
+}
+
+fn edge_fields(edge: &PgRoutingEdge) -> Vec<String> {
+	vec![
+		edge.gid.to_string(),
+		edge.osm_id.to_string(),
+		edge.source.to_string(),
+		edge.target.to_string(),
+		edge.cost.to_string(),
+		edge.reverse_cost.to_string(),
+		edge.length_m.to_string(),
+		edge.x1.to_string(),
+		edge.y1.to_string(),
+		edge.x2.to_string(),
+		edge.y2.to_string(),
+		format!(
+			"SRID=4326;LINESTRING({} {},{} {})",
+			edge.x1, edge.y1, edge.x2, edge.y2
+		),
+	]
+}
+
+/// Joins fields with commas, quoting those that contain a comma or quote, such as the
+/// EWKT `LINESTRING(x1 y1,x2 y2)`.
+fn csv_row(fields: Vec<String>) -> String {
+	fields
+		.into_iter()
+		.map(|field| {
+			if field.contains([',', '"', '\n']) {
+				format!("\"{}\"", field.replace('"', "\"\""))
+			} else {
+				field
+			}
+		})
+		.collect::<Vec<_>>()
+		.join(",")
+}
+
+fn write_file<F>(path: &Path, write: F) -> Result<()>
+where
+	F: FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+{
+	let file = File::create(path).context(FileWriteSnafu { path })?;
+	let mut out = BufWriter::new(file);
+	write(&mut out)
+		.and_then(|_| out.flush())
+		.context(FileWriteSnafu { path })
+}
+
+fn create_tables_sql(schema: &str) -> String {
+	format!(
+		"CREATE EXTENSION IF NOT EXISTS postgis;
+		CREATE SCHEMA IF NOT EXISTS {schema};
+		DROP TABLE IF EXISTS {schema}.ways, {schema}.ways_vertices_pgr;
+		CREATE TABLE {schema}.ways_vertices_pgr (id bigint NOT NULL, osm_id bigint, lon double precision, lat double precision, the_geom geometry(Point, 4326));
+		CREATE TABLE {schema}.ways (gid bigint NOT NULL, osm_id bigint, source bigint NOT NULL, target bigint NOT NULL, cost double precision NOT NULL, reverse_cost double precision NOT NULL, length_m double precision, x1 double precision, y1 double precision, x2 double precision, y2 double precision, the_geom geometry(LineString, 4326));"
+	)
+}
+
+fn create_indexes_sql(schema: &str) -> String {
+	format!(
+		"ALTER TABLE {schema}.ways_vertices_pgr ADD PRIMARY KEY (id);
+		ALTER TABLE {schema}.ways ADD PRIMARY KEY (gid);
+		CREATE INDEX ways_source_idx ON {schema}.ways (source);
+		CREATE INDEX ways_target_idx ON {schema}.ways (target);
+		CREATE INDEX ways_the_geom_idx ON {schema}.ways USING gist (the_geom);
+		CREATE INDEX ways_vertices_pgr_the_geom_idx ON {schema}.ways_vertices_pgr USING gist (the_geom);
+		ANALYZE {schema}.ways;
+		ANALYZE {schema}.ways_vertices_pgr;"
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Splits one CSV line, honouring double-quoted fields.
+	fn parse_csv_line(line: &str) -> Vec<String> {
+		let mut fields = vec![String::new()];
+		let mut quoted = false;
+		let mut chars = line.chars().peekable();
+		while let Some(c) = chars.next() {
+			match c {
+				'"' if quoted && chars.peek() == Some(&'"') => {
+					chars.next();
+					fields.last_mut().unwrap().push('"');
+				}
+				'"' => quoted = !quoted,
+				',' if !quoted => fields.push(String::new()),
+				c => fields.last_mut().unwrap().push(c),
+			}
+		}
+		fields
+	}
+
+	#[test]
+	fn csv_rows_match_header() {
+		let tables = PgRouting {
+			vertices: vec![
+				PgRoutingVertex {
+					id: 1,
+					osm_id: 10,
+					lon: 100.5,
+					lat: 13.75,
+				},
+				PgRoutingVertex {
+					id: 2,
+					osm_id: 11,
+					lon: 100.51,
+					lat: 13.76,
+				},
+			],
+			edges: vec![PgRoutingEdge {
+				gid: 1,
+				osm_id: 100,
+				source: 1,
+				target: 2,
+				cost: 12.5,
+				reverse_cost: -1.0,
+				length_m: 12.5,
+				x1: 100.5,
+				y1: 13.75,
+				x2: 100.51,
+				y2: 13.76,
+			}],
+		};
+		let dir = std::env::temp_dir().join(format!("pgrouting_csv_{}", std::process::id()));
+		tables.write_csv(&dir).unwrap();
+
+		for (file, rows) in [("ways.csv", 1), ("ways_vertices_pgr.csv", 2)] {
+			let text = std::fs::read_to_string(dir.join(file)).unwrap();
+			let lines = text.lines().map(parse_csv_line).collect::<Vec<_>>();
+			assert_eq!(lines.len(), rows + 1, "{file}");
+			for line in &lines[1..] {
+				assert_eq!(line.len(), lines[0].len(), "{file}: {line:?}");
+			}
+		}
+		let ways = std::fs::read_to_string(dir.join("ways.csv")).unwrap();
+		let row = parse_csv_line(ways.lines().nth(1).unwrap());
+		assert_eq!(row[11], "SRID=4326;LINESTRING(100.5 13.75,100.51 13.76)");
+		assert_eq!(row[5], "-1");
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
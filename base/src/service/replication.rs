@@ -0,0 +1,158 @@
+use std::io::{BufReader, Cursor, ErrorKind};
+
+use flate2::read::GzDecoder;
+use snafu::ResultExt;
+
+use crate::error::{Error, ExtSvcRequestSnafu, FileReadSnafu, FileWriteSnafu};
+use crate::model::osm_change_model::{ChangeSummary, OsmChange};
+use crate::model::osm_model::Osm;
+use crate::model::replication_model::{ReplicationClient, ReplicationConfig, ReplicationState};
+use crate::utils::Result;
+
+impl ReplicationState {
+	/// Parses the Java properties format of `state.txt`, ignoring keys other than
+	/// `sequenceNumber` and `timestamp`.
+	pub fn parse(text: &str) -> Result<ReplicationState> {
+		let mut sequence = None;
+		let mut timestamp = None;
+		for line in text.lines().map(str::trim) {
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let Some((key, value)) = line.split_once('=') else {
+				continue;
+			};
+			match key.trim() {
+				"sequenceNumber" => {
+					sequence = Some(value.trim().parse().map_err(|_| {
+						invalid_state(format!("invalid sequenceNumber {:?}", value))
+					})?)
+				}
+				"timestamp" => timestamp = Some(value.trim().replace('\\', "")),
+				_ => {}
+			}
+		}
+		let sequence = sequence.ok_or_else(|| invalid_state("missing sequenceNumber".to_string()))?;
+		Ok(ReplicationState { sequence, timestamp })
+	}
+
+	pub fn to_state_txt(&self) -> String {
+		let mut text = format!("sequenceNumber={}\n", self.sequence);
+		if let Some(timestamp) = &self.timestamp {
+			text.push_str(&format!("timestamp={}\n", timestamp.replace(':', "\\:")));
+		}
+		text
+	}
+}
+
+impl ReplicationClient {
+	pub fn new(config: ReplicationConfig) -> Self {
+		ReplicationClient {
+			config,
+			client: reqwest::Client::new(),
+		}
+	}
+
+	/// Latest state published by the feed.
+	pub async fn remote_state(&self) -> Result<ReplicationState> {
+		let text = self.get(&format!("{}/state.txt", self.base_url())).await?;
+		ReplicationState::parse(&String::from_utf8_lossy(&text))
+	}
+
+	/// Last applied state, or `None` when nothing has been recorded yet.
+	pub fn local_state(&self) -> Result<Option<ReplicationState>> {
+		let path = &self.config.state_path;
+		match std::fs::read_to_string(path) {
+			Ok(text) => ReplicationState::parse(&text).map(Some),
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(e).context(FileReadSnafu { path }),
+		}
+	}
+
+	/// Records `state` as applied; written to a temporary file first so a crash never
+	/// leaves a truncated state behind.
+	pub fn save_state(&self, state: &ReplicationState) -> Result<()> {
+		let path = &self.config.state_path;
+		let tmp = path.with_extension("tmp");
+		std::fs::write(&tmp, state.to_state_txt()).context(FileWriteSnafu { path: &tmp })?;
+		std::fs::rename(&tmp, path).context(FileWriteSnafu { path })
+	}
+
+	/// URL of the diff for `sequence`, e.g. `4_123_456` → `<base>/004/123/456.osc.gz`.
+	pub fn diff_url(&self, sequence: u64) -> String {
+		format!(
+			"{}/{:03}/{:03}/{:03}.osc.gz",
+			self.base_url(),
+			sequence / 1_000_000,
+			sequence / 1_000 % 1_000,
+			sequence % 1_000
+		)
+	}
+
+	pub async fn fetch_change(&self, sequence: u64) -> Result<OsmChange> {
+		let bytes = self.get(&self.diff_url(sequence)).await?;
+		OsmChange::from_reader(BufReader::new(GzDecoder::new(Cursor::new(bytes))))
+	}
+
+	/// Downloads and applies every diff after the locally recorded sequence up to the
+	/// feed's current state, in order. `on_applied` runs after each diff, e.g. to update a
+	/// routing graph. Returns the state reached, or `None` when already up to date.
+	///
+	/// The local state is not advanced here: `osm` only holds the changes in memory, so the
+	/// caller must persist it first, e.g. with `Osm::write_pbf`, and then record the
+	/// returned state with `save_state`. Otherwise the next run would reload the old data
+	/// and skip the diffs in between.
+	///
+	/// The local state file must be seeded with the sequence the loaded data corresponds
+	/// to; this client does not guess it.
+	pub async fn update<F>(&self, osm: &mut Osm, mut on_applied: F) -> Result<Option<ReplicationState>>
+	where
+		F: FnMut(u64, &Osm, &ChangeSummary),
+	{
+		let local = self.local_state()?.ok_or_else(|| {
+			invalid_state(format!(
+				"no local state at {:?}; write the sequenceNumber of the loaded data there first",
+				self.config.state_path
+			))
+		})?;
+		let remote = self.remote_state().await?;
+		if remote.sequence <= local.sequence {
+			tracing::info!("replication is up to date at sequence {}", local.sequence);
+			return Ok(None);
+		}
+
+		tracing::info!(
+			"applying replication diffs {} to {}",
+			local.sequence + 1,
+			remote.sequence
+		);
+		for sequence in local.sequence + 1..=remote.sequence {
+			let change = self.fetch_change(sequence).await?;
+			let summary = osm.apply_change(&change)?;
+			on_applied(sequence, osm, &summary);
+			tracing::debug!("applied replication diff {}", sequence);
+		}
+		Ok(Some(remote))
+	}
+
+	fn base_url(&self) -> &str {
+		self.config.base_url.trim_end_matches('/')
+	}
+
+	async fn get(&self, url: &str) -> Result<Vec<u8>> {
+		tracing::debug!("fetching {}", url);
+		let response = self
+			.client
+			.get(url)
+			.send()
+			.await
+			.and_then(|response| response.error_for_status())
+			.context(ExtSvcRequestSnafu)?;
+		let bytes = response.bytes().await.context(ExtSvcRequestSnafu)?;
+		Ok(bytes.to_vec())
+	}
+}
+
+fn invalid_state(message: String) -> Error {
+	Error::InvalidReplicationState { message }
+}
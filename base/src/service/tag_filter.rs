@@ -0,0 +1,330 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use osmpbfreader::{OsmId, OsmObj};
+use snafu::ResultExt;
+
+use crate::error::{Error, FileReadSnafu};
+use crate::model::osm_file_model::{FilteredOsmFile, OsmFile, OsmSource};
+use crate::model::tag_filter_model::{Condition, FilterExpression, TagFilter, ValuePattern};
+use crate::utils::Result;
+
+impl TagFilter {
+	/// Parses whitespace-separated expressions of the form `[types/]key[=values]`:
+	///
+	/// - `types` is any combination of `n`, `w` and `r`, all three when omitted
+	/// - `key` selects objects with the key, `!key` objects without it
+	/// - `key=a,b` selects listed values, `key!=a,b` any other value of the key
+	/// - values may start and/or end with `*` as a wildcard, and `*` alone matches any value
+	pub fn parse(text: &str) -> Result<TagFilter> {
+		let expressions = text
+			.split_whitespace()
+			.map(parse_expression)
+			.collect::<Result<Vec<_>>>()?;
+		if expressions.is_empty() {
+			return Err(invalid("no expressions".to_string()));
+		}
+		Ok(TagFilter { expressions })
+	}
+
+	/// Reads expressions from a file, one or more per line, with `#` starting a comment.
+	pub fn from_file<P: AsRef<Path>>(path: P) -> Result<TagFilter> {
+		let path = path.as_ref();
+		let text = std::fs::read_to_string(path).context(FileReadSnafu { path })?;
+		let expressions = text
+			.lines()
+			.map(|line| line.split('#').next().unwrap_or_default())
+			.collect::<Vec<_>>()
+			.join(" ");
+		TagFilter::parse(&expressions)
+	}
+
+	/// Whether any expression for the object's type matches its tags.
+	pub fn matches(&self, obj: &OsmObj) -> bool {
+		let tags = obj.tags();
+		self.expressions.iter().any(|expression| {
+			let applies = match obj {
+				OsmObj::Node(_) => expression.nodes,
+				OsmObj::Way(_) => expression.ways,
+				OsmObj::Relation(_) => expression.relations,
+			};
+			applies
+				&& expression
+					.condition
+					.matches(tags.get(expression.key.as_str()).map(|v| v.as_str()))
+		})
+	}
+}
+
+impl Condition {
+	fn matches(&self, value: Option<&str>) -> bool {
+		match self {
+			Condition::Present => value.is_some(),
+			Condition::Absent => value.is_none(),
+			Condition::In(patterns) => value.is_some_and(|v| patterns.iter().any(|p| p.matches(v))),
+			Condition::NotIn(patterns) => value.is_some_and(|v| !patterns.iter().any(|p| p.matches(v))),
+		}
+	}
+}
+
+impl ValuePattern {
+	fn parse(text: &str) -> ValuePattern {
+		match (text.strip_prefix('*'), text.strip_suffix('*')) {
+			_ if text == "*" => ValuePattern::Any,
+			(Some(rest), Some(_)) => ValuePattern::Contains(rest[..rest.len() - 1].to_string()),
+			(Some(suffix), None) => ValuePattern::Suffix(suffix.to_string()),
+			(None, Some(prefix)) => ValuePattern::Prefix(prefix.to_string()),
+			(None, None) => ValuePattern::Exact(text.to_string()),
+		}
+	}
+
+	fn matches(&self, value: &str) -> bool {
+		match self {
+			ValuePattern::Any => true,
+			ValuePattern::Exact(exact) => value == exact,
+			ValuePattern::Prefix(prefix) => value.starts_with(prefix.as_str()),
+			ValuePattern::Suffix(suffix) => value.ends_with(suffix.as_str()),
+			ValuePattern::Contains(part) => value.contains(part.as_str()),
+		}
+	}
+}
+
+fn parse_expression(text: &str) -> Result<FilterExpression> {
+	let (types, rest) = match text.split_once('/') {
+		Some((types, rest)) => (types, rest),
+		None => ("nwr", text),
+	};
+	if types.is_empty() || !types.chars().all(|c| matches!(c, 'n' | 'w' | 'r')) {
+		return Err(invalid(format!("{:?} has an invalid type prefix, use n, w and/or r", text)));
+	}
+
+	let (key, condition) = if let Some(key) = rest.strip_prefix('!') {
+		(key, Condition::Absent)
+	} else if let Some((key, values)) = rest.split_once("!=") {
+		(key, Condition::NotIn(parse_values(text, values)?))
+	} else if let Some((key, values)) = rest.split_once('=') {
+		(key, Condition::In(parse_values(text, values)?))
+	} else {
+		(rest, Condition::Present)
+	};
+	if key.is_empty() || key.contains(['=', '!']) {
+		return Err(invalid(format!("{:?} has no valid key", text)));
+	}
+	Ok(FilterExpression {
+		nodes: types.contains('n'),
+		ways: types.contains('w'),
+		relations: types.contains('r'),
+		key: key.to_string(),
+		condition,
+	})
+}
+
+fn parse_values(text: &str, values: &str) -> Result<Vec<ValuePattern>> {
+	if values.is_empty() {
+		return Err(invalid(format!("{:?} has no values after '='", text)));
+	}
+	Ok(values.split(',').map(ValuePattern::parse).collect())
+}
+
+impl OsmSource for FilteredOsmFile {
+	/// Reads the file three times: relations first, to learn which ways and nodes they
+	/// reference, then ways, to learn their nodes, and finally everything selected.
+	fn for_each_obj(&mut self, f: &mut dyn FnMut(OsmObj) -> Result<()>) -> Result<()> {
+		let filter = &self.filter;
+
+		let mut members: HashMap<i64, Vec<OsmId>> = HashMap::new();
+		let mut relations = HashSet::new();
+		OsmFile::open(&self.path)?.for_each_obj(&mut |obj| {
+			if let OsmObj::Relation(relation) = &obj {
+				if filter.matches(&obj) {
+					relations.insert(relation.id.0);
+				}
+				members.insert(relation.id.0, relation.refs.iter().map(|r| r.member).collect());
+			}
+			Ok(())
+		})?;
+
+		let mut ways = HashSet::new();
+		let mut nodes = HashSet::new();
+		let mut pending = relations.iter().copied().collect::<Vec<_>>();
+		while let Some(id) = pending.pop() {
+			for member in members.get(&id).into_iter().flatten() {
+				match *member {
+					OsmId::Node(node) => {
+						nodes.insert(node.0);
+					}
+					OsmId::Way(way) => {
+						ways.insert(way.0);
+					}
+					OsmId::Relation(relation) => {
+						if relations.insert(relation.0) {
+							pending.push(relation.0);
+						}
+					}
+				}
+			}
+		}
+		drop(members);
+
+		OsmFile::open(&self.path)?.for_each_obj(&mut |obj| {
+			if let OsmObj::Way(way) = &obj {
+				if ways.contains(&way.id.0) || filter.matches(&obj) {
+					ways.insert(way.id.0);
+					nodes.extend(way.nodes.iter().map(|id| id.0));
+				}
+			}
+			Ok(())
+		})?;
+		tracing::debug!(
+			"tag filter selected {} relations, {} ways and {} referenced nodes",
+			relations.len(),
+			ways.len(),
+			nodes.len()
+		);
+
+		OsmFile::open(&self.path)?.for_each_obj(&mut |obj| {
+			let keep = match &obj {
+				OsmObj::Node(node) => nodes.contains(&node.id.0) || filter.matches(&obj),
+				OsmObj::Way(way) => ways.contains(&way.id.0),
+				OsmObj::Relation(relation) => relations.contains(&relation.id.0),
+			};
+			if keep {
+				f(obj)
+			} else {
+				Ok(())
+			}
+		})
+	}
+}
+
+fn invalid(message: String) -> Error {
+	Error::InvalidTagFilter { message }
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeSet;
+
+	use osmpbfreader::{Node, NodeId, Tags, Way, WayId};
+
+	use super::*;
+
+	fn tags(pairs: &[(&str, &str)]) -> Tags {
+		let mut tags = Tags::new();
+		for (key, value) in pairs {
+			tags.insert((*key).into(), (*value).into());
+		}
+		tags
+	}
+
+	fn node(pairs: &[(&str, &str)]) -> OsmObj {
+		OsmObj::Node(Node {
+			id: NodeId(1),
+			tags: tags(pairs),
+			decimicro_lat: 0,
+			decimicro_lon: 0,
+		})
+	}
+
+	fn way(pairs: &[(&str, &str)]) -> OsmObj {
+		OsmObj::Way(Way {
+			id: WayId(1),
+			tags: tags(pairs),
+			nodes: vec![],
+		})
+	}
+
+	#[test]
+	fn type_prefix_limits_expression() {
+		let filter = TagFilter::parse("w/highway=primary,secondary").unwrap();
+		assert!(filter.matches(&way(&[("highway", "secondary")])));
+		assert!(!filter.matches(&way(&[("highway", "tertiary")])));
+		assert!(!filter.matches(&node(&[("highway", "secondary")])));
+	}
+
+	#[test]
+	fn negation() {
+		let filter = TagFilter::parse("!name").unwrap();
+		assert!(filter.matches(&node(&[])));
+		assert!(filter.matches(&way(&[("highway", "primary")])));
+		assert!(!filter.matches(&way(&[("name", "Sukhumvit")])));
+	}
+
+	#[test]
+	fn not_in_requires_key() {
+		let filter = TagFilter::parse("w/highway!=motorway,trunk").unwrap();
+		assert!(filter.matches(&way(&[("highway", "primary")])));
+		assert!(!filter.matches(&way(&[("highway", "trunk")])));
+		assert!(!filter.matches(&way(&[])));
+	}
+
+	#[test]
+	fn wildcards() {
+		let matches = |expression: &str, value: &str| {
+			TagFilter::parse(expression).unwrap().matches(&way(&[("name", value)]))
+		};
+		assert!(matches("name=Soi*", "Soi Ari"));
+		assert!(!matches("name=Soi*", "Thanon Soi"));
+		assert!(matches("name=*Road", "Rama IV Road"));
+		assert!(!matches("name=*Road", "Road 1"));
+		assert!(matches("name=*khumv*", "Sukhumvit"));
+		assert!(!matches("name=*khumv*", "Silom"));
+		assert!(matches("name=*", "anything"));
+		assert!(!TagFilter::parse("name=*").unwrap().matches(&way(&[])));
+	}
+
+	#[test]
+	fn rejects_invalid_expressions() {
+		for expression in ["", "x/highway", "/highway", "=primary", "highway=", "w/!"] {
+			assert!(TagFilter::parse(expression).is_err(), "{expression:?} should not parse");
+		}
+	}
+
+	#[test]
+	fn pulls_in_referenced_objects() {
+		let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="13.70" lon="100.50"><tag k="amenity" v="cafe"/></node>
+  <node id="2" lat="13.71" lon="100.50"/>
+  <node id="3" lat="13.72" lon="100.50"/>
+  <node id="4" lat="13.73" lon="100.50"/>
+  <node id="6" lat="13.74" lon="100.50"/>
+  <node id="7" lat="13.75" lon="100.50"/>
+  <node id="8" lat="13.75" lon="100.51"/>
+  <node id="9" lat="13.76" lon="100.51"/>
+  <node id="5000" lat="13.77" lon="100.50"><tag k="amenity" v="atm"/></node>
+  <way id="10"><nd ref="2"/><nd ref="3"/><tag k="highway" v="primary"/></way>
+  <way id="11"><nd ref="3"/><nd ref="4"/><tag k="highway" v="footway"/></way>
+  <way id="12"><nd ref="7"/><nd ref="8"/><nd ref="9"/><nd ref="7"/></way>
+  <way id="20"><nd ref="4"/><nd ref="6"/></way>
+  <relation id="7">
+    <member type="way" ref="12" role="outer"/>
+    <member type="relation" ref="8" role=""/>
+    <tag k="type" v="multipolygon"/>
+  </relation>
+  <relation id="8"><member type="node" ref="6" role=""/><tag k="type" v="collection"/></relation>
+  <relation id="5000"><member type="way" ref="20" role=""/><tag k="type" v="route"/></relation>
+</osm>"#;
+		let path = std::env::temp_dir().join(format!("tag_filter_{}.osm", std::process::id()));
+		std::fs::write(&path, xml).unwrap();
+		let mut file = FilteredOsmFile {
+			path: path.clone(),
+			filter: TagFilter::parse("w/highway=primary n/amenity r/type=multipolygon").unwrap(),
+		};
+		let mut kept = BTreeSet::new();
+		file.for_each_obj(&mut |obj| {
+			kept.insert(obj.id());
+			Ok(())
+		})
+		.unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		let expected = [1, 2, 3, 6, 7, 8, 9, 5000]
+			.map(|id| OsmId::Node(NodeId(id)))
+			.into_iter()
+			.chain([10, 12].map(|id| OsmId::Way(WayId(id))))
+			.chain([7, 8].map(|id| OsmId::Relation(osmpbfreader::RelationId(id))))
+			.collect::<BTreeSet<_>>();
+		assert_eq!(kept, expected);
+	}
+}
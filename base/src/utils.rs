@@ -1,15 +1,29 @@
-use crate::error::Error;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind};
+use std::path::Path;
+
+use crate::error::{Error, FileNotFoundSnafu, FileReadSnafu};
+use crate::model::pbf_model::PbfSource;
+use snafu::IntoError;
 use time::OffsetDateTime;
 use tracing;
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+// open an input file, telling a missing file apart from other read errors
+pub fn open_file(path: &Path) -> Result<File> {
+    File::open(path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => FileNotFoundSnafu { path }.into_error(e),
+        _ => FileReadSnafu { path }.into_error(e),
+    })
+}
+
 // create function to read pbf file
-pub fn read_pbf_file(filename: &str) -> osmpbfreader::OsmPbfReader<std::fs::File> {
-    let path = std::path::Path::new(filename);
-    tracing::debug!("start file target : {:?}", filename);
-    let r = std::fs::File::open(path).unwrap();
-    osmpbfreader::OsmPbfReader::new(r)
+pub fn read_pbf_file<P: AsRef<Path>>(filename: P) -> Result<PbfSource<BufReader<File>>> {
+    let path = filename.as_ref();
+    tracing::debug!("start file target : {:?}", path);
+    let r = open_file(path)?;
+    Ok(PbfSource::new(BufReader::new(r)))
 }
 
 pub fn time_diff_trace(text: &str, from: OffsetDateTime, to: OffsetDateTime) {
@@ -0,0 +1,105 @@
+//! Imports a small fixture into a throwaway PostGIS database, e.g.
+//!
+//! ```sh
+//! docker run --rm -d -e POSTGRES_PASSWORD=osm -p 5432:5432 postgis/postgis
+//! OPEN_RUST_MAP_TEST_PG="host=localhost user=postgres password=osm" cargo test -p base --test pg_import
+//! ```
+//!
+//! Skipped when `OPEN_RUST_MAP_TEST_PG` is unset.
+
+use base::model::osm_model::Osm;
+use base::model::pg_model::PgImportConfig;
+use osmpbfreader::{Node, NodeId, OsmId, Ref, Relation, RelationId, Tags, Way, WayId};
+use tokio_postgres::NoTls;
+
+const SCHEMA: &str = "open_rust_map_test_import";
+
+fn tags(pairs: &[(&str, &str)]) -> Tags {
+	let mut tags = Tags::new();
+	for (key, value) in pairs {
+		tags.insert((*key).into(), (*value).into());
+	}
+	tags
+}
+
+fn node(osm: &mut Osm, id: i64, lon: f64, lat: f64, pairs: &[(&str, &str)]) {
+	osm.add_node(Node {
+		id: NodeId(id),
+		tags: tags(pairs),
+		decimicro_lat: (lat * 1e7) as i32,
+		decimicro_lon: (lon * 1e7) as i32,
+	});
+}
+
+fn way(osm: &mut Osm, id: i64, nodes: &[i64], pairs: &[(&str, &str)]) {
+	osm.add_way(Way {
+		id: WayId(id),
+		tags: tags(pairs),
+		nodes: nodes.iter().map(|&id| NodeId(id)).collect(),
+	});
+}
+
+/// Two tagged nodes, a road, a building, an untagged way and a multipolygon relation.
+fn fixture() -> Osm {
+	let mut osm = Osm::default();
+	node(&mut osm, 1, 100.50, 13.75, &[("amenity", "cafe")]);
+	node(&mut osm, 2, 100.51, 13.75, &[("amenity", "atm")]);
+	node(&mut osm, 3, 100.52, 13.75, &[]);
+	for (id, lon, lat) in [(10, 100.60, 13.70), (11, 100.61, 13.70), (12, 100.61, 13.71), (13, 100.60, 13.71)] {
+		node(&mut osm, id, lon, lat, &[]);
+	}
+	for (id, lon, lat) in [(20, 100.70, 13.70), (21, 100.71, 13.70), (22, 100.71, 13.71), (23, 100.70, 13.71)] {
+		node(&mut osm, id, lon, lat, &[]);
+	}
+	way(&mut osm, 100, &[1, 2, 3], &[("highway", "residential"), ("name", "Soi 1")]);
+	way(&mut osm, 101, &[10, 11, 12, 13, 10], &[("building", "yes")]);
+	way(&mut osm, 102, &[20, 21, 22, 23, 20], &[]);
+	osm.add_relation(Relation {
+		id: RelationId(1000),
+		tags: tags(&[("type", "multipolygon"), ("natural", "water")]),
+		refs: vec![Ref {
+			member: OsmId::Way(WayId(102)),
+			role: "outer".into(),
+		}],
+	});
+	osm
+}
+
+#[tokio::test]
+async fn imports_fixture_with_gist_indexes() {
+	let Ok(connection) = std::env::var("OPEN_RUST_MAP_TEST_PG") else {
+		eprintln!("OPEN_RUST_MAP_TEST_PG is not set, skipping");
+		return;
+	};
+	let config = PgImportConfig {
+		connection: connection.clone(),
+		schema: SCHEMA.to_string(),
+	};
+	let summary = fixture().import_pg(&config).await.expect("import succeeds");
+	assert_eq!(
+		(summary.points, summary.lines, summary.roads, summary.polygons),
+		(2, 1, 1, 2)
+	);
+
+	let (client, driver) = tokio_postgres::connect(&connection, NoTls).await.unwrap();
+	tokio::spawn(driver);
+	for (table, expected) in [("points", 2i64), ("lines", 1), ("roads", 1), ("polygons", 2)] {
+		let row = client
+			.query_one(&format!("SELECT count(*) FROM {SCHEMA}.{table}"), &[])
+			.await
+			.unwrap();
+		assert_eq!(row.get::<_, i64>(0), expected, "rows in {table}");
+	}
+	let row = client
+		.query_one(
+			"SELECT count(*) FROM pg_indexes WHERE schemaname = $1 AND indexdef LIKE '%USING gist (geom)%'",
+			&[&SCHEMA],
+		)
+		.await
+		.unwrap();
+	assert_eq!(row.get::<_, i64>(0), 4);
+	client
+		.batch_execute(&format!("DROP SCHEMA {SCHEMA} CASCADE"))
+		.await
+		.unwrap();
+}
@@ -0,0 +1,108 @@
+//! Follows a replication feed served by a local static file server standing in for
+//! planet.openstreetmap.org.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+
+use base::model::osm_model::Osm;
+use base::model::replication_model::{ReplicationClient, ReplicationConfig, ReplicationState};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use osmpbfreader::{Node, NodeId, Tags};
+
+/// Serves `files` by path over HTTP/1.1 on an ephemeral port, returning the base URL.
+fn serve(files: HashMap<String, Vec<u8>>) -> String {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}", listener.local_addr().unwrap());
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			let mut stream = stream.unwrap();
+			let mut request = Vec::new();
+			let mut buf = [0; 1024];
+			while !request.ends_with(b"\r\n\r\n") {
+				let n = stream.read(&mut buf).unwrap();
+				if n == 0 {
+					break;
+				}
+				request.extend_from_slice(&buf[..n]);
+			}
+			let request = String::from_utf8_lossy(&request);
+			let path = request.split_whitespace().nth(1).unwrap_or("/");
+			let (status, body) = match files.get(path) {
+				Some(body) => ("200 OK", body.clone()),
+				None => ("404 Not Found", Vec::new()),
+			};
+			let header = format!(
+				"HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+				body.len()
+			);
+			stream.write_all(header.as_bytes()).unwrap();
+			stream.write_all(&body).unwrap();
+		}
+	});
+	url
+}
+
+fn gzip(text: &str) -> Vec<u8> {
+	let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+	encoder.write_all(text.as_bytes()).unwrap();
+	encoder.finish().unwrap()
+}
+
+#[tokio::test]
+async fn applies_missing_diffs_in_order() {
+	let mut files = HashMap::new();
+	files.insert(
+		"/replication/state.txt".to_string(),
+		b"#Sat Jan 01 00:00:00 UTC 2026\nsequenceNumber=2\ntimestamp=2026-01-01T00\\:00\\:00Z\n".to_vec(),
+	);
+	files.insert(
+		"/replication/000/000/001.osc.gz".to_string(),
+		gzip(r#"<osmChange version="0.6"><create><node id="2" version="1" lat="13.8" lon="100.6"/></create></osmChange>"#),
+	);
+	files.insert(
+		"/replication/000/000/002.osc.gz".to_string(),
+		gzip(r#"<osmChange version="0.6"><modify><node id="2" version="2" lat="13.9" lon="100.7"/></modify><delete><node id="1" version="2"/></delete></osmChange>"#),
+	);
+	let base_url = format!("{}/replication/", serve(files));
+
+	let state_path = std::env::temp_dir().join(format!("replication_state_{}.txt", std::process::id()));
+	let client = ReplicationClient::new(ReplicationConfig {
+		base_url,
+		state_path: state_path.clone(),
+	});
+	client
+		.save_state(&ReplicationState {
+			sequence: 0,
+			timestamp: None,
+		})
+		.unwrap();
+
+	let mut osm = Osm::default();
+	osm.add_node(Node {
+		id: NodeId(1),
+		tags: Tags::new(),
+		decimicro_lat: 137_000_000,
+		decimicro_lon: 1_005_000_000,
+	});
+	let mut applied = Vec::new();
+	let state = client
+		.update(&mut osm, |sequence, _, _| applied.push(sequence))
+		.await
+		.unwrap()
+		.expect("the feed is ahead of the local state");
+
+	assert_eq!(applied, vec![1, 2]);
+	assert_eq!(state.sequence, 2);
+	assert_eq!(state.timestamp.as_deref(), Some("2026-01-01T00:00:00Z"));
+	assert!(osm.get_node_by_id(1).is_none());
+	assert_eq!(osm.get_coordinate_by_node_id(2), Some((100.7, 13.9)));
+	// Nothing is recorded until the caller has persisted the data
+	assert_eq!(client.local_state().unwrap().unwrap().sequence, 0);
+
+	client.save_state(&state).unwrap();
+	assert_eq!(client.local_state().unwrap(), Some(state));
+	assert!(client.update(&mut osm, |_, _, _| {}).await.unwrap().is_none());
+	std::fs::remove_file(&state_path).unwrap();
+}
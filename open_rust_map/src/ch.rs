@@ -0,0 +1,714 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use tracing::{debug, info, instrument};
+
+use crate::{Graph, Metric};
+
+/// Nodes settled per witness search before a shortcut is added conservatively.
+const WITNESS_SETTLE_LIMIT: usize = 500;
+
+#[derive(Debug, Clone, Copy)]
+enum Arc {
+    /// An arc of the contracted graph, travelling along this graph edge
+    Original(EdgeIndex),
+    /// A shortcut bypassing the contracted middle node
+    Shortcut(u32),
+}
+
+/// Dijkstra queue entry ordered by smallest cost first.
+#[derive(Debug, Clone, Copy)]
+struct Candidate(f64, u32);
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0)
+    }
+}
+
+/// One hierarchy arc in flat form, as persisted in prepared graph files.
+#[derive(Debug, Clone, Copy)]
+pub struct ArcRecord {
+    pub from: u32,
+    pub to: u32,
+    pub weight: f64,
+    /// Whether the arc belongs to the upward (forward) search graph
+    pub upward: bool,
+    /// Graph edge index travelled, or the contracted middle node for shortcuts
+    pub original: Option<u32>,
+    pub middle: Option<u32>,
+}
+
+/// Contraction hierarchy over a routing [`Graph`] for one metric.
+///
+/// Without turn restrictions, hierarchy nodes are the `NodeIndex` values of the graph it was
+/// built from. With them, the turn-expanded graph of `Graph::turn_states` is contracted
+/// instead, so hierarchy nodes are edges with their via-way progress and only allowed turns
+/// connect them.
+#[derive(Debug)]
+pub struct ContractionHierarchy {
+    /// Upward arcs, searched from the start node
+    forward: Vec<Vec<(u32, f64)>>,
+    /// Downward arcs stored reversed, searched from the end node
+    backward: Vec<Vec<(u32, f64)>>,
+    arcs: HashMap<(u32, u32), (f64, Arc)>,
+    /// Graph edge of each turn-expanded hierarchy node; empty for node-based hierarchies
+    state_edges: Vec<u32>,
+    /// Turn-expanded hierarchy nodes beyond the first `edge_count`, by graph edge
+    extra_states: HashMap<u32, Vec<u32>>,
+}
+
+/// Uncontracted remainder of the graph during preprocessing.
+struct Overlay {
+    outgoing: Vec<HashMap<u32, f64>>,
+    incoming: Vec<HashMap<u32, f64>>,
+    arcs: HashMap<(u32, u32), (f64, Arc)>,
+    /// Shortcut count of each node, dropped when a neighbour is contracted
+    shortcut_counts: Vec<Option<i64>>,
+}
+
+impl Overlay {
+    fn insert_arc(&mut self, from: u32, to: u32, weight: f64, arc: Arc) {
+        if self.arcs.get(&(from, to)).is_some_and(|&(w, _)| w <= weight) {
+            return;
+        }
+        self.arcs.insert((from, to), (weight, arc));
+        self.outgoing[from as usize].insert(to, weight);
+        self.incoming[to as usize].insert(from, weight);
+    }
+
+    /// Local Dijkstra from `source` that avoids `skip`, settling nodes up to `max_cost`.
+    fn witness_search(&self, source: u32, skip: u32, max_cost: f64) -> HashMap<u32, f64> {
+        let mut dist = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        dist.insert(source, 0.0);
+        queue.push(Candidate(0.0, source));
+        let mut settled = 0;
+        while let Some(Candidate(cost, node)) = queue.pop() {
+            if cost > max_cost || settled >= WITNESS_SETTLE_LIMIT {
+                break;
+            }
+            if dist.get(&node).is_some_and(|&best| cost > best) {
+                continue;
+            }
+            settled += 1;
+            for (&next, &weight) in &self.outgoing[node as usize] {
+                if next == skip {
+                    continue;
+                }
+                let next_cost = cost + weight;
+                if dist.get(&next).is_none_or(|&best| next_cost < best) {
+                    dist.insert(next, next_cost);
+                    queue.push(Candidate(next_cost, next));
+                }
+            }
+        }
+        dist
+    }
+
+    /// Shortcuts `(from, to, weight)` needed to preserve shortest paths when `node` is removed.
+    fn shortcuts(&self, node: u32) -> Vec<(u32, u32, f64)> {
+        let outgoing = &self.outgoing[node as usize];
+        let max_out = outgoing.values().copied().fold(0.0, f64::max);
+        let mut shortcuts = Vec::new();
+        for (&from, &in_weight) in &self.incoming[node as usize] {
+            let witnesses = self.witness_search(from, node, in_weight + max_out);
+            for (&to, &out_weight) in outgoing {
+                if to == from {
+                    continue;
+                }
+                let via = in_weight + out_weight;
+                if witnesses.get(&to).is_none_or(|&d| d > via) {
+                    shortcuts.push((from, to, via));
+                }
+            }
+        }
+        shortcuts
+    }
+
+    /// Edge difference plus contracted-neighbour count; lower is contracted first.
+    fn priority(&mut self, node: u32, deleted_neighbours: &[i64]) -> i64 {
+        let removed = self.outgoing[node as usize].len() + self.incoming[node as usize].len();
+        let shortcuts = match self.shortcut_counts[node as usize] {
+            Some(count) => count,
+            None => {
+                let count = self.shortcuts(node).len() as i64;
+                self.shortcut_counts[node as usize] = Some(count);
+                count
+            }
+        };
+        shortcuts - removed as i64 + deleted_neighbours[node as usize]
+    }
+}
+
+impl ContractionHierarchy {
+    /// Contracts `graph` for `metric`, over the turn-expanded graph when it has turn restrictions.
+    #[instrument(skip(graph))]
+    pub fn build(graph: &Graph, metric: Metric) -> Self {
+        if graph.restrictions.is_empty() {
+            let arcs = graph
+                .graph
+                .edge_references()
+                .map(|edge| {
+                    let (from, to) = (edge.source().index() as u32, edge.target().index() as u32);
+                    (from, to, edge.weight().weight(metric), edge.id())
+                })
+                .collect::<Vec<_>>();
+            return Self::contract(graph.graph.node_count(), arcs, Vec::new());
+        }
+
+        let (state_edges, turns) = graph.turn_states();
+        info!(
+            "Expanded {} edges into {} turn states with {} allowed turns",
+            graph.graph.edge_count(),
+            state_edges.len(),
+            turns.len()
+        );
+        let arcs = turns
+            .into_iter()
+            .map(|(from, to)| {
+                // A turn costs the edge it turns onto
+                let edge = state_edges[to as usize];
+                (from, to, graph.graph[edge].weight(metric), edge)
+            })
+            .collect::<Vec<_>>();
+        let state_edges = state_edges.into_iter().map(|edge| edge.index() as u32).collect::<Vec<_>>();
+        Self::contract(state_edges.len(), arcs, state_edges)
+    }
+
+    /// Contracts `n` nodes joined by `(from, to, weight, graph edge)` arcs.
+    fn contract(n: usize, arcs: Vec<(u32, u32, f64, EdgeIndex)>, state_edges: Vec<u32>) -> Self {
+        info!("Contracting {} nodes", n);
+        let original_arcs = arcs.len();
+        let mut overlay = Overlay {
+            outgoing: vec![HashMap::new(); n],
+            incoming: vec![HashMap::new(); n],
+            arcs: HashMap::new(),
+            shortcut_counts: vec![None; n],
+        };
+        for (from, to, weight, edge) in arcs {
+            if from != to {
+                overlay.insert_arc(from, to, weight, Arc::Original(edge));
+            }
+        }
+
+        let mut deleted_neighbours = vec![0i64; n];
+        let mut contracted = vec![false; n];
+        let mut rank = vec![0usize; n];
+        let mut queue = (0..n as u32)
+            .map(|node| Reverse((overlay.priority(node, &deleted_neighbours), node)))
+            .collect::<BinaryHeap<_>>();
+
+        let mut order = 0;
+        while let Some(Reverse((priority, node))) = queue.pop() {
+            if contracted[node as usize] {
+                continue;
+            }
+            // Lazy update: requeue if the priority went stale since it was pushed. Shortcut
+            // counts are only recomputed for neighbours of contracted nodes; witness searches
+            // further away may change too, which only affects the order, not correctness.
+            let current = overlay.priority(node, &deleted_neighbours);
+            if current > priority {
+                queue.push(Reverse((current, node)));
+                continue;
+            }
+
+            for (from, to, weight) in overlay.shortcuts(node) {
+                overlay.insert_arc(from, to, weight, Arc::Shortcut(node));
+            }
+            let outgoing = std::mem::take(&mut overlay.outgoing[node as usize]);
+            let incoming = std::mem::take(&mut overlay.incoming[node as usize]);
+            for &to in outgoing.keys() {
+                overlay.incoming[to as usize].remove(&node);
+                overlay.shortcut_counts[to as usize] = None;
+                deleted_neighbours[to as usize] += 1;
+            }
+            for &from in incoming.keys() {
+                overlay.outgoing[from as usize].remove(&node);
+                overlay.shortcut_counts[from as usize] = None;
+                deleted_neighbours[from as usize] += 1;
+            }
+
+            contracted[node as usize] = true;
+            rank[node as usize] = order;
+            order += 1;
+            if order % 100_000 == 0 {
+                debug!("Contracted {} of {} nodes", order, n);
+            }
+        }
+
+        let mut forward = vec![Vec::new(); n];
+        let mut backward = vec![Vec::new(); n];
+        for (&(from, to), &(weight, _)) in &overlay.arcs {
+            if rank[to as usize] > rank[from as usize] {
+                forward[from as usize].push((to, weight));
+            } else {
+                backward[to as usize].push((from, weight));
+            }
+        }
+        info!(
+            "Contraction hierarchy built with {} arcs for {} original arcs",
+            overlay.arcs.len(),
+            original_arcs
+        );
+
+        ContractionHierarchy {
+            forward,
+            backward,
+            arcs: overlay.arcs,
+            extra_states: extra_states(&state_edges),
+            state_edges,
+        }
+    }
+
+    /// Whether the hierarchy was built over the turn-expanded graph.
+    pub fn is_turn_aware(&self) -> bool {
+        !self.state_edges.is_empty()
+    }
+
+    /// Graph edge of each turn-expanded hierarchy node, as persisted in prepared graph files.
+    pub fn state_edges(&self) -> &[u32] {
+        &self.state_edges
+    }
+
+    pub fn arc_records(&self) -> Vec<ArcRecord> {
+        let upward = self.forward.iter().enumerate().flat_map(|(from, arcs)| {
+            arcs.iter().map(move |&(to, weight)| (from as u32, to, weight, true))
+        });
+        let downward = self.backward.iter().enumerate().flat_map(|(to, arcs)| {
+            arcs.iter().map(move |&(from, weight)| (from, to as u32, weight, false))
+        });
+        upward
+            .chain(downward)
+            .map(|(from, to, weight, upward)| {
+                let (original, middle) = match self.arcs.get(&(from, to)) {
+                    Some(&(_, Arc::Original(edge))) => (Some(edge.index() as u32), None),
+                    Some(&(_, Arc::Shortcut(middle))) => (None, Some(middle)),
+                    None => (None, None),
+                };
+                ArcRecord {
+                    from,
+                    to,
+                    weight,
+                    upward,
+                    original,
+                    middle,
+                }
+            })
+            .collect()
+    }
+
+    /// Rebuilds a hierarchy from its arcs and, for turn-expanded ones, the edge of each state.
+    pub fn from_arc_records(
+        node_count: usize,
+        records: impl IntoIterator<Item = ArcRecord>,
+        state_edges: Vec<u32>,
+    ) -> Self {
+        let node_count = if state_edges.is_empty() { node_count } else { state_edges.len() };
+        let mut forward = vec![Vec::new(); node_count];
+        let mut backward = vec![Vec::new(); node_count];
+        let mut arcs = HashMap::new();
+        for record in records {
+            let arc = match (record.original, record.middle) {
+                (Some(edge), _) => Arc::Original(EdgeIndex::new(edge as usize)),
+                (None, Some(middle)) => Arc::Shortcut(middle),
+                (None, None) => continue,
+            };
+            if record.upward {
+                forward[record.from as usize].push((record.to, record.weight));
+            } else {
+                backward[record.to as usize].push((record.from, record.weight));
+            }
+            arcs.insert((record.from, record.to), (record.weight, arc));
+        }
+        ContractionHierarchy {
+            forward,
+            backward,
+            arcs,
+            extra_states: extra_states(&state_edges),
+            state_edges,
+        }
+    }
+
+    /// Bidirectional upward Dijkstra; returns the same shape as `Graph::find_shortest_path`.
+    #[instrument(skip(self, graph))]
+    pub fn find_shortest_path(
+        &self,
+        graph: &Graph,
+        start: NodeIndex,
+        end: NodeIndex,
+        metric: Metric,
+    ) -> Option<(Vec<NodeIndex>, f64)> {
+        if !self.is_turn_aware() {
+            let (hierarchy_path, cost) = self.search(&[(start.index() as u32, 0.0)], &[end.index() as u32])?;
+            let mut path = vec![start];
+            path.extend(
+                self.unpack_path(&hierarchy_path)
+                    .into_iter()
+                    .filter_map(|edge| graph.graph.edge_endpoints(edge).map(|(_, target)| target)),
+            );
+            return Some((path, cost));
+        }
+        if start == end {
+            return Some((vec![start], 0.0));
+        }
+
+        // Leave `start` on any of its edges, paying for that edge, and arrive at `end` in any
+        // state of an edge into it
+        let sources = graph
+            .graph
+            .edges_directed(start, Direction::Outgoing)
+            .map(|edge| (edge.id().index() as u32, edge.weight().weight(metric)))
+            .collect::<Vec<_>>();
+        let mut targets = Vec::new();
+        for edge in graph.graph.edges_directed(end, Direction::Incoming) {
+            let id = edge.id().index() as u32;
+            targets.push(id);
+            targets.extend(self.extra_states.get(&id).into_iter().flatten());
+        }
+        let (hierarchy_path, cost) = self.search(&sources, &targets)?;
+        let first = EdgeIndex::new(self.state_edges[hierarchy_path[0] as usize] as usize);
+        let mut path = vec![start];
+        path.extend(
+            std::iter::once(first)
+                .chain(self.unpack_path(&hierarchy_path))
+                .filter_map(|edge| graph.graph.edge_endpoints(edge).map(|(_, target)| target)),
+        );
+        Some((path, cost))
+    }
+
+    /// Route between two snapped edges; returns the same shape as
+    /// `Graph::find_restricted_path_between`.
+    pub fn find_path_between(
+        &self,
+        graph: &Graph,
+        exit: EdgeIndex,
+        entry: EdgeIndex,
+        metric: Metric,
+    ) -> Option<(Vec<NodeIndex>, f64)> {
+        let (_, exit_node) = graph.graph.edge_endpoints(exit)?;
+        if !self.is_turn_aware() {
+            let (entry_node, _) = graph.graph.edge_endpoints(entry)?;
+            return self.find_shortest_path(graph, exit_node, entry_node, metric);
+        }
+        if exit == entry {
+            // Going round to the same edge needs at least one turn, which a search meeting
+            // in the middle cannot tell apart from not moving; both points are on one edge
+            return graph.find_restricted_path_between(exit, entry, metric);
+        }
+
+        let entry_id = entry.index() as u32;
+        let mut targets = vec![entry_id];
+        targets.extend(self.extra_states.get(&entry_id).into_iter().flatten());
+        let (hierarchy_path, cost) = self.search(&[(exit.index() as u32, 0.0)], &targets)?;
+        let edges = self.unpack_path(&hierarchy_path);
+        // The last edge is `entry` itself, which the caller adds in part
+        let mut path = vec![exit_node];
+        path.extend(
+            edges[..edges.len().saturating_sub(1)]
+                .iter()
+                .filter_map(|&edge| graph.graph.edge_endpoints(edge).map(|(_, target)| target)),
+        );
+        Some((path, cost - graph.graph[entry].weight(metric)))
+    }
+
+    /// Bidirectional upward Dijkstra from any of `sources`, each with its starting cost, to any
+    /// of `targets`, returning the hierarchy nodes passed and the cost.
+    fn search(&self, sources: &[(u32, f64)], targets: &[u32]) -> Option<(Vec<u32>, f64)> {
+        let mut dist = [HashMap::new(), HashMap::new()];
+        let mut parents: [HashMap<u32, u32>; 2] = [HashMap::new(), HashMap::new()];
+        let mut queues = [BinaryHeap::new(), BinaryHeap::new()];
+        let arcs = [&self.forward, &self.backward];
+
+        let targets = targets.iter().map(|&target| (target, 0.0)).collect::<Vec<_>>();
+        for (side, seeds) in [sources, &targets].into_iter().enumerate() {
+            for &(seed, cost) in seeds {
+                if (seed as usize) < arcs[side].len() && dist[side].get(&seed).is_none_or(|&d| cost < d) {
+                    dist[side].insert(seed, cost);
+                    queues[side].push(Candidate(cost, seed));
+                }
+            }
+        }
+
+        let mut best = f64::INFINITY;
+        let mut meeting = None;
+        for &(seed, cost) in sources {
+            if dist[1].contains_key(&seed) && cost < best {
+                best = cost;
+                meeting = Some(seed);
+            }
+        }
+
+        loop {
+            let tops = [
+                queues[0].peek().map_or(f64::INFINITY, |c| c.0),
+                queues[1].peek().map_or(f64::INFINITY, |c| c.0),
+            ];
+            if tops[0].min(tops[1]) >= best {
+                break;
+            }
+            let side = if tops[0] <= tops[1] { 0 } else { 1 };
+            let Candidate(cost, node) = queues[side].pop()?;
+            if dist[side].get(&node).is_some_and(|&d| cost > d) {
+                continue;
+            }
+            if let Some(&other) = dist[1 - side].get(&node) {
+                if cost + other < best {
+                    best = cost + other;
+                    meeting = Some(node);
+                }
+            }
+            for &(next, weight) in &arcs[side][node as usize] {
+                let next_cost = cost + weight;
+                if dist[side].get(&next).is_none_or(|&d| next_cost < d) {
+                    dist[side].insert(next, next_cost);
+                    parents[side].insert(next, node);
+                    queues[side].push(Candidate(next_cost, next));
+                }
+            }
+        }
+
+        let meeting = meeting?;
+        let mut hierarchy_path = vec![meeting];
+        let mut node = meeting;
+        while let Some(&parent) = parents[0].get(&node) {
+            hierarchy_path.push(parent);
+            node = parent;
+        }
+        hierarchy_path.reverse();
+        node = meeting;
+        while let Some(&parent) = parents[1].get(&node) {
+            hierarchy_path.push(parent);
+            node = parent;
+        }
+
+        Some((hierarchy_path, best))
+    }
+
+    /// Graph edges along a path of hierarchy nodes, in travel order.
+    fn unpack_path(&self, hierarchy_path: &[u32]) -> Vec<EdgeIndex> {
+        let mut edges = Vec::new();
+        for pair in hierarchy_path.windows(2) {
+            self.unpack(pair[0], pair[1], &mut edges);
+        }
+        edges
+    }
+
+    /// Expands an arc of the hierarchy back into original graph edges, in travel order.
+    fn unpack(&self, from: u32, to: u32, edges: &mut Vec<EdgeIndex>) {
+        let mut stack = vec![(from, to)];
+        while let Some((from, to)) = stack.pop() {
+            match self.arcs.get(&(from, to)) {
+                Some(&(_, Arc::Original(edge))) => edges.push(edge),
+                Some(&(_, Arc::Shortcut(middle))) => {
+                    // Pushed in reverse so the first half is unpacked first
+                    stack.push((middle, to));
+                    stack.push((from, middle));
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// Turn-expanded hierarchy nodes beyond the first one of each edge, grouped by edge.
+fn extra_states(state_edges: &[u32]) -> HashMap<u32, Vec<u32>> {
+    let mut extra: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (id, &edge) in state_edges.iter().enumerate() {
+        // State `i` of the first `edge_count` is edge `i` itself
+        if edge as usize != id {
+            extra.entry(edge).or_default().push(id as u32);
+        }
+    }
+    extra
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::{NodeId, OsmId, WayId};
+
+    use super::*;
+    use crate::profile::Profile;
+    use crate::test_support::Fixture;
+
+    /// 4x4 grid of mixed road classes, with one-way rows and a one-way shortcut diagonal.
+    fn grid() -> Fixture {
+        let mut fixture = Fixture::default();
+        for row in 0..4 {
+            for col in 0..4 {
+                fixture.node(row * 4 + col + 1, 100.0 + col as f64 * 0.001, 13.0 + row as f64 * 0.001);
+            }
+        }
+        for row in 0..4 {
+            let nodes = (0..4).map(|col| row * 4 + col + 1).collect::<Vec<_>>();
+            let highway = if row % 2 == 0 { "primary" } else { "residential" };
+            let oneway = if row == 1 { "yes" } else if row == 2 { "-1" } else { "no" };
+            fixture.way(row + 1, &nodes, &[("highway", highway), ("oneway", oneway)]);
+        }
+        for col in 0..4 {
+            let nodes = (0..4).map(|row| row * 4 + col + 1).collect::<Vec<_>>();
+            let highway = if col == 3 { "secondary" } else { "tertiary" };
+            fixture.way(col + 11, &nodes, &[("highway", highway)]);
+        }
+        fixture.way(21, &[1, 6, 11, 16], &[("highway", "service"), ("oneway", "yes")]);
+        fixture
+    }
+
+    fn assert_close(actual: f64, expected: f64, context: &dyn std::fmt::Debug) {
+        assert!((actual - expected).abs() < 1e-6, "{:?}: {} vs {}", context, actual, expected);
+    }
+
+    /// Checks every pair of nodes against `Graph::find_shortest_path`, which runs the
+    /// restricted search when the graph has turn restrictions.
+    fn assert_matches_astar(graph: &Graph, turn_aware: bool) {
+        for metric in [Metric::Distance, Metric::Duration] {
+            let hierarchy = ContractionHierarchy::build(graph, metric);
+            assert_eq!(hierarchy.is_turn_aware(), turn_aware);
+            for start in graph.graph.node_indices() {
+                for end in graph.graph.node_indices() {
+                    if turn_aware && start == end {
+                        // The restricted search always leaves the start node
+                        continue;
+                    }
+                    let expected = graph.find_shortest_path(start, end, metric);
+                    let actual = hierarchy.find_shortest_path(graph, start, end, metric);
+                    let (Some((_, expected)), Some((path, cost))) = (expected, &actual) else {
+                        assert!(actual.is_none(), "{:?} -> {:?}", start, end);
+                        continue;
+                    };
+                    assert_close(*cost, expected, &(start, end));
+                    assert_eq!((path[0], path[path.len() - 1]), (start, end));
+                    let walked = path
+                        .windows(2)
+                        .map(|pair| {
+                            graph
+                                .graph
+                                .edges_connecting(pair[0], pair[1])
+                                .map(|edge| edge.weight().weight(metric))
+                                .fold(f64::INFINITY, f64::min)
+                        })
+                        .sum::<f64>();
+                    assert_close(walked, expected, &(start, end));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_astar_on_every_pair() {
+        let graph = grid().graph(Profile::Car);
+        assert!(graph.restrictions.is_empty());
+        assert_matches_astar(&graph, false);
+    }
+
+    #[test]
+    fn matches_restricted_astar_on_every_pair() {
+        let mut fixture = grid();
+        fixture
+            .relation(
+                1,
+                &[
+                    (OsmId::Way(WayId(1)), "from"),
+                    (OsmId::Node(NodeId(2)), "via"),
+                    (OsmId::Way(WayId(12)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "no_left_turn")],
+            )
+            .relation(
+                2,
+                &[
+                    (OsmId::Way(WayId(11)), "from"),
+                    (OsmId::Node(NodeId(5)), "via"),
+                    (OsmId::Way(WayId(2)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "only_right_turn")],
+            )
+            .relation(
+                3,
+                &[
+                    (OsmId::Way(WayId(3)), "from"),
+                    (OsmId::Way(WayId(13)), "via"),
+                    (OsmId::Way(WayId(4)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "no_left_turn")],
+            );
+        let graph = fixture.graph(Profile::Car);
+        assert!(!graph.restrictions.is_empty());
+        assert_matches_astar(&graph, true);
+    }
+
+    #[test]
+    fn matches_restricted_astar_on_every_pair_of_edges() {
+        let mut fixture = grid();
+        fixture
+            .relation(
+                1,
+                &[
+                    (OsmId::Way(WayId(1)), "from"),
+                    (OsmId::Node(NodeId(2)), "via"),
+                    (OsmId::Way(WayId(12)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "no_left_turn")],
+            )
+            .relation(
+                2,
+                &[
+                    (OsmId::Way(WayId(11)), "from"),
+                    (OsmId::Node(NodeId(5)), "via"),
+                    (OsmId::Way(WayId(2)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "only_right_turn")],
+            )
+            .relation(
+                3,
+                &[
+                    (OsmId::Way(WayId(3)), "from"),
+                    (OsmId::Way(WayId(13)), "via"),
+                    (OsmId::Way(WayId(4)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "no_left_turn")],
+            );
+        let graph = fixture.graph(Profile::Car);
+        assert!(!graph.restrictions.is_empty());
+
+        for metric in [Metric::Distance, Metric::Duration] {
+            let hierarchy = ContractionHierarchy::build(&graph, metric);
+            assert!(hierarchy.is_turn_aware());
+            let mut routes = 0;
+            for exit in graph.graph.edge_indices() {
+                for entry in graph.graph.edge_indices() {
+                    let expected = graph.find_restricted_path_between(exit, entry, metric);
+                    let actual = hierarchy.find_path_between(&graph, exit, entry, metric);
+                    match (expected, actual) {
+                        (Some((expected_path, expected)), Some((path, cost))) => {
+                            assert_close(cost, expected, &(exit, entry));
+                            assert_eq!(path.first(), expected_path.first(), "{:?}", (exit, entry));
+                            assert_eq!(path.last(), expected_path.last(), "{:?}", (exit, entry));
+                            routes += 1;
+                        }
+                        (None, None) => {}
+                        (expected, actual) => panic!("{:?}: {:?} vs {:?}", (exit, entry), expected, actual),
+                    }
+                }
+            }
+            assert!(routes > graph.graph.edge_count());
+        }
+    }
+}
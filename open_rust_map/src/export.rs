@@ -0,0 +1,429 @@
+//! Versioned JSON export of a routing [`Graph`].
+//!
+//! The file is a single JSON object:
+//!
+//! ```json
+//! {
+//!   "format": "open_rust_map/graph",
+//!   "version": 2,
+//!   "profile": "car",
+//!   "nodes": [
+//!     { "id": 123, "lat": 13.7563, "lon": 100.5018, "tags": { "highway": "traffic_signals" } }
+//!   ],
+//!   "edges": [
+//!     { "source": 123, "target": 456, "distance": 42.7, "duration": 3.1,
+//!       "way_id": 789, "highway_type": "primary" }
+//!   ],
+//!   "restrictions": [
+//!     { "from": 789, "to": 790, "kind": "no", "via_node": 456 },
+//!     { "from": 790, "to": 792, "kind": "only", "via_ways": [791] }
+//!   ]
+//! }
+//! ```
+//!
+//! - `id`, `source`, `target` and `way_id` are OSM ids; `source`/`target` refer to node `id`s.
+//! - Edges are directed: a two-way street appears once per direction.
+//! - `distance` is in metres and `duration` in seconds for the exported profile. Floats are
+//!   parsed exactly, so an imported graph routes identically to the exported one.
+//! - Only the node tags listed in [`EXPORTED_NODE_TAGS`] are written.
+//! - `restrictions` holds the profile's turn restrictions by OSM way and node id, each with
+//!   either a `via_node` or the ordered `via_ways`. Version 1 files have none.
+//! - The spatial index is not part of the file; it is rebuilt on import.
+//!
+//! Readers should reject files whose `format` differs or whose `version` is newer than
+//! they understand. In Python: `json.load(open(path))["edges"]`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use osmpbfreader::{NodeId, Tags, WayId};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::profile::Profile;
+use crate::restriction::{RestrictionKind, RestrictionRecord, TurnRestrictions};
+use crate::{Edge, Graph, Node};
+
+pub const FORMAT: &str = "open_rust_map/graph";
+pub const VERSION: u32 = 2;
+
+type BoxError = Box<dyn std::error::Error>;
+
+/// Node tags that are relevant to routing and kept in the export.
+pub const EXPORTED_NODE_TAGS: &[&str] = &["highway", "barrier", "crossing", "railway", "name"];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GraphFile {
+    format: String,
+    version: u32,
+    profile: Profile,
+    nodes: Vec<NodeRecord>,
+    edges: Vec<EdgeRecord>,
+    #[serde(default)]
+    restrictions: Vec<RestrictionEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NodeRecord {
+    id: i64,
+    lat: f64,
+    lon: f64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EdgeRecord {
+    source: i64,
+    target: i64,
+    distance: f64,
+    duration: f64,
+    way_id: i64,
+    highway_type: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RestrictionEntry {
+    from: i64,
+    to: i64,
+    kind: RestrictionKindName,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    via_node: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    via_ways: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RestrictionKindName {
+    No,
+    Only,
+}
+
+impl From<RestrictionKind> for RestrictionKindName {
+    fn from(kind: RestrictionKind) -> Self {
+        match kind {
+            RestrictionKind::No => RestrictionKindName::No,
+            RestrictionKind::Only => RestrictionKindName::Only,
+        }
+    }
+}
+
+impl From<RestrictionKindName> for RestrictionKind {
+    fn from(kind: RestrictionKindName) -> Self {
+        match kind {
+            RestrictionKindName::No => RestrictionKind::No,
+            RestrictionKindName::Only => RestrictionKind::Only,
+        }
+    }
+}
+
+impl From<RestrictionRecord> for RestrictionEntry {
+    fn from(record: RestrictionRecord) -> Self {
+        match record {
+            RestrictionRecord::ViaNode { from, via, to, kind } => RestrictionEntry {
+                from: from.0,
+                to: to.0,
+                kind: kind.into(),
+                via_node: Some(via.0),
+                via_ways: Vec::new(),
+            },
+            RestrictionRecord::ViaWays { from, via, to, kind } => RestrictionEntry {
+                from: from.0,
+                to: to.0,
+                kind: kind.into(),
+                via_node: None,
+                via_ways: via.into_iter().map(|way| way.0).collect(),
+            },
+        }
+    }
+}
+
+impl TryFrom<RestrictionEntry> for RestrictionRecord {
+    type Error = String;
+
+    fn try_from(entry: RestrictionEntry) -> Result<Self, Self::Error> {
+        let (from, to, kind) = (WayId(entry.from), WayId(entry.to), entry.kind.into());
+        match (entry.via_node, entry.via_ways.is_empty()) {
+            (Some(via), true) => Ok(RestrictionRecord::ViaNode { from, via: NodeId(via), to, kind }),
+            (None, false) => Ok(RestrictionRecord::ViaWays {
+                from,
+                via: entry.via_ways.into_iter().map(WayId).collect(),
+                to,
+                kind,
+            }),
+            _ => Err(format!(
+                "restriction from way {} to way {} needs either a via node or via ways",
+                entry.from, entry.to
+            )),
+        }
+    }
+}
+
+/// Writes `graph` to `path` using the schema described in the module docs.
+pub fn write_graph(graph: &Graph, profile: Profile, path: &Path) -> Result<(), BoxError> {
+    let nodes = graph
+        .graph
+        .node_weights()
+        .map(|node| NodeRecord {
+            id: node.id.0,
+            lat: node.point.y(),
+            lon: node.point.x(),
+            tags: node
+                .tags
+                .iter()
+                .filter(|(key, _)| EXPORTED_NODE_TAGS.contains(&key.as_str()))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        })
+        .collect();
+    let edges = graph
+        .graph
+        .edge_weights()
+        .map(|edge| EdgeRecord {
+            source: edge.source.0,
+            target: edge.target.0,
+            distance: edge.distance,
+            duration: edge.duration,
+            way_id: edge.way_id.0,
+            highway_type: edge.highway_type.clone(),
+        })
+        .collect();
+    let restrictions = graph
+        .restrictions
+        .records()
+        .into_iter()
+        .map(RestrictionEntry::from)
+        .collect();
+    let file = GraphFile {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        profile,
+        nodes,
+        edges,
+        restrictions,
+    };
+    serde_json::to_writer(BufWriter::new(File::create(path)?), &file)?;
+    info!(
+        "Exported {} graph with {} nodes, {} edges and {} turn restrictions to {}",
+        profile.as_str(),
+        file.nodes.len(),
+        file.edges.len(),
+        file.restrictions.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Loads a graph written by [`write_graph`] without reparsing the PBF.
+pub fn read_graph(path: &Path) -> Result<(Profile, Graph), BoxError> {
+    let file: GraphFile = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    if file.format != FORMAT {
+        return Err(format!("{} is not a graph export (format {:?})", path.display(), file.format).into());
+    }
+    if file.version > VERSION {
+        return Err(format!("unsupported graph export version {}", file.version).into());
+    }
+
+    let mut graph = load_graph(file.nodes, file.edges)?;
+    graph.restrictions = TurnRestrictions::from_records(
+        file.restrictions
+            .into_iter()
+            .map(RestrictionRecord::try_from)
+            .collect::<Result<Vec<_>, _>>()?,
+    );
+    graph.build_index();
+    info!(
+        "Imported {} graph with {} nodes and {} edges from {}",
+        file.profile.as_str(),
+        graph.graph.node_count(),
+        graph.graph.edge_count(),
+        path.display()
+    );
+    Ok((file.profile, graph))
+}
+
+/// Fills a graph directly rather than through `add_node`/`add_edge`, which trace every item;
+/// an edge whose endpoint is not among the nodes makes the file invalid.
+fn load_graph(nodes: Vec<NodeRecord>, edges: Vec<EdgeRecord>) -> Result<Graph, BoxError> {
+    let mut graph = Graph::new();
+    graph.graph.reserve_exact_nodes(nodes.len());
+    graph.node_indices.reserve(nodes.len());
+    for record in nodes {
+        let id = NodeId(record.id);
+        let mut tags = Tags::new();
+        for (key, value) in record.tags {
+            tags.insert(key.into(), value.into());
+        }
+        let idx = graph.graph.add_node(Node {
+            id,
+            point: geo_types::Point::new(record.lon, record.lat),
+            tags,
+        });
+        if graph.node_indices.insert(id, idx).is_some() {
+            return Err(format!("duplicate node {}", id.0).into());
+        }
+    }
+
+    graph.graph.reserve_exact_edges(edges.len());
+    for record in edges {
+        let index_of = |id: i64| {
+            graph
+                .node_indices
+                .get(&NodeId(id))
+                .copied()
+                .ok_or_else(|| format!("edge of way {} refers to unknown node {}", record.way_id, id))
+        };
+        let (source, target) = (index_of(record.source)?, index_of(record.target)?);
+        if record.duration > 0.0 {
+            graph.max_speed = graph.max_speed.max(record.distance / record.duration);
+        }
+        graph.graph.add_edge(
+            source,
+            target,
+            Edge {
+                source: NodeId(record.source),
+                target: NodeId(record.target),
+                distance: record.distance,
+                duration: record.duration,
+                way_id: WayId(record.way_id),
+                highway_type: record.highway_type,
+            },
+        );
+    }
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::OsmId;
+
+    use super::*;
+    use crate::test_support::Fixture;
+    use crate::Metric;
+
+    /// A lopsided square 1-2-3-4 with one via-node and one via-way restriction.
+    fn fixture() -> Fixture {
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, 100.000, 13.000)
+            .node(2, 100.001, 13.000)
+            .node(3, 100.001, 13.001)
+            .node(4, 100.000, 13.0012)
+            .way(10, &[1, 2], &[("highway", "primary")])
+            .way(20, &[2, 3], &[("highway", "residential")])
+            .way(30, &[3, 4], &[("highway", "residential")])
+            .way(40, &[4, 1], &[("highway", "service")])
+            .relation(
+                1,
+                &[
+                    (OsmId::Way(WayId(10)), "from"),
+                    (OsmId::Node(NodeId(2)), "via"),
+                    (OsmId::Way(WayId(20)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "no_left_turn")],
+            )
+            .relation(
+                2,
+                &[
+                    (OsmId::Way(WayId(20)), "from"),
+                    (OsmId::Way(WayId(30)), "via"),
+                    (OsmId::Way(WayId(40)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "no_left_turn")],
+            );
+        let signals = fixture.osm.nodes.get_mut(&2).unwrap();
+        signals.tags.insert("highway".into(), "traffic_signals".into());
+        fixture
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("export_{}_{}.json", name, std::process::id()))
+    }
+
+    fn sorted_records(graph: &Graph) -> Vec<String> {
+        let mut records = graph
+            .restrictions
+            .records()
+            .iter()
+            .map(|record| format!("{:?}", record))
+            .collect::<Vec<_>>();
+        records.sort();
+        records
+    }
+
+    #[test]
+    fn round_trips_nodes_edges_restrictions_and_routes() {
+        let graph = fixture().graph(Profile::Car);
+        let path = temp_path("round_trip");
+        write_graph(&graph, Profile::Car, &path).unwrap();
+        let (profile, loaded) = read_graph(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(profile, Profile::Car);
+        assert_eq!(loaded.graph.node_count(), graph.graph.node_count());
+        assert_eq!(loaded.graph.edge_count(), graph.graph.edge_count());
+        for (a, b) in graph.graph.node_weights().zip(loaded.graph.node_weights()) {
+            assert_eq!((a.id, a.point), (b.id, b.point));
+            assert_eq!(loaded.node_indices[&a.id], graph.node_indices[&a.id]);
+        }
+        let signals = &loaded.graph[loaded.node_indices[&NodeId(2)]];
+        assert_eq!(signals.tags.get("highway").map(|v| v.as_str()), Some("traffic_signals"));
+        for edge in graph.graph.edge_indices() {
+            assert_eq!(graph.graph.edge_endpoints(edge), loaded.graph.edge_endpoints(edge));
+            let (a, b) = (&graph.graph[edge], &loaded.graph[edge]);
+            assert_eq!((a.source, a.target, a.way_id), (b.source, b.target, b.way_id));
+            assert_eq!((a.distance, a.duration), (b.distance, b.duration));
+            assert_eq!(a.highway_type, b.highway_type);
+        }
+        assert_eq!(loaded.max_speed, graph.max_speed);
+        assert_eq!(sorted_records(&loaded), sorted_records(&graph));
+        assert_eq!(sorted_records(&graph).len(), 2);
+
+        for metric in [Metric::Distance, Metric::Duration] {
+            for start in graph.graph.node_indices() {
+                for end in graph.graph.node_indices() {
+                    assert_eq!(
+                        loaded.find_shortest_path(start, end, metric),
+                        graph.find_shortest_path(start, end, metric),
+                        "{:?} -> {:?} by {:?}",
+                        start,
+                        end,
+                        metric
+                    );
+                }
+            }
+            for exit in graph.graph.edge_indices() {
+                for entry in graph.graph.edge_indices() {
+                    assert_eq!(
+                        loaded.find_restricted_path_between(exit, entry, metric),
+                        graph.find_restricted_path_between(exit, entry, metric)
+                    );
+                }
+            }
+        }
+        // The restriction at node 2 is kept, so 1 -> 3 goes round by 4
+        let (path, _) = loaded
+            .find_shortest_path(loaded.node_indices[&NodeId(1)], loaded.node_indices[&NodeId(3)], Metric::Distance)
+            .unwrap();
+        let ids = path.iter().map(|&idx| loaded.graph[idx].id.0).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 4, 3]);
+    }
+
+    #[test]
+    fn rejects_edges_to_unknown_nodes() {
+        let path = temp_path("unknown_node");
+        std::fs::write(
+            &path,
+            r#"{"format":"open_rust_map/graph","version":1,"profile":"car",
+                "nodes":[{"id":1,"lat":0.0,"lon":0.0}],
+                "edges":[{"source":1,"target":2,"distance":1.0,"duration":1.0,"way_id":5,"highway_type":null}]}"#,
+        )
+        .unwrap();
+        let error = read_graph(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.to_string(), "edge of way 5 refers to unknown node 2");
+    }
+}
@@ -1,24 +1,55 @@
-use std::fs::File;
+mod ch;
+mod export;
+mod output;
+mod pgrouting;
+mod prepared;
+mod profile;
+mod restriction;
+mod snap;
+mod spatial;
+mod speed;
+#[cfg(test)]
+mod test_support;
+mod update;
+
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
 use geo::prelude::*;
-use geo_types::{Point, LineString};
+use geo_types::Point;
 use hashbrown::HashSet;
-use osmpbfreader::{OsmPbfReader, OsmObj, NodeId, WayId, Tags};
-use petgraph::graph::{NodeIndex, UnGraph};
+use osmpbfreader::{OsmObj, NodeId, Relation, Way, WayId, Tags};
+use petgraph::graph::{DiGraph, NodeIndex};
 use petgraph::algo::astar;
 use clap::Parser;
 use indicatif::{ProgressBar, ProgressStyle};
-use rayon::prelude::*;
 use tracing::{info, debug, warn, error, instrument};
-use tracing_subscriber::{fmt, EnvFilter};
+use tracing_subscriber::EnvFilter;
+
+use base::model::node_location_model::{NodeCacheConfig, NodeLocationStore};
+use base::error::Error as BaseError;
+use base::model::clip_model::{ClipArea, ClipOptions, ClipStrategy};
+use base::model::osm_file_model::{FilteredOsmFile, OsmFile, OsmSource};
+use base::model::tag_filter_model::TagFilter;
+use base::model::osm_model::Osm;
+use base::model::pg_model::{PgImportConfig, PgRoadsConfig};
+
+use crate::ch::ContractionHierarchy;
+use crate::output::FeatureCollection;
+use crate::prepared::PreparedError;
+use crate::profile::Profile;
+use crate::restriction::TurnRestrictions;
+use crate::snap::Route;
+use crate::spatial::SpatialIndex;
+use crate::speed::SpeedTable;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Path to the OSM PBF file
-    #[arg(short, long)]
-    input: PathBuf,
+    /// Path to the OSM file: PBF, or OSM XML (.osm, .osm.gz, .osm.bz2)
+    #[arg(short, long, required_unless_present_any = ["import_graph", "prepared", "pg_roads"])]
+    input: Option<PathBuf>,
 
     /// Start latitude
     #[arg(long)]
@@ -36,9 +67,132 @@ struct Args {
     #[arg(long)]
     end_lon: Option<f64>,
 
-    /// Export graph to JSON (optional)
+    /// Routing profile(s) to build a graph for, comma separated
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "car")]
+    profile: Vec<Profile>,
+
+    /// Edge weight minimised by the router
+    #[arg(long, value_enum, default_value = "distance")]
+    metric: Metric,
+
+    /// Preprocess each graph into a contraction hierarchy for fast queries; graphs with turn
+    /// restrictions are contracted turn by turn so the restrictions still hold
+    #[arg(long)]
+    contract: bool,
+
+    /// JSON object overriding the default per-highway speeds in km/h (optional)
+    #[arg(long)]
+    speed_table: Option<PathBuf>,
+
+    /// Maximum distance in metres a query point may be moved to reach a road (optional)
+    #[arg(long)]
+    max_snap_distance: Option<f64>,
+
+    /// Write the route(s) as a GeoJSON FeatureCollection to a file, or `-` for stdout (optional)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Fail with a data error when a highway way references a node missing from the input, instead of skipping the node
+    #[arg(long)]
+    strict: bool,
+
+    /// Stream node locations into a cache that spills to a temporary file in this directory instead of keeping nodes in memory (optional)
+    #[arg(long)]
+    node_cache: Option<PathBuf>,
+
+    /// Memory in MiB the node cache may use before spilling to --node-cache
+    #[arg(long, default_value_t = 1024)]
+    node_cache_memory: usize,
+
+    /// Export graph to JSON (optional); one file per profile when several are built
     #[arg(long)]
     export_graph: Option<PathBuf>,
+
+    /// Load a graph previously written with --export-graph instead of reading a PBF
+    #[arg(long, conflicts_with = "input")]
+    import_graph: Option<PathBuf>,
+
+    /// Write the built graph and any contraction hierarchy to a binary prepared file (optional)
+    #[arg(long)]
+    prepare: Option<PathBuf>,
+
+    /// Memory-map a file written with --prepare instead of reading a PBF
+    #[arg(long, conflicts_with_all = ["input", "import_graph"])]
+    prepared: Option<PathBuf>,
+
+    /// OsmChange file (.osc or .osc.gz) applied to the input before routing; repeat to apply several in order
+    #[arg(long, requires = "input")]
+    apply_change: Vec<PathBuf>,
+
+    /// Only keep data inside minlon,minlat,maxlon,maxlat (optional)
+    #[arg(long, allow_hyphen_values = true)]
+    bbox: Option<String>,
+
+    /// Only keep data inside the polygon in this GeoJSON or Osmosis .poly file (optional)
+    #[arg(long, conflicts_with = "bbox")]
+    clip_polygon: Option<PathBuf>,
+
+    /// How ways crossing the --bbox or --clip-polygon boundary are treated
+    #[arg(long, value_enum, default_value = "complete-ways")]
+    clip_strategy: ClipStrategy,
+
+    /// Keep every member of relations that are partly inside the clip area
+    #[arg(long)]
+    complete_relations: bool,
+
+    /// Only read objects matching this tag filter, e.g. "w/highway=primary,secondary r/type=restriction";
+    /// nodes and ways referenced by selected objects are pulled in automatically
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// Read the tag filter from this file, one or more expressions per line, `#` starting a comment
+    #[arg(long, conflicts_with = "filter")]
+    filter_file: Option<PathBuf>,
+
+    /// Write the loaded OSM data, after clipping and any changes, to this .osm.pbf file (optional)
+    #[arg(long, requires = "input")]
+    write_pbf: Option<PathBuf>,
+
+    /// Import the loaded OSM data into PostGIS at this connection string, e.g. "host=localhost user=postgres dbname=osm" (optional)
+    #[arg(long, requires = "input")]
+    import_pg: Option<String>,
+
+    /// Schema the --import-pg and pgRouting tables are (re)created in; pgRouting schemas get a `_<profile>` suffix when several profiles are built
+    #[arg(long, default_value = "osm")]
+    pg_schema: String,
+
+    /// Load each graph into pgRouting `ways`/`ways_vertices_pgr` tables in --pg-schema at this connection string (optional)
+    #[arg(long)]
+    pgrouting_pg: Option<String>,
+
+    /// Write each graph as a psql script recreating the pgRouting tables in --pg-schema (optional)
+    #[arg(long)]
+    pgrouting_sql: Option<PathBuf>,
+
+    /// Write each graph as pgRouting `ways.csv` and `ways_vertices_pgr.csv` into this directory (optional)
+    #[arg(long)]
+    pgrouting_csv: Option<PathBuf>,
+
+    /// Build the graphs from road lines in PostGIS at this connection string instead of reading a PBF
+    #[arg(long, conflicts_with_all = ["input", "import_graph", "prepared"])]
+    pg_roads: Option<String>,
+
+    /// Query selecting the --pg-roads lines: an integer `id`, a `geom` and tag columns such as `highway`, `oneway` and `maxspeed`
+    #[arg(long, default_value = "SELECT * FROM roads")]
+    pg_roads_query: String,
+
+    /// Times to retry the --pg-roads connection, two seconds apart, before giving up
+    #[arg(long, default_value_t = 5)]
+    pg_retries: u32,
+
+    /// Replication feed to catch up from after loading the input, e.g. https://planet.openstreetmap.org/replication/day;
+    /// the result must be saved with --write-pbf and used as the next run's --input
+    #[arg(long, requires_all = ["input", "replication_state", "write_pbf"])]
+    replication_url: Option<String>,
+
+    /// File holding the last applied replication sequence; seed it with the sequence of the input extract
+    #[arg(long, requires = "replication_url")]
+    replication_state: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -53,23 +207,83 @@ struct Edge {
     source: NodeId,
     target: NodeId,
     distance: f64,
+    /// Travel time in seconds
+    duration: f64,
     way_id: WayId,
     highway_type: Option<String>,
 }
 
+/// Quantity the router minimises along a path.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    /// Metres travelled
+    Distance,
+    /// Seconds travelled
+    Duration,
+}
+
+impl Edge {
+    fn weight(&self, metric: Metric) -> f64 {
+        match metric {
+            Metric::Distance => self.distance,
+            Metric::Duration => self.duration,
+        }
+    }
+}
+
+/// Direction(s) in which a way may be traversed, relative to its node order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Oneway {
+    No,
+    Forward,
+    Backward,
+}
+
+impl Oneway {
+    fn from_tags(tags: &Tags) -> Self {
+        match tags.get("oneway").map(|v| v.as_str()) {
+            Some("yes") | Some("true") | Some("1") => return Oneway::Forward,
+            Some("-1") | Some("reverse") => return Oneway::Backward,
+            Some("no") | Some("false") | Some("0") => return Oneway::No,
+            _ => {}
+        }
+        // Roundabouts are implicitly one-way in the direction of the way
+        match tags.get("junction").map(|v| v.as_str()) {
+            Some("roundabout") | Some("circular") => Oneway::Forward,
+            _ => Oneway::No,
+        }
+    }
+
+    fn allows_forward(self) -> bool {
+        self != Oneway::Backward
+    }
+
+    fn allows_backward(self) -> bool {
+        self != Oneway::Forward
+    }
+}
+
 #[derive(Debug)]
 struct Graph {
-    graph: UnGraph<Node, Edge>,
+    graph: DiGraph<Node, Edge>,
     node_indices: HashMap<NodeId, NodeIndex>,
+    /// Fastest edge speed in m/s, bounding the A* heuristic for duration routing. Graph
+    /// updates only ever raise it, so it may exceed the fastest remaining edge.
+    max_speed: f64,
+    restrictions: TurnRestrictions,
+    index: SpatialIndex,
 }
 
 impl Graph {
-    #[instrument(skip(self))]
+    #[instrument]
     fn new() -> Self {
         debug!("Creating new graph");
         Graph {
-            graph: UnGraph::new_undirected(),
+            graph: DiGraph::new(),
             node_indices: HashMap::new(),
+            max_speed: 0.0,
+            restrictions: TurnRestrictions::default(),
+            index: SpatialIndex::default(),
         }
     }
 
@@ -77,8 +291,8 @@ impl Graph {
     fn add_node(&mut self, node: Node) -> NodeIndex {
         let node_idx = self.graph.add_node(node.clone());
         self.node_indices.insert(node.id, node_idx);
-        debug!("Added node {:?} at ({:.6}, {:.6})", 
-               node.id, node.point.y(), node.point.x());
+        debug!("Added node {:?} at ({:.6}, {:.6}) with {} tags", 
+               node.id, node.point.y(), node.point.x(), node.tags.len());
         node_idx
     }
 
@@ -88,59 +302,54 @@ impl Graph {
             self.node_indices.get(&edge.source),
             self.node_indices.get(&edge.target),
         ) {
+            if edge.duration > 0.0 {
+                self.max_speed = self.max_speed.max(edge.distance / edge.duration);
+            }
             self.graph.add_edge(source_idx, target_idx, edge.clone());
-            debug!("Added edge from {:?} to {:?} with distance {:.2}m, way_id: {:?}", 
-                   edge.source, edge.target, edge.distance, edge.way_id);
+            debug!("Added edge from {:?} to {:?} with distance {:.2}m, way_id: {:?}, highway: {:?}", 
+                   edge.source, edge.target, edge.distance, edge.way_id, edge.highway_type);
         } else {
             warn!("Could not add edge: source {:?} or target {:?} not found in graph", 
                   edge.source, edge.target);
         }
     }
 
-    #[instrument(skip(self))]
-    fn get_nearest_node(&self, lat: f64, lon: f64) -> Option<NodeIndex> {
-        let query_point = Point::new(lon, lat);
-        debug!("Finding nearest node to ({}, {})", lat, lon);
-        
-        let nearest = self.graph
-            .node_indices()
-            .min_by_key(|&idx| {
-                let node = &self.graph[idx];
-                // Using an approximate distance metric for performance
-                let dist = node.point.geodesic_distance(&query_point);
-                (dist * 1000.0) as i32  // Convert to mm for integer comparison
-            });
-            
-        if let Some(idx) = nearest {
-            let node = &self.graph[idx];
-            debug!("Found nearest node {:?} at ({:.6}, {:.6}), distance: {:.2}m", 
-                   node.id, node.point.y(), node.point.x(),
-                   node.point.geodesic_distance(&query_point));
-        } else {
-            warn!("No nodes found in graph to calculate nearest");
+    /// Rebuilds the spatial index; call once all nodes and edges have been added.
+    fn build_index(&mut self) {
+        self.index = SpatialIndex::build(self);
+    }
+
+    /// Lower bound on the remaining cost from `idx` to `end`, admissible for both metrics.
+    fn heuristic(&self, idx: NodeIndex, end: NodeIndex, metric: Metric) -> f64 {
+        let node = &self.graph[idx];
+        let target = &self.graph[end];
+        let distance = node.point.geodesic_distance(&target.point);
+        match metric {
+            Metric::Distance => distance,
+            // No edge is faster than max_speed, so this never overestimates
+            Metric::Duration if self.max_speed > 0.0 => distance / self.max_speed,
+            Metric::Duration => 0.0,
         }
-        
-        nearest
     }
 
+    /// A* over outgoing edges only, so one-way streets are never driven against traffic.
+    /// Turn restrictions switch the search to an edge-based variant.
     #[instrument(skip(self))]
-    fn find_shortest_path(&self, start: NodeIndex, end: NodeIndex) -> Option<(Vec<NodeIndex>, f64)> {
+    fn find_shortest_path(&self, start: NodeIndex, end: NodeIndex, metric: Metric) -> Option<(Vec<NodeIndex>, f64)> {
         debug!("Finding shortest path from node index {:?} to {:?}", start, end);
         
-        let result = astar(
-            &self.graph,
-            start,
-            |finish| finish == end,
-            |e| {
-                let edge = e.weight();
-                edge.distance as f64
-            },
-            |idx| {
-                let node = &self.graph[idx];
-                let target = &self.graph[end];
-                node.point.geodesic_distance(&target.point)
-            },
-        );
+        let result = if self.restrictions.is_empty() {
+            astar(
+                &self.graph,
+                start,
+                |finish| finish == end,
+                |e| e.weight().weight(metric),
+                |idx| self.heuristic(idx, end, metric),
+            )
+            .map(|(cost, path)| (path, cost))
+        } else {
+            self.find_restricted_path(start, end, metric)
+        };
         
         match &result {
             Some((path, cost)) => {
@@ -153,69 +362,104 @@ impl Graph {
         
         result
     }
-}
+
+    /// Edges along a path, taking the one the router would have chosen between each pair
+    /// of consecutive nodes.
+    fn path_edges(&self, path: &[NodeIndex], metric: Metric) -> Vec<&Edge> {
+        path.windows(2)
+            .filter_map(|pair| {
+                self.graph
+                    .edges_connecting(pair[0], pair[1])
+                    .map(|e| e.weight())
+                    .min_by(|a, b| a.weight(metric).total_cmp(&b.weight(metric)))
+            })
+            .collect()
+    }
+
+    /// Sums distance and duration along a path.
+    fn path_totals(&self, path: &[NodeIndex], metric: Metric) -> (f64, f64) {
+        self.path_edges(path, metric)
+            .into_iter()
+            .fold((0.0, 0.0), |(distance, duration), edge| {
+                (distance + edge.distance, duration + edge.duration)
+            })
+    }
 }
 
-#[instrument]
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing subscriber
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .with_target(false)
-        .init();
-    
-    let args = Args::parse();
-    
-    info!("Reading OSM PBF file: {}", args.input.display());
-    let file = File::open(&args.input)?;
-    let mut pbf = OsmPbfReader::new(file);
-    
-    // First pass: collect all nodes
-    info!("Collecting nodes...");
-    let progress_style = ProgressStyle::default_bar()
-        .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
-        .unwrap();
-    
-    let mut nodes = HashMap::new();
-    let mut ways = Vec::new();
-    
-    // Process all objects
-    let progress = ProgressBar::new_spinner();
-    progress.set_style(progress_style.clone());
-    
-    for (i, obj) in pbf.iter().enumerate() {
-        if i % 100000 == 0 {
-            progress.set_message(format!("Processed {} objects", i));
-            progress.inc(1);
-        }
-        
-        match obj? {
-            OsmObj::Node(node) => {
-                nodes.insert(
-                    node.id,
-                    Node {
-                        id: node.id,
-                        point: Point::new(node.lon(), node.lat()),
-                        tags: node.tags,
-                    },
-                );
-            }
-            OsmObj::Way(way) => {
-                // Only keep ways that are roads/paths
-                if way.tags.contains_key("highway") {
-                    ways.push(way);
-                }
-            }
-            _ => {}
+/// Looks up graph nodes by OSM id while the graph is built.
+trait NodeSource {
+    fn get_node(&self, id: NodeId) -> Option<Node>;
+
+    /// Fails on the first node of `way` whose location is unknown.
+    fn require_way_nodes(&self, way: &Way) -> Result<(), BaseError> {
+        match way.nodes.iter().find(|&&id| self.get_node(id).is_none()) {
+            Some(id) => Err(BaseError::MissingNode {
+                way_id: way.id.0,
+                node_id: id.0,
+            }),
+            None => Ok(()),
         }
     }
-    progress.finish_with_message(format!("Collected {} nodes and {} ways", nodes.len(), ways.len()));
-    info!("Collected {} nodes and {} ways", nodes.len(), ways.len());
-    
+}
+
+impl NodeSource for HashMap<NodeId, Node> {
+    fn get_node(&self, id: NodeId) -> Option<Node> {
+        self.get(&id).cloned()
+    }
+}
+
+impl NodeSource for Osm {
+    fn get_node(&self, id: NodeId) -> Option<Node> {
+        let (lon, lat) = self.get_coordinate_by_node_id(id.0)?;
+        Some(Node {
+            id,
+            point: Point::new(lon, lat),
+            tags: self.get_node_by_id(id.0).map_or_else(Tags::new, |node| node.tags.clone()),
+        })
+    }
+
+    fn require_way_nodes(&self, way: &Way) -> Result<(), BaseError> {
+        self.try_get_coordinates_by_way(way).map(|_| ())
+    }
+}
+
+impl NodeSource for NodeLocationStore {
+    fn get_node(&self, id: NodeId) -> Option<Node> {
+        self.get(id.0).map(|(lon, lat)| Node {
+            id,
+            point: Point::new(lon, lat),
+            tags: Tags::new(),
+        })
+    }
+}
+
+/// Builds the routing graph for one profile from the parsed nodes and highway ways.
+///
+/// Nodes missing from `nodes` are skipped with a warning, or fail the build when `strict`.
+fn build_graph(
+    nodes: &dyn NodeSource,
+    ways: &[Way],
+    relations: &[Relation],
+    profile: Profile,
+    speeds: &SpeedTable,
+    strict: bool,
+    progress_style: &ProgressStyle,
+) -> Result<Graph, BaseError> {
     // Build graph
-    info!("Building graph...");
+    info!("Building {} graph...", profile.as_str());
     let mut graph = Graph::new();
     
+    // Only ways this profile may use take part in the graph
+    let ways = ways
+        .iter()
+        .filter(|way| profile.allows_way(&way.tags))
+        .collect::<Vec<_>>();
+    if strict {
+        for way in &ways {
+            nodes.require_way_nodes(way)?;
+        }
+    }
+    
     // First add all nodes that are part of ways
     let mut way_nodes = HashSet::new();
     for way in &ways {
@@ -230,8 +474,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     progress.set_style(progress_style.clone());
     
     for node_id in way_nodes {
-        if let Some(node) = nodes.get(&node_id) {
-            graph.add_node(node.clone());
+        if let Some(node) = nodes.get_node(node_id) {
+            graph.add_node(node);
         } else {
             warn!("Node {} referenced in way but not found in nodes collection", node_id.0);
         }
@@ -243,111 +487,701 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Add edges
     info!("Adding edges...");
     let progress = ProgressBar::new(ways.len() as u64);
-    progress.set_style(progress_style);
+    progress.set_style(progress_style.clone());
     
     for way in &ways {
-        let highway_type = way.tags.get("highway").map(|s| s.to_string());
-        
-        // Create edges between consecutive nodes
-        for window in way.nodes.windows(2) {
-            if let [source, target] = *window {
-                if let (Some(source_node), Some(target_node)) = (nodes.get(&source), nodes.get(&target)) {
-                    let distance = source_node.point.geodesic_distance(&target_node.point);
-                    
+        add_way_edges(&mut graph, nodes, way, profile, speeds);
+        progress.inc(1);
+    }
+    progress.finish_with_message("Built graph");
+    
+    graph.restrictions = TurnRestrictions::from_relations(relations, profile);
+    graph.build_index();
+    
+    info!("{} graph built with {} nodes and {} edges", 
+          profile.as_str(),
+          graph.graph.node_count(), 
+          graph.graph.edge_count());
+    
+    Ok(graph)
+}
+
+/// Adds the edges of one highway way, in each direction the profile may travel it.
+fn add_way_edges(graph: &mut Graph, nodes: &dyn NodeSource, way: &Way, profile: Profile, speeds: &SpeedTable) {
+    let highway_type = way.tags.get("highway").map(|s| s.to_string());
+    let oneway = profile.oneway(&way.tags);
+    let forward_speed = speeds.way_speed(&way.tags, profile, true);
+    let backward_speed = speeds.way_speed(&way.tags, profile, false);
+    
+    // Create edges between consecutive nodes, in each allowed direction
+    for window in way.nodes.windows(2) {
+        if let [source, target] = *window {
+            if let (Some(source_node), Some(target_node)) = (nodes.get_node(source), nodes.get_node(target)) {
+                let distance = source_node.point.geodesic_distance(&target_node.point);
+                
+                if oneway.allows_forward() {
                     graph.add_edge(Edge {
                         source,
                         target,
                         distance,
+                        duration: distance / (forward_speed / 3.6),
+                        way_id: way.id,
+                        highway_type: highway_type.clone(),
+                    });
+                }
+                if oneway.allows_backward() {
+                    graph.add_edge(Edge {
+                        source: target,
+                        target: source,
+                        distance,
+                        duration: distance / (backward_speed / 3.6),
                         way_id: way.id,
                         highway_type: highway_type.clone(),
                     });
                 }
             }
         }
-        progress.inc(1);
     }
-    progress.finish_with_message("Built graph");
+}
+
+/// Snaps the start and end coordinates onto the nearest roads and finds the shortest path between them.
+fn route(
+    graph: &Graph,
+    hierarchy: Option<&ContractionHierarchy>,
+    metric: Metric,
+    max_snap_distance: Option<f64>,
+    from: Point<f64>,
+    to: Point<f64>,
+) -> Option<Route> {
+    info!("Finding shortest path from ({}, {}) to ({}, {})", 
+          from.y(), from.x(), to.y(), to.x());
     
-    info!("Graph built with {} nodes and {} edges", 
-          graph.graph.node_count(), 
-          graph.graph.edge_count());
+    let (Some(start), Some(end)) = (
+        graph.snap(from.y(), from.x(), max_snap_distance),
+        graph.snap(to.y(), to.x(), max_snap_distance),
+    ) else {
+        error!("Could not snap the given coordinates to a road");
+        return None;
+    };
     
-    // If coordinates are provided, find path
-    if let (Some(start_lat), Some(start_lon), Some(end_lat), Some(end_lon)) = 
-       (args.start_lat, args.start_lon, args.end_lat, args.end_lon) {
-        
-        info!("Finding shortest path from ({}, {}) to ({}, {})", 
-              start_lat, start_lon, end_lat, end_lon);
+    info!("Start snapped to ({:.6}, {:.6}), {:.2}m from the query point", 
+          start.point.y(), 
+          start.point.x(),
+          start.distance);
+    
+    info!("End snapped to ({:.6}, {:.6}), {:.2}m from the query point", 
+          end.point.y(), 
+          end.point.x(),
+          end.distance);
+    
+    let Some(route) = graph.route_between(hierarchy, &start, &end, metric) else {
+        warn!("No path found between the given points");
+        return None;
+    };
+    
+    let path = &route.path;
+    info!("Found path with {} nodes, total distance of {:.2} km and duration of {:.1} min", 
+          path.len(), route.distance / 1000.0, route.duration / 60.0);
+    
+    // Print detailed path info
+    debug!("Path details:");
+    for (i, &idx) in path.iter().enumerate() {
+        if i % 10 == 0 || i == path.len() - 1 {  // print every 10th node or the last one
+            let node = &graph.graph[idx];
+            debug!("  Node {}: ({:.6}, {:.6})", 
+                   i, node.point.y(), node.point.x());
+        }
+    }
+    
+    Some(route)
+}
+
+/// Inserts the profile name before the extension, e.g. `graph.json` becomes `graph-car.json`.
+fn profile_export_path(path: &Path, profile: Profile) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let mut name = format!("{}-{}", stem, profile.as_str());
+    if let Some(extension) = path.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+/// Writes each graph as pgRouting tables to the database and/or dump files requested.
+fn export_pgrouting(graphs: &[(Profile, Graph)], args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    for (profile, graph) in graphs {
+        let tables = pgrouting::to_pgrouting(graph, args.metric);
+        let schema = if graphs.len() > 1 {
+            format!("{}_{}", args.pg_schema, profile.as_str())
+        } else {
+            args.pg_schema.clone()
+        };
+        let profile_path = |path: &PathBuf| {
+            if graphs.len() > 1 {
+                profile_export_path(path, *profile)
+            } else {
+                path.clone()
+            }
+        };
+        if let Some(connection) = &args.pgrouting_pg {
+            let config = PgImportConfig {
+                connection: connection.clone(),
+                schema: schema.clone(),
+            };
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            runtime.block_on(tables.load_pg(&config))?;
+        }
+        if let Some(path) = &args.pgrouting_sql {
+            let path = profile_path(path);
+            tables.write_sql(&path, &schema)?;
+            info!("Wrote pgRouting SQL for {} to {}", profile.as_str(), path.display());
+        }
+        if let Some(dir) = &args.pgrouting_csv {
+            let dir = profile_path(dir);
+            tables.write_csv(&dir)?;
+            info!("Wrote pgRouting CSV for {} to {}", profile.as_str(), dir.display());
+        }
+    }
+    Ok(())
+}
+
+/// Whether the input must be kept as a full `Osm` store rather than streamed into graphs.
+fn needs_osm_store(args: &Args) -> bool {
+    !args.apply_change.is_empty()
+        || args.replication_url.is_some()
+        || args.write_pbf.is_some()
+        || args.import_pg.is_some()
+}
+
+/// Opens the input, reading only what --filter or --filter-file selects when either was given.
+fn open_input(input: &Path, args: &Args) -> Result<Box<dyn OsmSource>, Box<dyn std::error::Error>> {
+    let filter = match (&args.filter, &args.filter_file) {
+        (Some(expressions), _) => TagFilter::parse(expressions)?,
+        (None, Some(path)) => TagFilter::from_file(path)?,
+        (None, None) => return Ok(Box::new(OsmFile::open(input)?)),
+    };
+    Ok(Box::new(FilteredOsmFile {
+        path: input.to_path_buf(),
+        filter,
+    }))
+}
+
+/// Clip area from --bbox or --clip-polygon, if either was given.
+fn clip_options(args: &Args) -> Result<Option<ClipOptions>, Box<dyn std::error::Error>> {
+    let area = match (&args.bbox, &args.clip_polygon) {
+        (Some(bbox), _) => ClipArea::from_bbox_str(bbox)?,
+        (None, Some(path)) => ClipArea::from_path(path)?,
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(ClipOptions {
+        area,
+        strategy: args.clip_strategy,
+        complete_relations: args.complete_relations,
+    }))
+}
+
+/// Clips the collected highway ways and turn restrictions as `Osm::clip` does, using the
+/// nodes found inside the clip area while reading.
+fn clip_collected(
+    ways: Vec<Way>,
+    relations: Vec<Relation>,
+    clip: &ClipOptions,
+    inside: &HashSet<i64>,
+) -> (Vec<Way>, Vec<Relation>) {
+    let total = ways.len();
+    let mut osm = Osm::default();
+    ways.into_iter().for_each(|way| osm.add_way(way));
+    relations.into_iter().for_each(|relation| osm.add_relation(relation));
+    osm.clip_with(clip, |_, id| inside.contains(&id.0));
+
+    let mut ways = osm.ways.into_values().collect::<Vec<_>>();
+    ways.sort_by_key(|way| way.id);
+    let mut relations = osm.relations.into_values().collect::<Vec<_>>();
+    relations.sort_by_key(|relation| relation.id);
+    info!("Clipped {} highway ways to {} inside the clip area", total, ways.len());
+    (ways, relations)
+}
+
+/// Speed table from --speed-table, or the built-in defaults.
+fn load_speeds(args: &Args) -> Result<SpeedTable, Box<dyn std::error::Error>> {
+    Ok(match &args.speed_table {
+        Some(path) => SpeedTable::from_file(path)?,
+        None => SpeedTable::default(),
+    })
+}
+
+/// Reads road lines from the --pg-roads query and builds one graph per requested profile.
+fn read_pg_graphs(
+    args: &Args,
+    progress_style: &ProgressStyle,
+) -> Result<Vec<(Profile, Graph)>, Box<dyn std::error::Error>> {
+    let connection = args
+        .pg_roads
+        .clone()
+        .ok_or("clap requires --input, --import-graph, --prepared or --pg-roads")?;
+    let config = PgRoadsConfig {
+        connection,
+        query: args.pg_roads_query.clone(),
+        retries: args.pg_retries,
+        retry_delay: Duration::from_secs(2),
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let mut osm = runtime.block_on(Osm::from_pg_roads(&config))?;
+    if let Some(clip) = clip_options(args)? {
+        osm.clip(&clip);
+    }
+    let speeds = load_speeds(args)?;
+
+    let mut ways = osm.ways.values().cloned().collect::<Vec<_>>();
+    ways.sort_by_key(|way| way.id);
+    Ok(args
+        .profile
+        .iter()
+        .map(|&profile| {
+            let graph = build_graph(&osm, &ways, &[], profile, &speeds, args.strict, progress_style)?;
+            Ok((profile, graph))
+        })
+        .collect::<Result<Vec<_>, BaseError>>()?)
+}
+
+/// Keeps a node read from the input, or only its location when streaming through the node cache.
+fn store_node(
+    node: osmpbfreader::Node,
+    nodes: &mut HashMap<NodeId, Node>,
+    locations: &mut Option<NodeLocationStore>,
+) -> Result<(), BaseError> {
+    match locations {
+        Some(locations) => locations.insert(node.id.0, node.decimicro_lat, node.decimicro_lon)?,
+        None => {
+            nodes.insert(
+                node.id,
+                Node {
+                    id: node.id,
+                    point: Point::new(node.lon(), node.lat()),
+                    tags: node.tags,
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Parses the PBF or OSM XML input and builds one graph per requested profile.
+fn read_pbf_graphs(
+    input: &Path,
+    args: &Args,
+    progress_style: &ProgressStyle,
+) -> Result<Vec<(Profile, Graph)>, Box<dyn std::error::Error>> {
+    info!("Reading OSM file: {}", input.display());
+    let mut file = open_input(input, args)?;
+    
+    // First pass: collect all nodes, or only their locations when streaming through the node cache
+    info!("Collecting nodes...");
+    let mut nodes = HashMap::new();
+    let mut locations = args.node_cache.as_ref().map(|path| {
+        NodeLocationStore::new(NodeCacheConfig {
+            memory_limit: args.node_cache_memory * 1024 * 1024,
+            spill_dir: path.clone(),
+        })
+    });
+    let mut ways = Vec::new();
+    let mut relations = Vec::new();
+    let clip = clip_options(args)?;
+    let mut inside = HashSet::new();
+    
+    // Process all objects
+    let progress = ProgressBar::new_spinner();
+    progress.set_style(progress_style.clone());
+    
+    let mut processed = 0usize;
+    file.for_each_obj(&mut |obj| {
+        if processed.is_multiple_of(100000) {
+            progress.set_message(format!("Processed {} objects", processed));
+            progress.inc(1);
+        }
+        processed += 1;
         
-        if let (Some(start_idx), Some(end_idx)) = (
-            graph.get_nearest_node(start_lat, start_lon),
-            graph.get_nearest_node(end_lat, end_lon),
-        ) {
-            let start_node = &graph.graph[start_idx];
-            let end_node = &graph.graph[end_idx];
-            
-            info!("Nearest start node: {:?} at ({}, {})", 
-                  start_node.id, 
-                  start_node.point.y(), 
-                  start_node.point.x());
-            
-            info!("Nearest end node: {:?} at ({}, {})", 
-                  end_node.id, 
-                  end_node.point.y(), 
-                  end_node.point.x());
-            
-            if let Some((path, cost)) = graph.find_shortest_path(start_idx, end_idx) {
-                info!("Found path with {} nodes and total distance of {:.2} km", 
-                      path.len(), cost / 1000.0);
-                
-                // Print detailed path info
-                debug!("Path details:");
-                let path_line = LineString(path.iter()
-                    .map(|&idx| {
-                        let node = &graph.graph[idx];
-                        (node.point.x(), node.point.y()).into()
-                    })
-                    .collect());
-                
-                for (i, &idx) in path.iter().enumerate() {
-                    if i % 10 == 0 || i == path.len() - 1 {  // print every 10th node or the last one
-                        let node = &graph.graph[idx];
-                        debug!("  Node {}: ({:.6}, {:.6})", 
-                               i, node.point.y(), node.point.x());
+        match obj {
+            OsmObj::Node(node) => {
+                let keep = match &clip {
+                    Some(clip) if clip.area.contains(node.lon(), node.lat()) => {
+                        inside.insert(node.id.0);
+                        true
                     }
+                    // Outside nodes are only needed for complete ways or complete relations
+                    Some(clip) => clip.keeps_outside_nodes(),
+                    None => true,
+                };
+                if keep {
+                    store_node(node, &mut nodes, &mut locations)?;
                 }
-                
-                // Output GeoJSON path
-                info!("Path GeoJSON:");
-                info!("{{");
-                info!("  \"type\": \"LineString\",");
-                info!("  \"coordinates\": [");
-                
-                let mut geojson = String::new();
-                for (i, point) in path_line.0.iter().enumerate() {
-                    let line = format!("    [{}, {}]{}", 
-                        point.x, point.y, 
-                        if i < path_line.0.len() - 1 { "," } else { "" }
-                    );
-                    geojson.push_str(&line);
-                    geojson.push('\n');
-                }
-                
-                info!("  {}]", geojson);
-                info!("}}");
+            }
+            // Only keep ways that are roads/paths
+            OsmObj::Way(way) if way.tags.contains_key("highway") => {
+                ways.push(way);
+            }
+            OsmObj::Relation(relation) if relation.tags.get("type").map(|v| v.as_str()) == Some("restriction") => {
+                relations.push(relation);
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+    let node_source: &dyn NodeSource = match &locations {
+        Some(locations) => locations,
+        None => &nodes,
+    };
+    if let Some(clip) = &clip {
+        (ways, relations) = clip_collected(ways, relations, clip, &inside);
+    }
+    let node_count = locations.as_ref().map_or(nodes.len(), |l| l.len());
+    progress.finish_with_message(format!("Collected {} nodes and {} ways", node_count, ways.len()));
+    info!("Collected {} nodes, {} ways and {} turn restrictions", node_count, ways.len(), relations.len());
+    
+    let speeds = load_speeds(args)?;
+    
+    let graphs = args
+        .profile
+        .iter()
+        .map(|&profile| {
+            let graph = build_graph(node_source, &ways, &relations, profile, &speeds, args.strict, progress_style)?;
+            Ok((profile, graph))
+        })
+        .collect::<Result<Vec<_>, BaseError>>()?;
+    
+    Ok(graphs)
+}
+
+// Process exit codes, following the BSD sysexits convention
+const EXIT_FAILURE: u8 = 1;
+const EXIT_DATA_ERROR: u8 = 65;
+const EXIT_NO_INPUT: u8 = 66;
+const EXIT_UNAVAILABLE: u8 = 69;
+const EXIT_IO_ERROR: u8 = 74;
+
+/// Maps an error to the process exit code reported for it.
+fn exit_code(err: &(dyn std::error::Error + 'static)) -> u8 {
+    if let Some(err) = err.downcast_ref::<BaseError>() {
+        return match err {
+            BaseError::FileNotFound { .. } => EXIT_NO_INPUT,
+            BaseError::PbfDecode { .. }
+            | BaseError::MissingNode { .. }
+            | BaseError::InvalidSpeedTable { .. } => EXIT_DATA_ERROR,
+            BaseError::FileRead { .. } | BaseError::NodeCache { .. } => EXIT_IO_ERROR,
+            BaseError::TokioPgError { .. } | BaseError::ReconnectError => EXIT_UNAVAILABLE,
+            _ => EXIT_FAILURE,
+        };
+    }
+    if let Some(err) = err.downcast_ref::<PreparedError>() {
+        return match err {
+            PreparedError::Read { source, .. } if source.kind() == std::io::ErrorKind::NotFound => EXIT_NO_INPUT,
+            PreparedError::Read { .. } => EXIT_IO_ERROR,
+            _ => EXIT_DATA_ERROR,
+        };
+    }
+    if let Some(err) = err.downcast_ref::<std::io::Error>() {
+        return match err.kind() {
+            std::io::ErrorKind::NotFound => EXIT_NO_INPUT,
+            _ => EXIT_IO_ERROR,
+        };
+    }
+    if let Some(err) = err.downcast_ref::<serde_json::Error>() {
+        return if err.is_io() { EXIT_IO_ERROR } else { EXIT_DATA_ERROR };
+    }
+    EXIT_FAILURE
+}
+
+#[instrument]
+fn main() -> ExitCode {
+    // Initialize tracing subscriber; logs go to stderr so `--output -` stays valid GeoJSON
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_target(false)
+        .with_writer(std::io::stderr)
+        .init();
+    
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            match err.downcast_ref::<BaseError>() {
+                Some(err) => err.report(),
+                None => error!("error: {}", err),
+            }
+            ExitCode::from(exit_code(err.as_ref()))
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    
+    let progress_style = ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
+        .unwrap();
+    
+    let mut loaded_hierarchies = Vec::new();
+    let graphs = match (&args.prepared, &args.import_graph, &args.input) {
+        (Some(path), _, _) => {
+            let prepared = prepared::read_prepared(path)?;
+            loaded_hierarchies.push(prepared.hierarchy);
+            vec![(prepared.profile, prepared.graph)]
+        }
+        (None, Some(path), _) => vec![export::read_graph(path)?],
+        (None, None, Some(input)) if needs_osm_store(args) => {
+            update::read_updated_graphs(input, args, &progress_style)?
+        }
+        (None, None, Some(input)) => read_pbf_graphs(input, args, &progress_style)?,
+        (None, None, None) => read_pg_graphs(args, &progress_style)?,
+    };
+    loaded_hierarchies.resize_with(graphs.len(), || None);
+    
+    let hierarchies = graphs
+        .iter()
+        .zip(loaded_hierarchies)
+        .map(|((_, graph), loaded)| {
+            match loaded {
+                Some((metric, hierarchy)) if metric == args.metric => return Some(hierarchy),
+                Some(_) => warn!("Prepared contraction hierarchy was built for another metric, ignoring it"),
+                None => {}
+            }
+            if !args.contract {
+                return None;
+            }
+            Some(ContractionHierarchy::build(graph, args.metric))
+        })
+        .collect::<Vec<_>>();
+    
+    if let Some(prepare_path) = &args.prepare {
+        for ((profile, graph), hierarchy) in graphs.iter().zip(&hierarchies) {
+            let path = if graphs.len() > 1 {
+                profile_export_path(prepare_path, *profile)
             } else {
-                warn!("No path found between the given points");
+                prepare_path.clone()
+            };
+            let hierarchy = hierarchy.as_ref().map(|h| (args.metric, h));
+            prepared::write_prepared(&path, *profile, graph, hierarchy)?;
+        }
+    }
+    
+    // If coordinates are provided, find path
+    if let (Some(start_lat), Some(start_lon), Some(end_lat), Some(end_lon)) = 
+       (args.start_lat, args.start_lon, args.end_lat, args.end_lon) {
+        let mut features = FeatureCollection::default();
+        let (from, to) = (Point::new(start_lon, start_lat), Point::new(end_lon, end_lat));
+        for ((profile, graph), hierarchy) in graphs.iter().zip(&hierarchies) {
+            info!("Routing with {} profile", profile.as_str());
+            let route = route(graph, hierarchy.as_ref(), args.metric, args.max_snap_distance, from, to);
+            if let Some(route) = route {
+                features.push_route(graph, *profile, args.metric, &route, from, to);
             }
-        } else {
-            error!("Could not find nearest nodes to the given coordinates");
+        }
+        
+        if let Some(output) = &args.output {
+            features.write(output)?;
+            info!("Wrote {} route features to {}", features.features.len(), output.display());
         }
     }
     
     // Export graph if requested
-    if let Some(export_path) = args.export_graph {
-        info!("Exporting graph to {}", export_path.display());
-        // Implementation for graph export...
+    if let Some(export_path) = &args.export_graph {
+        for (profile, graph) in &graphs {
+            let path = if graphs.len() > 1 {
+                profile_export_path(export_path, *profile)
+            } else {
+                export_path.clone()
+            };
+            info!("Exporting graph to {}", path.display());
+            export::write_graph(graph, *profile, &path)?;
+        }
+    }
+    
+    if args.pgrouting_pg.is_some() || args.pgrouting_sql.is_some() || args.pgrouting_csv.is_some() {
+        export_pgrouting(&graphs, args)?;
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use base::model::pbf_model::PbfWriter;
+
+    use super::*;
+    use crate::test_support::{tags, Fixture};
+
+    /// Pairs of OSM node ids joined by an edge, in travel direction.
+    fn edges(graph: &Graph) -> Vec<(i64, i64)> {
+        let mut edges = graph
+            .graph
+            .edge_weights()
+            .map(|edge| (edge.source.0, edge.target.0))
+            .collect::<Vec<_>>();
+        edges.sort();
+        edges
+    }
+
+    fn single_way_graph(pairs: &[(&str, &str)]) -> Graph {
+        let mut fixture = Fixture::default();
+        fixture.node(1, 100.0, 13.0).node(2, 100.001, 13.0).node(3, 100.002, 13.0);
+        fixture.way(1, &[1, 2, 3], pairs);
+        fixture.graph(Profile::Car)
+    }
+
+    #[test]
+    fn parses_oneway_values() {
+        for (pairs, expected) in [
+            (&[("oneway", "yes")][..], Oneway::Forward),
+            (&[("oneway", "true")], Oneway::Forward),
+            (&[("oneway", "1")], Oneway::Forward),
+            (&[("oneway", "-1")], Oneway::Backward),
+            (&[("oneway", "reverse")], Oneway::Backward),
+            (&[("oneway", "no")], Oneway::No),
+            (&[("oneway", "false")], Oneway::No),
+            (&[("oneway", "0")], Oneway::No),
+            (&[("oneway", "alternating")], Oneway::No),
+            (&[], Oneway::No),
+            (&[("junction", "roundabout")], Oneway::Forward),
+            (&[("junction", "circular")], Oneway::Forward),
+            // An explicit oneway=no wins over the roundabout default
+            (&[("junction", "roundabout"), ("oneway", "no")], Oneway::No),
+            (&[("junction", "roundabout"), ("oneway", "-1")], Oneway::Backward),
+        ] {
+            assert_eq!(Oneway::from_tags(&tags(pairs)), expected, "{:?}", pairs);
+        }
+    }
+
+    #[test]
+    fn builds_edges_in_the_allowed_directions() {
+        let forward = vec![(1, 2), (2, 3)];
+        let backward = vec![(2, 1), (3, 2)];
+        let both = vec![(1, 2), (2, 1), (2, 3), (3, 2)];
+        for (pairs, expected) in [
+            (&[("highway", "primary")][..], &both),
+            (&[("highway", "primary"), ("oneway", "yes")], &forward),
+            (&[("highway", "primary"), ("oneway", "-1")], &backward),
+            (&[("highway", "primary"), ("oneway", "reverse")], &backward),
+            (&[("highway", "primary"), ("oneway", "no")], &both),
+            (&[("highway", "primary"), ("junction", "roundabout")], &forward),
+            (&[("highway", "primary"), ("junction", "roundabout"), ("oneway", "no")], &both),
+        ] {
+            assert_eq!(&edges(&single_way_graph(pairs)), expected, "{:?}", pairs);
+        }
+    }
+
+    #[test]
+    fn never_routes_against_a_oneway() {
+        let graph = single_way_graph(&[("highway", "primary"), ("oneway", "-1")]);
+        let (one, three) = (graph.node_indices[&NodeId(1)], graph.node_indices[&NodeId(3)]);
+        assert!(graph.find_shortest_path(one, three, Metric::Distance).is_none());
+        let (path, _) = graph.find_shortest_path(three, one, Metric::Distance).unwrap();
+        assert_eq!(path.len(), 3);
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("main_{}_{}.osm.pbf", name, std::process::id()))
+    }
+
+    /// A PBF with nodes 1 and 2 and a residential way 10 over nodes 1, 2 and 3.
+    fn pbf_missing_node_3() -> Vec<u8> {
+        let mut writer = PbfWriter::new(Vec::new()).unwrap();
+        for (id, lon) in [(1, 100.0), (2, 100.001)] {
+            writer
+                .write(OsmObj::Node(osmpbfreader::Node {
+                    id: NodeId(id),
+                    tags: Tags::new(),
+                    decimicro_lat: 130_000_000,
+                    decimicro_lon: (lon * 1e7) as i32,
+                }))
+                .unwrap();
+        }
+        writer
+            .write(OsmObj::Way(Way {
+                id: WayId(10),
+                tags: tags(&[("highway", "residential")]),
+                nodes: vec![NodeId(1), NodeId(2), NodeId(3)],
+            }))
+            .unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn run_with(args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+        run(&Args::try_parse_from([&["open_rust_map"], args].concat()).unwrap())
+    }
+
+    #[test]
+    fn reports_a_missing_input_file() {
+        let path = temp_path("missing");
+        let error = run_with(&["--input", path.to_str().unwrap()]).unwrap_err();
+        assert!(
+            matches!(error.downcast_ref::<BaseError>(), Some(BaseError::FileNotFound { path: found, .. }) if *found == path),
+            "{:?}",
+            error
+        );
+        assert_eq!(exit_code(error.as_ref()), EXIT_NO_INPUT);
+    }
+
+    #[test]
+    fn reports_the_offset_of_a_corrupt_blob() {
+        let path = temp_path("corrupt");
+        let mut bytes = pbf_missing_node_3();
+        let valid = bytes.len() as u64;
+        // A four-byte BlobHeader that is not valid protobuf
+        bytes.extend_from_slice(&[0, 0, 0, 4, 0xff, 0xff, 0xff, 0xff]);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let error = run_with(&["--input", path.to_str().unwrap()]).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            matches!(error.downcast_ref::<BaseError>(), Some(BaseError::PbfDecode { offset, .. }) if *offset == valid),
+            "{:?}",
+            error
+        );
+        assert_eq!(exit_code(error.as_ref()), EXIT_DATA_ERROR);
+    }
+
+    #[test]
+    fn strict_builds_fail_on_missing_nodes() {
+        let path = temp_path("strict");
+        std::fs::write(&path, pbf_missing_node_3()).unwrap();
+        let input = path.to_str().unwrap();
+
+        let lenient = run_with(&["--input", input]);
+        let strict = run_with(&["--input", input, "--strict"]).unwrap_err();
+        let cache_dir = std::env::temp_dir();
+        let cache_dir = cache_dir.to_str().unwrap();
+        let cached = run_with(&["--input", input, "--strict", "--node-cache", cache_dir]).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(lenient.is_ok(), "{:?}", lenient);
+        for error in [strict, cached] {
+            assert!(
+                matches!(
+                    error.downcast_ref::<BaseError>(),
+                    Some(BaseError::MissingNode { way_id: 10, node_id: 3 })
+                ),
+                "{:?}",
+                error
+            );
+            assert_eq!(exit_code(error.as_ref()), EXIT_DATA_ERROR);
+        }
+    }
+
+    #[test]
+    fn maps_errors_to_sysexits_codes() {
+        let not_found = std::io::Error::new(std::io::ErrorKind::NotFound, "gone");
+        let denied = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        let cases: Vec<(Box<dyn std::error::Error>, u8)> = vec![
+            (Box::new(BaseError::MissingNode { way_id: 1, node_id: 2 }), EXIT_DATA_ERROR),
+            (Box::new(BaseError::ReconnectError), EXIT_UNAVAILABLE),
+            (Box::new(BaseError::Overflow), EXIT_FAILURE),
+            (Box::new(not_found), EXIT_NO_INPUT),
+            (Box::new(denied), EXIT_IO_ERROR),
+            (Box::new(serde_json::from_str::<u32>("x").unwrap_err()), EXIT_DATA_ERROR),
+            (Box::new(PreparedError::Truncated), EXIT_DATA_ERROR),
+            ("plain message".into(), EXIT_FAILURE),
+        ];
+        for (error, expected) in cases {
+            assert_eq!(exit_code(error.as_ref()), expected, "{:?}", error);
+        }
+    }
+}
@@ -42,16 +42,46 @@ struct Edge {
     highway_type: Option<String>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Oneway {
+    No,
+    Forward,
+    Backward,
+}
+
+impl Oneway {
+    fn from_tags(tags: &Tags) -> Self {
+        match tags.get("oneway").map(|v| v.as_str()) {
+            Some("yes") | Some("true") | Some("1") => return Oneway::Forward,
+            Some("-1") | Some("reverse") => return Oneway::Backward,
+            Some("no") | Some("false") | Some("0") => return Oneway::No,
+            _ => {}
+        }
+        match tags.get("junction").map(|v| v.as_str()) {
+            Some("roundabout") | Some("circular") => Oneway::Forward,
+            _ => Oneway::No,
+        }
+    }
+
+    fn allows_forward(self) -> bool {
+        self != Oneway::Backward
+    }
+
+    fn allows_backward(self) -> bool {
+        self != Oneway::Forward
+    }
+}
+
 #[derive(Debug)]
 struct Graph {
-    graph: UnGraph<Node, Edge>,
+    graph: DiGraph<Node, Edge>,
     node_indices: HashMap<NodeId, NodeIndex>,
 }
 
 impl Graph {
     fn new() -> Self {
         Graph {
-            graph: UnGraph::new_undirected(),
+            graph: DiGraph::new(),
             node_indices: HashMap::new(),
         }
     }
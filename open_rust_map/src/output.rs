@@ -0,0 +1,233 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use geo_types::Point;
+use serde::Serialize;
+
+use crate::profile::Profile;
+use crate::snap::{Route, Snap};
+use crate::{Graph, Metric};
+
+/// GeoJSON `FeatureCollection` written by `--output`.
+#[derive(Debug, Default, Serialize)]
+#[serde(tag = "type", rename = "FeatureCollection")]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename = "Feature")]
+pub struct Feature {
+    pub geometry: Geometry,
+    pub properties: Properties,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum Geometry {
+    Point([f64; 2]),
+    LineString(Vec<[f64; 2]>),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Properties {
+    Route {
+        profile: Profile,
+        metric: &'static str,
+        /// Metres
+        distance: f64,
+        /// Seconds
+        duration: f64,
+        node_count: usize,
+        way_ids: Vec<i64>,
+    },
+    Start {
+        profile: Profile,
+        query: [f64; 2],
+        snap_distance: f64,
+    },
+    End {
+        profile: Profile,
+        query: [f64; 2],
+        snap_distance: f64,
+    },
+}
+
+fn coordinates(point: Point<f64>) -> [f64; 2] {
+    [point.x(), point.y()]
+}
+
+impl FeatureCollection {
+    /// Adds the route line and its snapped start and end points.
+    pub fn push_route(
+        &mut self,
+        graph: &Graph,
+        profile: Profile,
+        metric: Metric,
+        route: &Route,
+        start_query: Point<f64>,
+        end_query: Point<f64>,
+    ) {
+        let line = route.points(graph).into_iter().map(coordinates).collect();
+        self.features.push(Feature {
+            geometry: Geometry::LineString(line),
+            properties: Properties::Route {
+                profile,
+                metric: match metric {
+                    Metric::Distance => "distance",
+                    Metric::Duration => "duration",
+                },
+                distance: route.distance,
+                duration: route.duration,
+                node_count: route.path.len(),
+                way_ids: way_ids(graph, metric, route),
+            },
+        });
+        self.features.push(snap_feature(&route.start, Properties::Start {
+            profile,
+            query: coordinates(start_query),
+            snap_distance: route.start.distance,
+        }));
+        self.features.push(snap_feature(&route.end, Properties::End {
+            profile,
+            query: coordinates(end_query),
+            snap_distance: route.end.distance,
+        }));
+    }
+
+    /// Writes the collection to `path`, or to stdout when `path` is `-`.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let writer: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(io::stdout().lock())
+        } else {
+            Box::new(File::create(path)?)
+        };
+        let mut writer = BufWriter::new(writer);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+}
+
+fn snap_feature(snap: &Snap, properties: Properties) -> Feature {
+    Feature {
+        geometry: Geometry::Point(coordinates(snap.point)),
+        properties,
+    }
+}
+
+/// OSM way ids traversed by the route, in order and without consecutive repeats.
+fn way_ids(graph: &Graph, metric: Metric, route: &Route) -> Vec<i64> {
+    let mut ids = vec![graph.graph[route.start.edge].way_id.0];
+    ids.extend(graph.path_edges(&route.path, metric).iter().map(|edge| edge.way_id.0));
+    ids.push(graph.graph[route.end.edge].way_id.0);
+    ids.dedup();
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::Fixture;
+
+    /// Ways 10 (east along the equator) and 20 (north along 1°E), both oneway, with
+    /// coordinates that project exactly so the snapped points can be compared literally.
+    fn graph() -> Graph {
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, 0.0, 0.0)
+            .node(2, 1.0, 0.0)
+            .node(3, 1.0, 1.0)
+            .way(10, &[1, 2], &[("highway", "primary"), ("oneway", "yes")])
+            .way(20, &[2, 3], &[("highway", "residential"), ("oneway", "yes")]);
+        fixture.graph(Profile::Car)
+    }
+
+    fn route(graph: &Graph, from: Point<f64>, to: Point<f64>) -> Route {
+        let start = graph.snap(from.y(), from.x(), None).unwrap();
+        let end = graph.snap(to.y(), to.x(), None).unwrap();
+        graph.route_between(None, &start, &end, Metric::Duration).unwrap()
+    }
+
+    #[test]
+    fn writes_route_and_snapped_points_in_lon_lat_order() {
+        let graph = graph();
+        let (from, to) = (Point::new(0.25, 0.001), Point::new(1.001, 0.5));
+        let route = route(&graph, from, to);
+        let mut features = FeatureCollection::default();
+        features.push_route(&graph, Profile::Car, Metric::Duration, &route, from, to);
+
+        assert_eq!(
+            serde_json::to_value(&features).unwrap(),
+            json!({
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "geometry": { "type": "LineString", "coordinates": [[0.25, 0.0], [1.0, 0.0], [1.0, 0.5]] },
+                        "properties": {
+                            "kind": "route",
+                            "profile": "car",
+                            "metric": "duration",
+                            "distance": route.distance,
+                            "duration": route.duration,
+                            "node_count": 1,
+                            "way_ids": [10, 20]
+                        }
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": { "type": "Point", "coordinates": [0.25, 0.0] },
+                        "properties": {
+                            "kind": "start",
+                            "profile": "car",
+                            "query": [0.25, 0.001],
+                            "snap_distance": route.start.distance
+                        }
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": { "type": "Point", "coordinates": [1.0, 0.5] },
+                        "properties": {
+                            "kind": "end",
+                            "profile": "car",
+                            "query": [1.001, 0.5],
+                            "snap_distance": route.end.distance
+                        }
+                    }
+                ]
+            })
+        );
+        assert!((route.start.distance - 110.6).abs() < 0.1, "{}", route.start.distance);
+    }
+
+    #[test]
+    fn writes_a_route_within_one_edge_without_nodes() {
+        let graph = graph();
+        let (from, to) = (Point::new(0.25, 0.0), Point::new(0.5, 0.0));
+        let route = route(&graph, from, to);
+        let mut features = FeatureCollection::default();
+        features.push_route(&graph, Profile::Car, Metric::Duration, &route, from, to);
+
+        let value = serde_json::to_value(&features).unwrap();
+        assert_eq!(
+            value["features"][0]["geometry"],
+            json!({ "type": "LineString", "coordinates": [[0.25, 0.0], [0.5, 0.0]] })
+        );
+        assert_eq!(value["features"][0]["properties"]["node_count"], json!(0));
+        assert_eq!(value["features"][0]["properties"]["way_ids"], json!([10]));
+    }
+
+    #[test]
+    fn writes_an_empty_collection_when_no_route_is_found() {
+        let path = std::env::temp_dir().join(format!("output_empty_{}.geojson", std::process::id()));
+        FeatureCollection::default().write(&path).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, "{\n  \"type\": \"FeatureCollection\",\n  \"features\": []\n}\n");
+    }
+}
@@ -0,0 +1,107 @@
+//! pgRouting export of a routing [`Graph`].
+//!
+//! Writes the `ways` and `ways_vertices_pgr` tables that `pgr_dijkstra` and friends expect,
+//! so the database router works on the same network as this one:
+//!
+//! - Vertices are numbered from 1 in graph order; `osm_id` keeps the OSM node id.
+//! - A road segment becomes one `ways` row. Its `cost` runs `source` → `target` and its
+//!   `reverse_cost` runs back, each in the unit of `--metric` (metres or seconds) and
+//!   `-1` where the profile forbids that direction.
+//! - Turn restrictions are not exported.
+//!
+//! ```sql
+//! SELECT * FROM pgr_dijkstra('SELECT gid AS id, source, target, cost, reverse_cost FROM osm.ways', 1, 42);
+//! ```
+
+use std::collections::HashMap;
+
+use base::model::pg_model::{PgRouting, PgRoutingEdge, PgRoutingVertex};
+use osmpbfreader::WayId;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+
+use crate::{Graph, Metric};
+
+/// Collapses the directed edges of `graph` into pgRouting rows weighted by `metric`.
+pub fn to_pgrouting(graph: &Graph, metric: Metric) -> PgRouting {
+    let vertex_id = |idx: NodeIndex| idx.index() as i64 + 1;
+    let vertices = graph
+        .graph
+        .node_indices()
+        .map(|idx| {
+            let node = &graph.graph[idx];
+            PgRoutingVertex {
+                id: vertex_id(idx),
+                osm_id: node.id.0,
+                lon: node.point.x(),
+                lat: node.point.y(),
+            }
+        })
+        .collect();
+
+    // A two-way segment is two opposite edges of the same way; the second one seen
+    // fills in the reverse cost of the row created for the first
+    let mut edges: Vec<PgRoutingEdge> = Vec::new();
+    let mut rows: HashMap<(WayId, NodeIndex, NodeIndex), usize> = HashMap::new();
+    for edge in graph.graph.edge_references() {
+        let weight = edge.weight();
+        if let Some(&row) = rows.get(&(weight.way_id, edge.target(), edge.source())) {
+            if edges[row].reverse_cost < 0.0 {
+                edges[row].reverse_cost = weight.weight(metric);
+                continue;
+            }
+        }
+        rows.insert((weight.way_id, edge.source(), edge.target()), edges.len());
+        let source = &graph.graph[edge.source()];
+        let target = &graph.graph[edge.target()];
+        edges.push(PgRoutingEdge {
+            gid: edges.len() as i64 + 1,
+            osm_id: weight.way_id.0,
+            source: vertex_id(edge.source()),
+            target: vertex_id(edge.target()),
+            cost: weight.weight(metric),
+            reverse_cost: -1.0,
+            length_m: weight.distance,
+            x1: source.point.x(),
+            y1: source.point.y(),
+            x2: target.point.x(),
+            y2: target.point.y(),
+        });
+    }
+    PgRouting { vertices, edges }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::Profile;
+    use crate::test_support::Fixture;
+
+    #[test]
+    fn collapses_two_way_and_oneway_segments() {
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, 100.000, 13.0)
+            .node(2, 100.001, 13.0)
+            .node(3, 100.002, 13.0)
+            .way(10, &[1, 2], &[("highway", "residential")])
+            .way(11, &[2, 3], &[("highway", "residential"), ("oneway", "yes")]);
+        let graph = fixture.graph(Profile::Car);
+        let tables = to_pgrouting(&graph, Metric::Distance);
+
+        assert_eq!(tables.vertices.len(), 3);
+        assert_eq!(tables.edges.len(), 2);
+        let osm_vertex = |id: i64| tables.vertices.iter().find(|v| v.osm_id == id).unwrap().id;
+
+        let two_way = tables.edges.iter().find(|e| e.osm_id == 10).unwrap();
+        assert!(two_way.cost > 0.0);
+        assert_eq!(two_way.reverse_cost, two_way.cost);
+        assert_eq!(two_way.length_m, two_way.cost);
+
+        let oneway = tables.edges.iter().find(|e| e.osm_id == 11).unwrap();
+        assert_eq!((oneway.source, oneway.target), (osm_vertex(2), osm_vertex(3)));
+        assert!(oneway.cost > 0.0);
+        assert_eq!(oneway.reverse_cost, -1.0);
+        assert_eq!((oneway.x1, oneway.x2), (100.001, 100.002));
+    }
+}
@@ -0,0 +1,700 @@
+//! Compact binary file holding a built routing graph and its speed-up structures.
+//!
+//! All integers and floats are little-endian. The layout is:
+//!
+//! | section       | contents                                                                  |
+//! |---------------|---------------------------------------------------------------------------|
+//! | header        | magic `ORMGRAPH`, version `u32`, profile `u8`, metric `u8`, has-CH `u8`    |
+//! | highway table | count `u32`, then `u16` length + UTF-8 bytes per `highway` value          |
+//! | nodes         | count `u64`, then OSM id `i64`, lon `f64`, lat `f64` in `NodeIndex` order  |
+//! | edges         | count `u64`, then source `u32`, target `u32` (node indices), distance `f64`, |
+//! |               | duration `f64`, way id `i64`, highway `u16` (`u16::MAX` = none)           |
+//! | restrictions  | count `u32`, then from `i64`, to `i64`, kind `u8`, via-node flag `u8`,    |
+//! |               | via node `i64` or via-way count `u32` + way ids `i64`                      |
+//! | hierarchy     | only when has-CH: count `u64`, then from `u32`, to `u32`, weight `f64`,   |
+//! |               | flags `u8` (1 = upward, 2 = shortcut), edge index or middle node `u32`;   |
+//! |               | then state count `u64` and edge index `u32` per turn-expanded node        |
+//! | trailer       | CRC-32 `u32` of every preceding byte                                      |
+//!
+//! Node tags are not stored. Files are memory-mapped on load and the checksum is verified
+//! before anything is decoded.
+//!
+//! The file stores what is expensive to derive: the graph without the PBF parse and the
+//! hierarchy without contraction. The petgraph adjacency, the OSM id to `NodeIndex` map and
+//! the segment R-tree are rebuilt on load instead of being read in place. petgraph owns its
+//! node and edge vectors, so it cannot borrow the mapping. The id map costs one hash insert
+//! per node, and the R-tree is bulk-loaded in O(n log n) over the edges just decoded. Both
+//! are single passes over data already in memory, a small fraction of parsing or contracting.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
+use osmpbfreader::{NodeId, Tags, WayId};
+use petgraph::graph::NodeIndex;
+use snafu::prelude::*;
+use tracing::info;
+
+use crate::ch::{ArcRecord, ContractionHierarchy};
+use crate::profile::Profile;
+use crate::restriction::{RestrictionKind, RestrictionRecord, TurnRestrictions};
+use crate::{Edge, Graph, Metric, Node};
+
+const MAGIC: &[u8; 8] = b"ORMGRAPH";
+pub const VERSION: u32 = 2;
+
+const ARC_UPWARD: u8 = 1;
+const ARC_SHORTCUT: u8 = 2;
+const NO_HIGHWAY: u16 = u16::MAX;
+
+type BoxError = Box<dyn std::error::Error>;
+
+/// Why a prepared file could not be loaded.
+#[derive(Debug, Snafu)]
+pub enum PreparedError {
+    #[snafu(display("unable to read {}", path.display()))]
+    Read { path: PathBuf, source: io::Error },
+    #[snafu(display("{} is not a prepared graph file", path.display()))]
+    NotPrepared { path: PathBuf },
+    #[snafu(display("checksum mismatch in {}: expected {expected:08x}, got {actual:08x}", path.display()))]
+    ChecksumMismatch { path: PathBuf, expected: u32, actual: u32 },
+    #[snafu(display("unsupported prepared graph version {version}"))]
+    UnsupportedVersion { version: u32 },
+    #[snafu(display("prepared graph file is truncated"))]
+    Truncated,
+    #[snafu(display("{message}"))]
+    Malformed { message: String },
+}
+
+fn malformed(message: impl Into<String>) -> PreparedError {
+    PreparedError::Malformed {
+        message: message.into(),
+    }
+}
+
+/// A graph loaded from a prepared file, with its hierarchy when one was stored.
+pub struct Prepared {
+    pub profile: Profile,
+    pub graph: Graph,
+    pub hierarchy: Option<(Metric, ContractionHierarchy)>,
+}
+
+fn profile_code(profile: Profile) -> u8 {
+    match profile {
+        Profile::Car => 0,
+        Profile::Bicycle => 1,
+        Profile::Foot => 2,
+        Profile::Motorcycle => 3,
+    }
+}
+
+fn profile_from_code(code: u8) -> Result<Profile, PreparedError> {
+    Ok(match code {
+        0 => Profile::Car,
+        1 => Profile::Bicycle,
+        2 => Profile::Foot,
+        3 => Profile::Motorcycle,
+        _ => return Err(malformed(format!("unknown profile code {}", code))),
+    })
+}
+
+fn metric_code(metric: Metric) -> u8 {
+    match metric {
+        Metric::Distance => 0,
+        Metric::Duration => 1,
+    }
+}
+
+fn metric_from_code(code: u8) -> Result<Metric, PreparedError> {
+    Ok(match code {
+        0 => Metric::Distance,
+        1 => Metric::Duration,
+        _ => return Err(malformed(format!("unknown metric code {}", code))),
+    })
+}
+
+/// Writes `graph`, its turn restrictions and optionally a contraction hierarchy to `path`.
+pub fn write_prepared(
+    path: &Path,
+    profile: Profile,
+    graph: &Graph,
+    hierarchy: Option<(Metric, &ContractionHierarchy)>,
+) -> Result<(), BoxError> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&VERSION.to_le_bytes());
+    buf.push(profile_code(profile));
+    buf.push(hierarchy.map_or(0, |(metric, _)| metric_code(metric)));
+    buf.push(hierarchy.is_some() as u8);
+
+    let mut highways: Vec<&str> = Vec::new();
+    let mut highway_codes: HashMap<&str, u16> = HashMap::new();
+    for edge in graph.graph.edge_weights() {
+        if let Some(highway) = edge.highway_type.as_deref() {
+            if !highway_codes.contains_key(highway) {
+                // `NO_HIGHWAY` is reserved, so the last code is `u16::MAX - 1`
+                if highways.len() >= NO_HIGHWAY as usize {
+                    return Err(format!("more than {} distinct highway values", NO_HIGHWAY).into());
+                }
+                highway_codes.insert(highway, highways.len() as u16);
+                highways.push(highway);
+            }
+        }
+    }
+    buf.extend_from_slice(&(highways.len() as u32).to_le_bytes());
+    for highway in &highways {
+        let len = u16::try_from(highway.len()).map_err(|_| format!("highway value too long: {}", highway))?;
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(highway.as_bytes());
+    }
+
+    buf.extend_from_slice(&(graph.graph.node_count() as u64).to_le_bytes());
+    for node in graph.graph.node_weights() {
+        buf.extend_from_slice(&node.id.0.to_le_bytes());
+        buf.extend_from_slice(&node.point.x().to_le_bytes());
+        buf.extend_from_slice(&node.point.y().to_le_bytes());
+    }
+
+    buf.extend_from_slice(&(graph.graph.edge_count() as u64).to_le_bytes());
+    for edge in graph.graph.edge_indices() {
+        let (source, target) = graph.graph.edge_endpoints(edge).ok_or("dangling edge")?;
+        let weight = &graph.graph[edge];
+        buf.extend_from_slice(&(source.index() as u32).to_le_bytes());
+        buf.extend_from_slice(&(target.index() as u32).to_le_bytes());
+        buf.extend_from_slice(&weight.distance.to_le_bytes());
+        buf.extend_from_slice(&weight.duration.to_le_bytes());
+        buf.extend_from_slice(&weight.way_id.0.to_le_bytes());
+        let highway = weight
+            .highway_type
+            .as_deref()
+            .map_or(NO_HIGHWAY, |h| highway_codes[h]);
+        buf.extend_from_slice(&highway.to_le_bytes());
+    }
+
+    let restrictions = graph.restrictions.records();
+    buf.extend_from_slice(&(restrictions.len() as u32).to_le_bytes());
+    for record in &restrictions {
+        let (from, to, kind) = match record {
+            RestrictionRecord::ViaNode { from, to, kind, .. }
+            | RestrictionRecord::ViaWays { from, to, kind, .. } => (from, to, kind),
+        };
+        buf.extend_from_slice(&from.0.to_le_bytes());
+        buf.extend_from_slice(&to.0.to_le_bytes());
+        buf.push(match kind {
+            RestrictionKind::No => 0,
+            RestrictionKind::Only => 1,
+        });
+        match record {
+            RestrictionRecord::ViaNode { via, .. } => {
+                buf.push(1);
+                buf.extend_from_slice(&via.0.to_le_bytes());
+            }
+            RestrictionRecord::ViaWays { via, .. } => {
+                buf.push(0);
+                buf.extend_from_slice(&(via.len() as u32).to_le_bytes());
+                for way in via {
+                    buf.extend_from_slice(&way.0.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    if let Some((_, hierarchy)) = hierarchy {
+        let arcs = hierarchy.arc_records();
+        buf.extend_from_slice(&(arcs.len() as u64).to_le_bytes());
+        for arc in &arcs {
+            buf.extend_from_slice(&arc.from.to_le_bytes());
+            buf.extend_from_slice(&arc.to.to_le_bytes());
+            buf.extend_from_slice(&arc.weight.to_le_bytes());
+            let mut flags = 0;
+            if arc.upward {
+                flags |= ARC_UPWARD;
+            }
+            if arc.middle.is_some() {
+                flags |= ARC_SHORTCUT;
+            }
+            buf.push(flags);
+            let payload = arc.original.or(arc.middle).unwrap_or(u32::MAX);
+            buf.extend_from_slice(&payload.to_le_bytes());
+        }
+        let state_edges = hierarchy.state_edges();
+        buf.extend_from_slice(&(state_edges.len() as u64).to_le_bytes());
+        for edge in state_edges {
+            buf.extend_from_slice(&edge.to_le_bytes());
+        }
+    }
+
+    let checksum = crc32fast::hash(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&buf)?;
+    writer.flush()?;
+    info!(
+        "Prepared {} graph written to {} ({} bytes)",
+        profile.as_str(),
+        path.display(),
+        buf.len()
+    );
+    Ok(())
+}
+
+/// Sequential little-endian reader over the mapped file.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], PreparedError> {
+        let end = self.pos.checked_add(len).context(TruncatedSnafu)?;
+        let slice = self.bytes.get(self.pos..end).context(TruncatedSnafu)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], PreparedError> {
+        Ok(self.take(N)?.try_into().expect("take returns the requested length"))
+    }
+
+    fn u8(&mut self) -> Result<u8, PreparedError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, PreparedError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, PreparedError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, PreparedError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, PreparedError> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, PreparedError> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+}
+
+/// Memory-maps a prepared file, verifies its checksum and rebuilds the graph.
+pub fn read_prepared(path: &Path) -> Result<Prepared, PreparedError> {
+    let file = File::open(path).context(ReadSnafu { path })?;
+    // SAFETY: the mapping is read-only and dropped before this function returns
+    let mmap = unsafe { Mmap::map(&file).context(ReadSnafu { path })? };
+    ensure!(
+        mmap.len() >= MAGIC.len() + 4 && &mmap[..MAGIC.len()] == MAGIC,
+        NotPreparedSnafu { path }
+    );
+    let (body, trailer) = mmap.split_at(mmap.len() - 4);
+    let expected = u32::from_le_bytes(trailer.try_into().expect("trailer is four bytes"));
+    let actual = crc32fast::hash(body);
+    ensure!(
+        expected == actual,
+        ChecksumMismatchSnafu {
+            path,
+            expected,
+            actual
+        }
+    );
+
+    let mut cursor = Cursor {
+        bytes: body,
+        pos: MAGIC.len(),
+    };
+    let version = cursor.u32()?;
+    ensure!(version == VERSION, UnsupportedVersionSnafu { version });
+    let profile = profile_from_code(cursor.u8()?)?;
+    let metric = metric_from_code(cursor.u8()?)?;
+    let has_hierarchy = cursor.u8()? != 0;
+
+    let highway_count = cursor.u32()? as usize;
+    let mut highways = Vec::with_capacity(highway_count);
+    for _ in 0..highway_count {
+        let len = cursor.u16()? as usize;
+        let highway =
+            std::str::from_utf8(cursor.take(len)?).map_err(|_| malformed("highway value is not UTF-8"))?;
+        highways.push(highway.to_string());
+    }
+
+    // Fill the graph directly rather than through `add_node`/`add_edge`, which trace every item
+    // and look endpoints up by OSM id
+    let mut graph = Graph::new();
+    let node_count = cursor.u64()? as usize;
+    graph.graph.reserve_nodes(node_count);
+    graph.node_indices.reserve(node_count);
+    for _ in 0..node_count {
+        let id = NodeId(cursor.i64()?);
+        let lon = cursor.f64()?;
+        let lat = cursor.f64()?;
+        let idx = graph.graph.add_node(Node {
+            id,
+            point: geo_types::Point::new(lon, lat),
+            tags: Tags::new(),
+        });
+        graph.node_indices.insert(id, idx);
+    }
+
+    let edge_count = cursor.u64()? as usize;
+    graph.graph.reserve_edges(edge_count);
+    for _ in 0..edge_count {
+        let source = NodeIndex::new(cursor.u32()? as usize);
+        let target = NodeIndex::new(cursor.u32()? as usize);
+        let distance = cursor.f64()?;
+        let duration = cursor.f64()?;
+        let way_id = WayId(cursor.i64()?);
+        let highway_type = match cursor.u16()? {
+            NO_HIGHWAY => None,
+            code => Some(
+                highways
+                    .get(code as usize)
+                    .ok_or_else(|| malformed(format!("unknown highway code {}", code)))?
+                    .clone(),
+            ),
+        };
+        let source_id = graph
+            .graph
+            .node_weight(source)
+            .ok_or_else(|| malformed("edge source out of range"))?
+            .id;
+        let target_id = graph
+            .graph
+            .node_weight(target)
+            .ok_or_else(|| malformed("edge target out of range"))?
+            .id;
+        if duration > 0.0 {
+            graph.max_speed = graph.max_speed.max(distance / duration);
+        }
+        graph.graph.add_edge(
+            source,
+            target,
+            Edge {
+                source: source_id,
+                target: target_id,
+                distance,
+                duration,
+                way_id,
+                highway_type,
+            },
+        );
+    }
+
+    let restriction_count = cursor.u32()? as usize;
+    let mut restrictions = Vec::with_capacity(restriction_count);
+    for _ in 0..restriction_count {
+        let from = WayId(cursor.i64()?);
+        let to = WayId(cursor.i64()?);
+        let kind = match cursor.u8()? {
+            0 => RestrictionKind::No,
+            1 => RestrictionKind::Only,
+            code => return Err(malformed(format!("unknown restriction kind {}", code))),
+        };
+        match cursor.u8()? {
+            1 => {
+                let via = NodeId(cursor.i64()?);
+                restrictions.push(RestrictionRecord::ViaNode { from, via, to, kind });
+            }
+            0 => {
+                let count = cursor.u32()? as usize;
+                let via = (0..count)
+                    .map(|_| cursor.i64().map(WayId))
+                    .collect::<Result<Vec<_>, _>>()?;
+                restrictions.push(RestrictionRecord::ViaWays { from, via, to, kind });
+            }
+            flag => return Err(malformed(format!("invalid via-node flag {}", flag))),
+        }
+    }
+    graph.restrictions = TurnRestrictions::from_records(restrictions);
+
+    let hierarchy = if has_hierarchy {
+        let arc_count = cursor.u64()? as usize;
+        let mut arcs = Vec::with_capacity(arc_count);
+        for _ in 0..arc_count {
+            let from = cursor.u32()?;
+            let to = cursor.u32()?;
+            let weight = cursor.f64()?;
+            let flags = cursor.u8()?;
+            let payload = cursor.u32()?;
+            let shortcut = flags & ARC_SHORTCUT != 0;
+            arcs.push(ArcRecord {
+                from,
+                to,
+                weight,
+                upward: flags & ARC_UPWARD != 0,
+                original: (!shortcut).then_some(payload),
+                middle: shortcut.then_some(payload),
+            });
+        }
+        let state_count = cursor.u64()? as usize;
+        let state_edges = (0..state_count).map(|_| cursor.u32()).collect::<Result<Vec<_>, _>>()?;
+        if state_edges.is_empty() != graph.restrictions.is_empty() {
+            return Err(malformed("contraction hierarchy does not match the graph's turn restrictions"));
+        }
+        let hierarchy_nodes = if state_edges.is_empty() { node_count } else { state_count };
+        if state_edges.iter().any(|&edge| edge as usize >= edge_count) {
+            return Err(malformed("hierarchy state refers to an edge out of range"));
+        }
+        let out_of_range = |arc: &ArcRecord| {
+            [Some(arc.from), Some(arc.to), arc.middle]
+                .into_iter()
+                .flatten()
+                .any(|node| node as usize >= hierarchy_nodes)
+                || arc.original.is_some_and(|edge| edge as usize >= edge_count)
+        };
+        if arcs.iter().any(out_of_range) {
+            return Err(malformed("hierarchy arc refers to a node or edge out of range"));
+        }
+        Some((metric, ContractionHierarchy::from_arc_records(node_count, arcs, state_edges)))
+    } else {
+        None
+    };
+
+    graph.build_index();
+    info!(
+        "Loaded prepared {} graph with {} nodes and {} edges from {}",
+        profile.as_str(),
+        graph.graph.node_count(),
+        graph.graph.edge_count(),
+        path.display()
+    );
+    Ok(Prepared {
+        profile,
+        graph,
+        hierarchy,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::OsmId;
+
+    use super::*;
+    use crate::test_support::Fixture;
+
+    fn fixture() -> Fixture {
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, 100.000, 13.000)
+            .node(2, 100.001, 13.000)
+            .node(3, 100.001, 13.001)
+            .way(10, &[1, 2], &[("highway", "residential")])
+            .way(30, &[2, 3], &[("highway", "residential"), ("oneway", "yes")])
+            .relation(
+                1,
+                &[
+                    (OsmId::Way(WayId(10)), "from"),
+                    (OsmId::Node(NodeId(2)), "via"),
+                    (OsmId::Way(WayId(30)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "no_left_turn")],
+            );
+        fixture
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("prepared_{}_{}.ormgraph", name, std::process::id()))
+    }
+
+    #[test]
+    fn round_trips_graph_and_restrictions() {
+        let graph = fixture().graph(Profile::Car);
+        let path = temp_path("round_trip");
+        write_prepared(&path, Profile::Car, &graph, None).unwrap();
+        let prepared = read_prepared(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(prepared.profile, Profile::Car);
+        assert!(prepared.hierarchy.is_none());
+        let loaded = prepared.graph;
+        assert_eq!(loaded.graph.node_count(), graph.graph.node_count());
+        assert_eq!(loaded.graph.edge_count(), graph.graph.edge_count());
+        for (a, b) in graph.graph.node_weights().zip(loaded.graph.node_weights()) {
+            assert_eq!((a.id, a.point), (b.id, b.point));
+            assert_eq!(loaded.node_indices[&a.id], graph.node_indices[&a.id]);
+        }
+        for edge in graph.graph.edge_indices() {
+            assert_eq!(graph.graph.edge_endpoints(edge), loaded.graph.edge_endpoints(edge));
+            let (a, b) = (&graph.graph[edge], &loaded.graph[edge]);
+            assert_eq!((a.source, a.target, a.way_id), (b.source, b.target, b.way_id));
+            assert_eq!((a.distance, a.duration), (b.distance, b.duration));
+            assert_eq!(a.highway_type, b.highway_type);
+        }
+        assert_eq!(loaded.max_speed, graph.max_speed);
+        assert_eq!(loaded.restrictions.records().len(), 1);
+    }
+
+    #[test]
+    fn rejects_unknown_restriction_kind() {
+        let graph = fixture().graph(Profile::Car);
+        let path = temp_path("bad_kind");
+        write_prepared(&path, Profile::Car, &graph, None).unwrap();
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        let highway_table = 4 + 2 + "residential".len();
+        let nodes = 8 + 24 * graph.graph.node_count();
+        let edges = 8 + 34 * graph.graph.edge_count();
+        // Header, tables, restriction count, then the from and to way ids
+        let kind = MAGIC.len() + 4 + 3 + highway_table + nodes + edges + 4 + 16;
+        assert_eq!(bytes[kind], 0);
+        bytes[kind] = 7;
+        let body = bytes.len() - 4;
+        let checksum = crc32fast::hash(&bytes[..body]);
+        bytes[body..].copy_from_slice(&checksum.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+
+        let error = read_prepared(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(error, PreparedError::Malformed { .. }), "{:?}", error);
+        assert_eq!(error.to_string(), "unknown restriction kind 7");
+    }
+
+    #[test]
+    fn rejects_too_many_highway_values() {
+        let mut graph = fixture().graph(Profile::Car);
+        let (a, b) = (graph.node_indices[&NodeId(1)], graph.node_indices[&NodeId(2)]);
+        for i in 0..NO_HIGHWAY as usize {
+            graph.graph.add_edge(
+                a,
+                b,
+                Edge {
+                    source: NodeId(1),
+                    target: NodeId(2),
+                    distance: 1.0,
+                    duration: 1.0,
+                    way_id: WayId(10),
+                    highway_type: Some(format!("value_{}", i)),
+                },
+            );
+        }
+        let path = temp_path("highways");
+        let error = write_prepared(&path, Profile::Car, &graph, None).err().unwrap();
+        assert!(!path.exists());
+        assert!(error.to_string().starts_with("more than"));
+    }
+
+    /// Rewrites the file after `edit` and fixes up the checksum.
+    fn rewrite(path: &Path, edit: impl FnOnce(&mut Vec<u8>)) {
+        let mut bytes = std::fs::read(path).unwrap();
+        edit(&mut bytes);
+        let body = bytes.len() - 4;
+        let checksum = crc32fast::hash(&bytes[..body]);
+        bytes[body..].copy_from_slice(&checksum.to_le_bytes());
+        std::fs::write(path, &bytes).unwrap();
+    }
+
+    fn assert_same_hierarchy(
+        graph: &Graph,
+        a: &ContractionHierarchy,
+        b: &ContractionHierarchy,
+        metric: Metric,
+    ) {
+        assert_eq!(a.state_edges(), b.state_edges());
+        let key = |arc: &ArcRecord| (arc.from, arc.to, arc.upward);
+        let mut expected = a.arc_records();
+        let mut actual = b.arc_records();
+        expected.sort_by_key(key);
+        actual.sort_by_key(key);
+        assert_eq!(expected.len(), actual.len());
+        for (x, y) in expected.iter().zip(&actual) {
+            assert_eq!((x.from, x.to, x.upward), (y.from, y.to, y.upward));
+            assert_eq!((x.original, x.middle, x.weight), (y.original, y.middle, y.weight));
+        }
+        for exit in graph.graph.edge_indices() {
+            for entry in graph.graph.edge_indices() {
+                assert_eq!(
+                    a.find_path_between(graph, exit, entry, metric),
+                    b.find_path_between(graph, exit, entry, metric)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_node_and_turn_based_hierarchies() {
+        let restricted = fixture().graph(Profile::Car);
+        let mut unrestricted = fixture().graph(Profile::Car);
+        unrestricted.restrictions = TurnRestrictions::default();
+
+        for (graph, turn_aware) in [(unrestricted, false), (restricted, true)] {
+            let hierarchy = ContractionHierarchy::build(&graph, Metric::Duration);
+            assert_eq!(hierarchy.is_turn_aware(), turn_aware);
+            let path = temp_path("hierarchy");
+            write_prepared(&path, Profile::Car, &graph, Some((Metric::Duration, &hierarchy))).unwrap();
+            let prepared = read_prepared(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            let (metric, loaded) = prepared.hierarchy.expect("hierarchy is stored");
+            assert_eq!(metric, Metric::Duration);
+            assert_eq!(loaded.is_turn_aware(), turn_aware);
+            assert_same_hierarchy(&prepared.graph, &hierarchy, &loaded, metric);
+            assert_eq!(prepared.graph.index.nearest_edges(&prepared.graph, 13.0005, 100.001, 1).len(), 1);
+        }
+    }
+
+    #[test]
+    fn rejects_a_hierarchy_that_does_not_match_the_restrictions() {
+        let mut graph = fixture().graph(Profile::Car);
+        let restrictions = std::mem::take(&mut graph.restrictions);
+        let hierarchy = ContractionHierarchy::build(&graph, Metric::Distance);
+        graph.restrictions = restrictions;
+        let path = temp_path("mismatch");
+        write_prepared(&path, Profile::Car, &graph, Some((Metric::Distance, &hierarchy))).unwrap();
+
+        let error = read_prepared(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(error, PreparedError::Malformed { .. }), "{:?}", error);
+        assert_eq!(crate::exit_code(&error), crate::EXIT_DATA_ERROR);
+    }
+
+    #[test]
+    fn reports_typed_errors_with_exit_codes() {
+        let graph = fixture().graph(Profile::Car);
+        let path = temp_path("errors");
+
+        let error = read_prepared(&path).err().unwrap();
+        assert!(matches!(error, PreparedError::Read { .. }), "{:?}", error);
+        assert_eq!(crate::exit_code(&error), crate::EXIT_NO_INPUT);
+
+        std::fs::write(&path, b"{\"format\": \"open_rust_map/graph\"}").unwrap();
+        let error = read_prepared(&path).err().unwrap();
+        assert!(matches!(error, PreparedError::NotPrepared { .. }), "{:?}", error);
+        assert_eq!(crate::exit_code(&error), crate::EXIT_DATA_ERROR);
+
+        write_prepared(&path, Profile::Car, &graph, None).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[MAGIC.len() + 5] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        let error = read_prepared(&path).err().unwrap();
+        assert!(matches!(error, PreparedError::ChecksumMismatch { .. }), "{:?}", error);
+        assert_eq!(crate::exit_code(&error), crate::EXIT_DATA_ERROR);
+
+        write_prepared(&path, Profile::Car, &graph, None).unwrap();
+        rewrite(&path, |bytes| {
+            bytes[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(VERSION + 1).to_le_bytes())
+        });
+        let error = read_prepared(&path).err().unwrap();
+        assert!(
+            matches!(error, PreparedError::UnsupportedVersion { version } if version == VERSION + 1),
+            "{:?}",
+            error
+        );
+        assert_eq!(crate::exit_code(&error), crate::EXIT_DATA_ERROR);
+
+        write_prepared(&path, Profile::Car, &graph, None).unwrap();
+        rewrite(&path, |bytes| {
+            let body = bytes.len() - 4;
+            bytes.drain(body - 10..body);
+        });
+        let error = read_prepared(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(error, PreparedError::Truncated), "{:?}", error);
+        assert_eq!(crate::exit_code(&error), crate::EXIT_DATA_ERROR);
+    }
+}
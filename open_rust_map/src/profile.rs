@@ -0,0 +1,261 @@
+use osmpbfreader::Tags;
+
+use crate::Oneway;
+
+/// Mode of transport a routing graph is built for.
+///
+/// Each profile decides which `highway` ways it may use and in which
+/// direction, taking the OSM access tag hierarchy into account.
+#[derive(clap::ValueEnum, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    Car,
+    Bicycle,
+    Foot,
+    Motorcycle,
+}
+
+impl Profile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Profile::Car => "car",
+            Profile::Bicycle => "bicycle",
+            Profile::Foot => "foot",
+            Profile::Motorcycle => "motorcycle",
+        }
+    }
+
+    /// Highest speed this mode of transport travels at, in km/h.
+    pub fn max_speed(&self) -> f64 {
+        match self {
+            Profile::Car => 130.0,
+            Profile::Motorcycle => 90.0,
+            Profile::Bicycle => 18.0,
+            Profile::Foot => 5.0,
+        }
+    }
+
+    /// Access keys from least to most specific; the most specific key with a recognised value
+    /// wins.
+    fn access_keys(&self) -> &'static [&'static str] {
+        match self {
+            Profile::Car => &["access", "vehicle", "motor_vehicle", "motorcar"],
+            Profile::Motorcycle => &["access", "vehicle", "motor_vehicle", "motorcycle"],
+            Profile::Bicycle => &["access", "vehicle", "bicycle"],
+            Profile::Foot => &["access", "foot"],
+        }
+    }
+
+    /// Whether a way of this `highway` class is usable when no access tag says otherwise.
+    fn allows_highway(&self, highway: &str) -> bool {
+        match self {
+            Profile::Car => matches!(
+                highway,
+                "motorway"
+                    | "motorway_link"
+                    | "trunk"
+                    | "trunk_link"
+                    | "primary"
+                    | "primary_link"
+                    | "secondary"
+                    | "secondary_link"
+                    | "tertiary"
+                    | "tertiary_link"
+                    | "unclassified"
+                    | "residential"
+                    | "living_street"
+                    | "service"
+                    | "road"
+            ),
+            // Motorcycles are banned from Thai motorways but commonly use tracks
+            Profile::Motorcycle => matches!(
+                highway,
+                "trunk"
+                    | "trunk_link"
+                    | "primary"
+                    | "primary_link"
+                    | "secondary"
+                    | "secondary_link"
+                    | "tertiary"
+                    | "tertiary_link"
+                    | "unclassified"
+                    | "residential"
+                    | "living_street"
+                    | "service"
+                    | "road"
+                    | "track"
+            ),
+            Profile::Bicycle => matches!(
+                highway,
+                "trunk"
+                    | "trunk_link"
+                    | "primary"
+                    | "primary_link"
+                    | "secondary"
+                    | "secondary_link"
+                    | "tertiary"
+                    | "tertiary_link"
+                    | "unclassified"
+                    | "residential"
+                    | "living_street"
+                    | "service"
+                    | "road"
+                    | "track"
+                    | "cycleway"
+                    | "path"
+            ),
+            Profile::Foot => matches!(
+                highway,
+                "trunk"
+                    | "trunk_link"
+                    | "primary"
+                    | "primary_link"
+                    | "secondary"
+                    | "secondary_link"
+                    | "tertiary"
+                    | "tertiary_link"
+                    | "unclassified"
+                    | "residential"
+                    | "living_street"
+                    | "service"
+                    | "road"
+                    | "track"
+                    | "cycleway"
+                    | "path"
+                    | "footway"
+                    | "pedestrian"
+                    | "steps"
+                    | "bridleway"
+            ),
+        }
+    }
+
+    /// Whether this profile may use the way at all.
+    pub fn allows_way(&self, tags: &Tags) -> bool {
+        let highway = match tags.get("highway") {
+            Some(highway) => highway.as_str(),
+            None => return false,
+        };
+
+        let mut allowed = self.allows_highway(highway);
+        // motorroad=yes restricts the way to fast motor vehicles
+        if *self != Profile::Car && tags.get("motorroad").map(|v| v.as_str()) == Some("yes") {
+            allowed = false;
+        }
+
+        // Values that do not decide access fall through to the next less specific key
+        self.access_keys()
+            .iter()
+            .rev()
+            .find_map(|key| tags.get(*key).and_then(|v| allowed_access_value(v)))
+            .unwrap_or(allowed)
+    }
+
+    /// Direction(s) this profile may travel along the way.
+    pub fn oneway(&self, tags: &Tags) -> Oneway {
+        match self {
+            Profile::Foot => Oneway::No,
+            Profile::Bicycle => {
+                let contraflow = tags.get("oneway:bicycle").map(|v| v.as_str()) == Some("no")
+                    || tags
+                        .get("cycleway")
+                        .is_some_and(|v| v.starts_with("opposite"));
+                if contraflow {
+                    Oneway::No
+                } else {
+                    Oneway::from_tags(tags)
+                }
+            }
+            Profile::Car | Profile::Motorcycle => Oneway::from_tags(tags),
+        }
+    }
+}
+
+/// Maps an access tag value to allowed/denied, or `None` for values that do not decide access.
+fn allowed_access_value(value: &str) -> Option<bool> {
+    match value {
+        "yes" | "permissive" | "designated" | "destination" | "delivery" | "customers" => Some(true),
+        "no" | "private" | "agricultural" | "forestry" | "use_sidepath" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tags;
+
+    const PROFILES: [Profile; 4] = [Profile::Car, Profile::Motorcycle, Profile::Bicycle, Profile::Foot];
+
+    /// Which of car, motorcycle, bicycle and foot may use a way with these tags.
+    fn allowed(pairs: &[(&str, &str)]) -> [bool; 4] {
+        PROFILES.map(|profile| profile.allows_way(&tags(pairs)))
+    }
+
+    #[test]
+    fn highway_defaults() {
+        assert_eq!(allowed(&[("highway", "motorway")]), [true, false, false, false]);
+        assert_eq!(allowed(&[("highway", "primary")]), [true, true, true, true]);
+        assert_eq!(allowed(&[("highway", "track")]), [false, true, true, true]);
+        assert_eq!(allowed(&[("highway", "cycleway")]), [false, false, true, true]);
+        assert_eq!(allowed(&[("highway", "footway")]), [false, false, false, true]);
+        assert_eq!(allowed(&[("highway", "steps")]), [false, false, false, true]);
+        assert_eq!(allowed(&[("highway", "proposed")]), [false; 4]);
+        assert_eq!(allowed(&[("building", "yes")]), [false; 4]);
+        assert_eq!(
+            allowed(&[("highway", "primary"), ("motorroad", "yes")]),
+            [true, false, false, false]
+        );
+    }
+
+    #[test]
+    fn access_tags_override_highway_defaults() {
+        assert_eq!(allowed(&[("highway", "primary"), ("access", "no")]), [false; 4]);
+        assert_eq!(allowed(&[("highway", "residential"), ("access", "private")]), [false; 4]);
+        assert_eq!(
+            allowed(&[("highway", "footway"), ("bicycle", "designated")]),
+            [false, false, true, true]
+        );
+        assert_eq!(
+            allowed(&[("highway", "primary"), ("access", "no"), ("foot", "yes")]),
+            [false, false, false, true]
+        );
+        assert_eq!(
+            allowed(&[("highway", "primary"), ("vehicle", "no")]),
+            [false, false, false, true]
+        );
+        assert_eq!(
+            allowed(&[("highway", "primary"), ("motor_vehicle", "no"), ("motorcycle", "yes")]),
+            [false, true, true, true]
+        );
+        assert_eq!(
+            allowed(&[("highway", "service"), ("access", "no"), ("motorcar", "destination")]),
+            [true, false, false, false]
+        );
+    }
+
+    #[test]
+    fn unrecognised_access_values_fall_back_to_less_specific_keys() {
+        assert_eq!(
+            allowed(&[("highway", "primary"), ("access", "no"), ("motor_vehicle", "unknown_value")]),
+            [false; 4]
+        );
+        assert_eq!(
+            allowed(&[("highway", "footway"), ("access", "yes"), ("bicycle", "dismount")]),
+            [true, true, true, true]
+        );
+        // No recognised value anywhere keeps the highway default
+        assert_eq!(allowed(&[("highway", "primary"), ("access", "unknown")]), [true; 4]);
+    }
+
+    #[test]
+    fn oneway_exceptions_per_profile() {
+        let oneway = |pairs: &[(&str, &str)]| PROFILES.map(|profile| profile.oneway(&tags(pairs)));
+        let (f, n) = (Oneway::Forward, Oneway::No);
+        assert_eq!(oneway(&[("oneway", "yes")]), [f, f, f, n]);
+        assert_eq!(oneway(&[("oneway", "yes"), ("oneway:bicycle", "no")]), [f, f, n, n]);
+        assert_eq!(oneway(&[("oneway", "yes"), ("cycleway", "opposite_lane")]), [f, f, n, n]);
+        assert_eq!(oneway(&[("junction", "roundabout")]), [f, f, f, n]);
+        assert_eq!(oneway(&[("oneway", "-1")]), [Oneway::Backward, Oneway::Backward, Oneway::Backward, n]);
+    }
+}
@@ -0,0 +1,625 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use osmpbfreader::{NodeId, OsmId, Relation, WayId};
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use tracing::{debug, warn};
+
+use crate::profile::Profile;
+use crate::{Graph, Metric};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestrictionKind {
+    /// `no_*`: the turn onto the `to` way is forbidden
+    No,
+    /// `only_*`: every turn except the one onto the `to` way is forbidden
+    Only,
+}
+
+#[derive(Debug, Clone)]
+struct WayRestriction {
+    from: WayId,
+    via: Vec<WayId>,
+    to: WayId,
+    kind: RestrictionKind,
+}
+
+/// One restriction in flat form, as persisted in graph exports and prepared graph files.
+#[derive(Debug, Clone)]
+pub enum RestrictionRecord {
+    ViaNode {
+        from: WayId,
+        via: NodeId,
+        to: WayId,
+        kind: RestrictionKind,
+    },
+    ViaWays {
+        from: WayId,
+        via: Vec<WayId>,
+        to: WayId,
+        kind: RestrictionKind,
+    },
+}
+
+/// Turn restrictions from `type=restriction` relations that apply to one profile.
+#[derive(Debug, Clone, Default)]
+pub struct TurnRestrictions {
+    via_node: HashMap<(WayId, NodeId), Vec<(WayId, RestrictionKind)>>,
+    via_way: Vec<WayRestriction>,
+    via_way_by_from: HashMap<WayId, Vec<usize>>,
+}
+
+/// Progress through the via-way restrictions being followed: index into `via_way` and current
+/// via member, sorted so equal progress hashes equally.
+type ActiveRestrictions = Vec<(usize, usize)>;
+
+impl TurnRestrictions {
+    pub fn from_relations(relations: &[Relation], profile: Profile) -> Self {
+        let mut restrictions = TurnRestrictions::default();
+        for relation in relations {
+            if let Some(kind) = restriction_kind(relation, profile) {
+                restrictions.add_relation(relation, kind);
+            }
+        }
+        debug!(
+            "Loaded {} via-node and {} via-way turn restrictions for {}",
+            restrictions.via_node.values().map(Vec::len).sum::<usize>(),
+            restrictions.via_way.len(),
+            profile.as_str()
+        );
+        restrictions
+    }
+
+    pub fn from_records(records: impl IntoIterator<Item = RestrictionRecord>) -> Self {
+        let mut restrictions = TurnRestrictions::default();
+        for record in records {
+            restrictions.push(record);
+        }
+        restrictions
+    }
+
+    pub fn records(&self) -> Vec<RestrictionRecord> {
+        let via_node = self.via_node.iter().flat_map(|(&(from, via), turns)| {
+            turns.iter().map(move |&(to, kind)| RestrictionRecord::ViaNode { from, via, to, kind })
+        });
+        let via_ways = self.via_way.iter().map(|r| RestrictionRecord::ViaWays {
+            from: r.from,
+            via: r.via.clone(),
+            to: r.to,
+            kind: r.kind,
+        });
+        via_node.chain(via_ways).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.via_node.is_empty() && self.via_way.is_empty()
+    }
+
+    fn push(&mut self, record: RestrictionRecord) {
+        match record {
+            RestrictionRecord::ViaNode { from, via, to, kind } => {
+                self.via_node.entry((from, via)).or_default().push((to, kind));
+            }
+            RestrictionRecord::ViaWays { from, via, to, kind } => {
+                self.via_way_by_from
+                    .entry(from)
+                    .or_default()
+                    .push(self.via_way.len());
+                self.via_way.push(WayRestriction { from, via, to, kind });
+            }
+        }
+    }
+
+    fn add_relation(&mut self, relation: &Relation, kind: RestrictionKind) {
+        let mut from = None;
+        let mut to = None;
+        let mut via_node = None;
+        let mut via_ways = Vec::new();
+        for member in &relation.refs {
+            match (member.role.as_str(), member.member) {
+                ("from", OsmId::Way(id)) => from = Some(id),
+                ("to", OsmId::Way(id)) => to = Some(id),
+                ("via", OsmId::Node(id)) => via_node = Some(id),
+                ("via", OsmId::Way(id)) => via_ways.push(id),
+                _ => {}
+            }
+        }
+
+        match (from, to, via_node, via_ways.is_empty()) {
+            (Some(from), Some(to), Some(via), true) => {
+                self.push(RestrictionRecord::ViaNode { from, via, to, kind });
+            }
+            (Some(from), Some(to), None, false) => {
+                self.push(RestrictionRecord::ViaWays { from, via: via_ways, to, kind });
+            }
+            _ => warn!("Skipping malformed turn restriction {:?}", relation.id),
+        }
+    }
+}
+
+/// Reads the restriction value that applies to `profile`, honouring `restriction:<mode>` and `except`.
+fn restriction_kind(relation: &Relation, profile: Profile) -> Option<RestrictionKind> {
+    if relation.tags.get("type").map(|v| v.as_str()) != Some("restriction") {
+        return None;
+    }
+    let (specific_keys, exempt): (&[&str], &str) = match profile {
+        Profile::Car => (&["restriction:motorcar", "restriction:motor_vehicle", "restriction:vehicle"], "motorcar"),
+        Profile::Motorcycle => (&["restriction:motorcycle", "restriction:motor_vehicle", "restriction:vehicle"], "motorcycle"),
+        Profile::Bicycle => (&["restriction:bicycle", "restriction:vehicle"], "bicycle"),
+        // Turn restrictions only bind pedestrians when tagged for them explicitly
+        Profile::Foot => (&["restriction:foot"], "foot"),
+    };
+
+    let exempted = relation
+        .tags
+        .get("except")
+        .is_some_and(|v| v.split(';').any(|mode| mode.trim() == exempt));
+    if exempted {
+        return None;
+    }
+
+    let value = specific_keys
+        .iter()
+        .find_map(|key| relation.tags.get(*key))
+        .or_else(|| match profile {
+            Profile::Foot => None,
+            _ => relation.tags.get("restriction"),
+        })?;
+    if value.starts_with("no_") {
+        Some(RestrictionKind::No)
+    } else if value.starts_with("only_") {
+        Some(RestrictionKind::Only)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct State {
+    edge: EdgeIndex,
+    active: ActiveRestrictions,
+}
+
+impl State {
+    fn edge_target(&self, graph: &Graph) -> Option<NodeIndex> {
+        graph.graph.edge_endpoints(self.edge).map(|(_, target)| target)
+    }
+}
+
+struct QueueEntry {
+    estimate: f64,
+    cost: f64,
+    state: State,
+}
+
+impl PartialEq for QueueEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl Eq for QueueEntry {}
+
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueEntry {
+    // Reversed so the BinaryHeap pops the smallest estimate first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+impl Graph {
+    /// Whether the turn from `incoming` onto `outgoing` is legal, returning the via-way
+    /// restrictions still being tracked after the turn.
+    fn turn_allowed(
+        &self,
+        incoming: EdgeIndex,
+        outgoing: EdgeIndex,
+        active: &[(usize, usize)],
+    ) -> Option<ActiveRestrictions> {
+        let restrictions = &self.restrictions;
+        let (from_idx, via_idx) = self.graph.edge_endpoints(incoming)?;
+        let (_, to_idx) = self.graph.edge_endpoints(outgoing)?;
+        let from_way = self.graph[incoming].way_id;
+        let to_way = self.graph[outgoing].way_id;
+
+        // U-turns back along the same way are only allowed at dead ends
+        if to_idx == from_idx && from_way == to_way {
+            let exits = self.graph.edges_directed(via_idx, Direction::Outgoing).count();
+            if exits > 1 {
+                return None;
+            }
+        }
+
+        let via_node = self.graph[via_idx].id;
+        if let Some(turns) = restrictions.via_node.get(&(from_way, via_node)) {
+            for &(restricted_to, kind) in turns {
+                match kind {
+                    RestrictionKind::No if to_way == restricted_to => return None,
+                    RestrictionKind::Only if to_way != restricted_to => return None,
+                    _ => {}
+                }
+            }
+        }
+
+        let mut next = Vec::new();
+        for &(index, step) in active {
+            let restriction = &restrictions.via_way[index];
+            if to_way == restriction.via[step] {
+                next.push((index, step));
+            } else if step + 1 < restriction.via.len() && to_way == restriction.via[step + 1] {
+                next.push((index, step + 1));
+            } else if step + 1 == restriction.via.len() && self.is_way_end(via_idx, restriction.via[step]) {
+                // Leaving the last via way anywhere but at its end is a different manoeuvre
+                match restriction.kind {
+                    RestrictionKind::No if to_way == restriction.to => return None,
+                    RestrictionKind::Only if to_way != restriction.to => return None,
+                    _ => {}
+                }
+            }
+        }
+        if from_way != to_way {
+            if let Some(candidates) = restrictions.via_way_by_from.get(&from_way) {
+                next.extend(
+                    candidates
+                        .iter()
+                        .filter(|&&index| restrictions.via_way[index].via[0] == to_way)
+                        .map(|&index| (index, 0)),
+                );
+            }
+        }
+        next.sort_unstable();
+        next.dedup();
+        Some(next)
+    }
+
+    /// Whether `idx` is an end node of `way_id`, joined to at most one other node of that way.
+    fn is_way_end(&self, idx: NodeIndex, way_id: WayId) -> bool {
+        let mut neighbours = self
+            .graph
+            .edges_directed(idx, Direction::Outgoing)
+            .filter(|e| e.weight().way_id == way_id)
+            .map(|e| e.target())
+            .chain(
+                self.graph
+                    .edges_directed(idx, Direction::Incoming)
+                    .filter(|e| e.weight().way_id == way_id)
+                    .map(|e| e.source()),
+            )
+            .collect::<Vec<_>>();
+        neighbours.sort();
+        neighbours.dedup();
+        neighbours.len() <= 1
+    }
+
+    /// The turn-expanded graph the restricted search walks: the edge of every reachable state,
+    /// with state `i` being edge `i` without any via-way restriction in progress, and the
+    /// allowed turns between states as `(from, to)` pairs.
+    pub(crate) fn turn_states(&self) -> (Vec<EdgeIndex>, Vec<(u32, u32)>) {
+        let mut states = self
+            .graph
+            .edge_indices()
+            .map(|edge| State {
+                edge,
+                active: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        let mut ids = states
+            .iter()
+            .enumerate()
+            .map(|(id, state)| (state.clone(), id as u32))
+            .collect::<HashMap<_, _>>();
+        let mut turns = Vec::new();
+        let mut id = 0;
+        while id < states.len() {
+            let state = states[id].clone();
+            if let Some(node) = state.edge_target(self) {
+                for edge in self.graph.edges_directed(node, Direction::Outgoing) {
+                    let Some(active) = self.turn_allowed(state.edge, edge.id(), &state.active) else {
+                        continue;
+                    };
+                    let next = State {
+                        edge: edge.id(),
+                        active,
+                    };
+                    let next_id = *ids.entry(next).or_insert_with_key(|next| {
+                        states.push(next.clone());
+                        states.len() as u32 - 1
+                    });
+                    turns.push((id as u32, next_id));
+                }
+            }
+            id += 1;
+        }
+        (states.into_iter().map(|state| state.edge).collect(), turns)
+    }
+
+    /// Edge-based A* that enforces turn restrictions and forbids U-turns except at dead ends.
+    pub(crate) fn find_restricted_path(
+        &self,
+        start: NodeIndex,
+        end: NodeIndex,
+        metric: Metric,
+    ) -> Option<(Vec<NodeIndex>, f64)> {
+        if start == end {
+            return Some((vec![start], 0.0));
+        }
+
+        let seeds = self
+            .graph
+            .edges_directed(start, Direction::Outgoing)
+            .map(|edge| {
+                let state = State {
+                    edge: edge.id(),
+                    active: Vec::new(),
+                };
+                (state, edge.weight().weight(metric))
+            })
+            .collect();
+        self.restricted_search(seeds, |state| state.edge_target(self) == Some(end), end, metric)
+    }
+
+    /// Restricted search between two snapped edges. It starts out on `exit`, so the first turn
+    /// is checked as well, and ends once the turn onto `entry` is allowed. Returns the nodes
+    /// from the target of `exit` to the source of `entry` and the cost between them.
+    pub(crate) fn find_restricted_path_between(
+        &self,
+        exit: EdgeIndex,
+        entry: EdgeIndex,
+        metric: Metric,
+    ) -> Option<(Vec<NodeIndex>, f64)> {
+        let (_, end) = self.graph.edge_endpoints(entry)?;
+        let seed = State {
+            edge: exit,
+            active: Vec::new(),
+        };
+        let (mut path, cost) = self.restricted_search(
+            vec![(seed.clone(), 0.0)],
+            |state| *state != seed && state.edge == entry,
+            end,
+            metric,
+        )?;
+        // Both snapped edges are only partly driven, the caller adds those shares
+        path.remove(0);
+        path.pop();
+        Some((path, cost - self.graph[entry].weight(metric)))
+    }
+
+    /// A* over edge states from `seeds` until a state satisfies `goal`, estimating towards `end`.
+    fn restricted_search(
+        &self,
+        seeds: Vec<(State, f64)>,
+        goal: impl Fn(&State) -> bool,
+        end: NodeIndex,
+        metric: Metric,
+    ) -> Option<(Vec<NodeIndex>, f64)> {
+        let mut costs: HashMap<State, f64> = HashMap::new();
+        let mut parents: HashMap<State, State> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+
+        for (state, cost) in seeds {
+            costs.insert(state.clone(), cost);
+            queue.push(QueueEntry {
+                estimate: cost + self.heuristic(state.edge_target(self)?, end, metric),
+                cost,
+                state,
+            });
+        }
+
+        while let Some(QueueEntry { cost, state, .. }) = queue.pop() {
+            if costs.get(&state).is_some_and(|&best| cost > best) {
+                continue;
+            }
+            if goal(&state) {
+                return Some((self.unpack_states(state, &parents), cost));
+            }
+            let node = state.edge_target(self)?;
+
+            for edge in self.graph.edges_directed(node, Direction::Outgoing) {
+                let active = match self.turn_allowed(state.edge, edge.id(), &state.active) {
+                    Some(active) => active,
+                    None => continue,
+                };
+                let next = State {
+                    edge: edge.id(),
+                    active,
+                };
+                let next_cost = cost + edge.weight().weight(metric);
+                if costs.get(&next).is_none_or(|&best| next_cost < best) {
+                    costs.insert(next.clone(), next_cost);
+                    parents.insert(next.clone(), state.clone());
+                    queue.push(QueueEntry {
+                        estimate: next_cost + self.heuristic(edge.target(), end, metric),
+                        cost: next_cost,
+                        state: next,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    fn unpack_states(&self, last: State, parents: &HashMap<State, State>) -> Vec<NodeIndex> {
+        let mut edges = vec![last.edge];
+        let mut current = &last;
+        while let Some(parent) = parents.get(current) {
+            edges.push(parent.edge);
+            current = parent;
+        }
+        edges.reverse();
+
+        let mut path = Vec::with_capacity(edges.len() + 1);
+        for (i, &edge) in edges.iter().enumerate() {
+            if let Some((source, target)) = self.graph.edge_endpoints(edge) {
+                if i == 0 {
+                    path.push(source);
+                }
+                path.push(target);
+            }
+        }
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::{NodeId, OsmId, WayId};
+
+    use crate::profile::Profile;
+    use crate::test_support::Fixture;
+    use crate::{Graph, Metric};
+
+    fn node_ids(graph: &Graph, path: &[petgraph::graph::NodeIndex]) -> Vec<i64> {
+        path.iter().map(|&idx| graph.graph[idx].id.0).collect()
+    }
+
+    fn route(graph: &Graph, from: i64, to: i64) -> Vec<i64> {
+        let start = graph.node_indices[&NodeId(from)];
+        let end = graph.node_indices[&NodeId(to)];
+        let (path, _) = graph.find_restricted_path(start, end, Metric::Distance).unwrap();
+        node_ids(graph, &path)
+    }
+
+    #[test]
+    fn via_way_restriction_applies_only_at_the_end_of_the_via_way() {
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, 100.000, 13.000)
+            .node(2, 100.001, 13.000)
+            .node(3, 100.002, 13.000)
+            .node(4, 100.003, 13.000)
+            .node(5, 100.003, 13.001)
+            .node(6, 100.002, 13.001)
+            .node(7, 100.003, 12.999)
+            .way(10, &[1, 2], &[("highway", "residential")])
+            .way(20, &[2, 3, 4], &[("highway", "residential")])
+            .way(30, &[4, 5], &[("highway", "residential")])
+            .way(40, &[3, 6], &[("highway", "residential")])
+            .way(50, &[4, 7], &[("highway", "residential")])
+            .relation(
+                1,
+                &[
+                    (OsmId::Way(WayId(10)), "from"),
+                    (OsmId::Way(WayId(20)), "via"),
+                    (OsmId::Way(WayId(30)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "only_left_turn")],
+            );
+        let graph = fixture.graph(Profile::Car);
+
+        // Turning off the via way at its interior node 3 is not part of the manoeuvre
+        assert_eq!(route(&graph, 1, 6), vec![1, 2, 3, 6]);
+        // At its end node 4 only the `to` way is allowed, so the route has to turn around first
+        let detour = route(&graph, 1, 7);
+        assert_ne!(detour, vec![1, 2, 3, 4, 7]);
+        assert_eq!(detour.last(), Some(&7));
+    }
+
+    #[test]
+    fn tracks_every_via_way_restriction_sharing_a_from_way() {
+        let via_way = |id, to, restriction| {
+            (
+                id,
+                [
+                    (OsmId::Way(WayId(10)), "from"),
+                    (OsmId::Way(WayId(20)), "via"),
+                    (OsmId::Way(WayId(to)), "to"),
+                ],
+                [("type", "restriction"), ("restriction", restriction)],
+            )
+        };
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, 100.000, 13.000)
+            .node(2, 100.001, 13.000)
+            .node(3, 100.002, 13.000)
+            .node(4, 100.003, 13.000)
+            .node(5, 100.002, 12.999)
+            .node(6, 100.0015, 13.002)
+            .way(10, &[1, 2], &[("highway", "residential")])
+            .way(20, &[2, 3], &[("highway", "residential")])
+            .way(30, &[3, 4], &[("highway", "residential")])
+            .way(40, &[3, 5], &[("highway", "residential")])
+            .way(50, &[1, 6], &[("highway", "residential")])
+            .way(60, &[4, 6, 5], &[("highway", "residential")]);
+        for (id, members, tags) in [via_way(1, 30, "no_straight_on"), via_way(2, 40, "no_right_turn")] {
+            fixture.relation(id, &members, &tags);
+        }
+        let graph = fixture.graph(Profile::Car);
+        let passes = |path: &[i64], turn: &[i64]| path.windows(turn.len()).any(|w| w == turn);
+
+        // Both restrictions start with the turn from way 10 onto way 20 and both apply
+        let straight_on = route(&graph, 1, 4);
+        assert!(!passes(&straight_on, &[2, 3, 4]), "{:?}", straight_on);
+        let right = route(&graph, 1, 5);
+        assert!(!passes(&right, &[2, 3, 5]), "{:?}", right);
+        // Neither bans the turn onto way 20 itself
+        assert_eq!(route(&graph, 1, 3), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn via_node_restriction_forbids_the_turn() {
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, 100.000, 13.000)
+            .node(2, 100.001, 13.000)
+            .node(3, 100.001, 13.001)
+            .node(4, 100.002, 13.000)
+            .way(10, &[1, 2], &[("highway", "residential")])
+            .way(20, &[2, 4], &[("highway", "residential")])
+            .way(30, &[2, 3], &[("highway", "residential")])
+            .relation(
+                1,
+                &[
+                    (OsmId::Way(WayId(10)), "from"),
+                    (OsmId::Node(NodeId(2)), "via"),
+                    (OsmId::Way(WayId(30)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "no_left_turn")],
+            );
+        let graph = fixture.graph(Profile::Car);
+
+        // Way 10 may not turn onto way 30 at node 2, so the route turns around at the dead end 4
+        assert_eq!(route(&graph, 1, 3), vec![1, 2, 4, 2, 3]);
+        assert_eq!(route(&graph, 4, 3), vec![4, 2, 3]);
+        // Walkers are not bound by a plain restriction tag
+        let graph = fixture.graph(Profile::Foot);
+        assert_eq!(route(&graph, 1, 3), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn snapped_route_checks_the_first_turn() {
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, 100.000, 13.000)
+            .node(2, 100.001, 13.000)
+            .node(3, 100.001, 13.001)
+            .node(4, 100.002, 13.000)
+            .way(10, &[1, 2], &[("highway", "residential")])
+            .way(20, &[2, 4], &[("highway", "residential")])
+            .way(30, &[2, 3], &[("highway", "residential")])
+            .relation(
+                1,
+                &[
+                    (OsmId::Way(WayId(10)), "from"),
+                    (OsmId::Node(NodeId(2)), "via"),
+                    (OsmId::Way(WayId(30)), "to"),
+                ],
+                &[("type", "restriction"), ("restriction", "no_left_turn")],
+            );
+        let graph = fixture.graph(Profile::Car);
+
+        let start = graph.snap(13.000, 100.0005, None).unwrap();
+        let end = graph.snap(13.0005, 100.001, None).unwrap();
+        let route = graph.route_between(None, &start, &end, Metric::Distance).unwrap();
+        // Way 10 may not turn onto way 30 at node 2, so the route turns around at the dead end 4
+        assert_eq!(node_ids(&graph, &route.path), vec![2, 4, 2]);
+    }
+}
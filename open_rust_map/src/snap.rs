@@ -0,0 +1,254 @@
+use geo::prelude::*;
+use geo_types::Point;
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+use tracing::debug;
+
+use crate::ch::ContractionHierarchy;
+use crate::spatial::closest_point_on_edge;
+use crate::{Graph, Metric};
+
+/// A query coordinate projected onto the closest road segment.
+#[derive(Debug, Clone)]
+pub struct Snap {
+    /// Directed edge the point was projected onto
+    pub edge: EdgeIndex,
+    /// Projected point on the edge, `(lon, lat)`
+    pub point: Point<f64>,
+    /// Position along the edge from its source (0.0) to its target (1.0)
+    pub fraction: f64,
+    /// Distance in metres between the query coordinate and `point`
+    pub distance: f64,
+}
+
+/// A route between two snapped points, including the partial edges at either end.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub start: Snap,
+    pub end: Snap,
+    /// Graph nodes passed between the two snapped points
+    pub path: Vec<NodeIndex>,
+    /// Total cost in the routing metric
+    pub cost: f64,
+    pub distance: f64,
+    pub duration: f64,
+}
+
+impl Route {
+    /// Route geometry as `(lon, lat)` points, starting and ending at the snapped points.
+    pub fn points(&self, graph: &Graph) -> Vec<Point<f64>> {
+        let mut points = Vec::with_capacity(self.path.len() + 2);
+        points.push(self.start.point);
+        points.extend(self.path.iter().map(|&idx| graph.graph[idx].point));
+        points.push(self.end.point);
+        points
+    }
+}
+
+impl Graph {
+    /// Projects a coordinate onto the closest edge, rejecting it when further than `max_distance` metres.
+    pub fn snap(&self, lat: f64, lon: f64, max_distance: Option<f64>) -> Option<Snap> {
+        let query = Point::new(lon, lat);
+        let (edge, distance) = self.index.nearest_edges(self, lat, lon, 1).into_iter().next()?;
+        if max_distance.is_some_and(|max| distance > max) {
+            debug!("Nearest road to ({}, {}) is {:.2}m away, beyond the snap limit", lat, lon, distance);
+            return None;
+        }
+
+        let point = closest_point_on_edge(self, edge, query);
+        let (source, _) = self.graph.edge_endpoints(edge)?;
+        let length = self.graph[edge].distance;
+        let fraction = if length > 0.0 {
+            (self.graph[source].point.geodesic_distance(&point) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        Some(Snap {
+            edge,
+            point,
+            fraction,
+            distance,
+        })
+    }
+
+    /// The snapped edge plus its reverse twin on the same way, each with the snap fraction
+    /// measured in its own direction.
+    fn snapped_edges(&self, snap: &Snap) -> Vec<(EdgeIndex, f64)> {
+        let mut edges = vec![(snap.edge, snap.fraction)];
+        if let Some((source, target)) = self.graph.edge_endpoints(snap.edge) {
+            let way_id = self.graph[snap.edge].way_id;
+            edges.extend(
+                self.graph
+                    .edges_connecting(target, source)
+                    .filter(|e| e.weight().way_id == way_id)
+                    .map(|e| (e.id(), 1.0 - snap.fraction)),
+            );
+        }
+        edges
+    }
+
+    /// Routes between two snapped points by splitting their edges virtually at the snap.
+    pub fn route_between(
+        &self,
+        hierarchy: Option<&ContractionHierarchy>,
+        start: &Snap,
+        end: &Snap,
+        metric: Metric,
+    ) -> Option<Route> {
+        let mut best: Option<Route> = None;
+        let mut consider = |route: Route| {
+            if best.as_ref().is_none_or(|b| route.cost < b.cost) {
+                best = Some(route);
+            }
+        };
+
+        for (exit, exit_fraction) in self.snapped_edges(start) {
+            let exit_edge = &self.graph[exit];
+            let (_, exit_node) = self.graph.edge_endpoints(exit)?;
+            let head = 1.0 - exit_fraction;
+
+            for (entry, entry_fraction) in self.snapped_edges(end) {
+                let entry_edge = &self.graph[entry];
+
+                // Both points on the same directed edge, start before end
+                if exit == entry && entry_fraction >= exit_fraction {
+                    let share = entry_fraction - exit_fraction;
+                    consider(Route {
+                        start: start.clone(),
+                        end: end.clone(),
+                        path: Vec::new(),
+                        cost: share * exit_edge.weight(metric),
+                        distance: share * exit_edge.distance,
+                        duration: share * exit_edge.duration,
+                    });
+                }
+
+                let (entry_node, _) = self.graph.edge_endpoints(entry)?;
+                let result = match hierarchy {
+                    Some(hierarchy) => hierarchy.find_path_between(self, exit, entry, metric),
+                    // Start from the snapped edge so the first turn is checked too
+                    None if !self.restrictions.is_empty() => self.find_restricted_path_between(exit, entry, metric),
+                    None => self.find_shortest_path(exit_node, entry_node, metric),
+                };
+                if let Some((path, cost)) = result {
+                    let (distance, duration) = self.path_totals(&path, metric);
+                    consider(Route {
+                        start: start.clone(),
+                        end: end.clone(),
+                        cost: head * exit_edge.weight(metric) + cost + entry_fraction * entry_edge.weight(metric),
+                        distance: head * exit_edge.distance + distance + entry_fraction * entry_edge.distance,
+                        duration: head * exit_edge.duration + duration + entry_fraction * entry_edge.duration,
+                        path,
+                    });
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osmpbfreader::NodeId;
+    use crate::profile::Profile;
+    use crate::test_support::Fixture;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6 * expected.max(1.0), "{} != {}", actual, expected);
+    }
+
+    /// Two oneway residential ways, 1 -> 2 -> 3, heading east along the equator.
+    fn two_ways() -> Graph {
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, 0.0, 0.0)
+            .node(2, 0.01, 0.0)
+            .node(3, 0.03, 0.0)
+            .way(1, &[1, 2], &[("highway", "residential"), ("oneway", "yes")])
+            .way(2, &[2, 3], &[("highway", "residential"), ("oneway", "yes")]);
+        fixture.graph(Profile::Car)
+    }
+
+    #[test]
+    fn snaps_onto_the_closest_edge() {
+        let graph = two_ways();
+        let snap = graph.snap(0.0001, 0.0025, None).unwrap();
+        assert_eq!(graph.graph[snap.edge].way_id.0, 1);
+        assert_close(snap.fraction, 0.25);
+        assert!((snap.distance - 11.1).abs() < 0.1, "{:?}", snap);
+        assert!((snap.point.x() - 0.0025).abs() < 1e-9 && snap.point.y().abs() < 1e-9, "{:?}", snap);
+
+        let snap = graph.snap(-0.0001, 0.02, None).unwrap();
+        assert_eq!(graph.graph[snap.edge].way_id.0, 2);
+        assert_close(snap.fraction, 0.5);
+    }
+
+    #[test]
+    fn rejects_points_beyond_the_snap_distance() {
+        let graph = two_ways();
+        assert!(graph.snap(0.0001, 0.0025, Some(5.0)).is_none());
+        assert!(graph.snap(0.0001, 0.0025, Some(20.0)).is_some());
+        assert!(Graph::new().snap(0.0, 0.0, None).is_none());
+    }
+
+    #[test]
+    fn splits_edge_weights_at_the_snapped_fraction() {
+        let graph = two_ways();
+        let first = &graph.graph[graph.snap(0.0, 0.0, None).unwrap().edge];
+        let second = &graph.graph[graph.snap(0.0, 0.03, None).unwrap().edge];
+        let start = graph.snap(0.0001, 0.0025, None).unwrap();
+        let end = graph.snap(-0.0001, 0.02, None).unwrap();
+
+        for metric in [Metric::Distance, Metric::Duration] {
+            let hierarchy = ContractionHierarchy::build(&graph, metric);
+            for hierarchy in [None, Some(&hierarchy)] {
+                let route = graph.route_between(hierarchy, &start, &end, metric).unwrap();
+                assert_eq!(route.path, vec![graph.node_indices[&NodeId(2)]]);
+                assert_close(route.distance, 0.75 * first.distance + 0.5 * second.distance);
+                assert_close(route.duration, 0.75 * first.duration + 0.5 * second.duration);
+                assert_close(route.cost, 0.75 * first.weight(metric) + 0.5 * second.weight(metric));
+            }
+        }
+        // Oneway, so there is no way back
+        assert!(graph.route_between(None, &end, &start, Metric::Distance).is_none());
+    }
+
+    #[test]
+    fn routes_along_a_single_edge() {
+        let graph = two_ways();
+        let start = graph.snap(0.0001, 0.0025, None).unwrap();
+        let end = graph.snap(0.0001, 0.0075, None).unwrap();
+        let edge = &graph.graph[start.edge];
+        assert_eq!(start.edge, end.edge);
+
+        let route = graph.route_between(None, &start, &end, Metric::Distance).unwrap();
+        assert!(route.path.is_empty());
+        assert_close(route.distance, 0.5 * edge.distance);
+        assert_close(route.duration, 0.5 * edge.duration);
+        let points = route.points(&graph);
+        assert_eq!(points, vec![start.point, end.point]);
+
+        // Against a oneway, the start of the edge cannot be reached again from its end
+        assert!(graph.route_between(None, &end, &start, Metric::Distance).is_none());
+    }
+
+    #[test]
+    fn routes_backwards_along_a_two_way_edge() {
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, 0.0, 0.0)
+            .node(2, 0.01, 0.0)
+            .way(1, &[1, 2], &[("highway", "residential")]);
+        let graph = fixture.graph(Profile::Car);
+        let start = graph.snap(0.0001, 0.0075, None).unwrap();
+        let end = graph.snap(0.0001, 0.0025, None).unwrap();
+        let length = graph.graph[start.edge].distance;
+
+        for hierarchy in [None, Some(&ContractionHierarchy::build(&graph, Metric::Distance))] {
+            let route = graph.route_between(hierarchy, &start, &end, Metric::Distance).unwrap();
+            assert!(route.path.is_empty(), "{:?}", route.path);
+            assert_close(route.distance, 0.5 * length);
+        }
+    }
+}
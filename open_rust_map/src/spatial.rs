@@ -0,0 +1,162 @@
+use geo::prelude::*;
+use geo::{Closest, ClosestPoint};
+use geo_types::{Line, Point};
+use petgraph::graph::{DiGraph, EdgeIndex};
+use rstar::primitives::{GeomWithData, Line as RLine};
+use rstar::RTree;
+
+use crate::{Edge, Graph, Node};
+
+/// Shortest distance in metres covered by one degree of latitude, bounding how close a
+/// candidate a given distance away in degree space can be in metres.
+const MIN_METRES_PER_DEGREE: f64 = 110_574.0;
+
+type IndexedSegment = GeomWithData<RLine<[f64; 2]>, EdgeIndex>;
+
+/// R-tree over graph edge segments in `[lon, lat]` degrees.
+///
+/// Lookups walk candidates in degree space and rank them by geodesic distance in metres.
+#[derive(Debug, Default)]
+pub struct SpatialIndex {
+    segments: RTree<IndexedSegment>,
+}
+
+impl SpatialIndex {
+    pub fn build(graph: &Graph) -> Self {
+        let segments = graph
+            .graph
+            .edge_indices()
+            .map(|edge| indexed_segment(&graph.graph, edge))
+            .collect();
+        SpatialIndex {
+            segments: RTree::bulk_load(segments),
+        }
+    }
+
+    /// Adds an edge segment between the current positions of its endpoints.
+    pub fn insert_edge(&mut self, graph: &DiGraph<Node, Edge>, edge: EdgeIndex) {
+        self.segments.insert(indexed_segment(graph, edge));
+    }
+
+    /// Drops an edge segment; call while the graph still holds the edge and its endpoints.
+    pub fn remove_edge(&mut self, graph: &DiGraph<Node, Edge>, edge: EdgeIndex) {
+        self.segments.remove(&indexed_segment(graph, edge));
+    }
+
+    /// The `k` edges whose geometry passes closest to the query point, nearest first.
+    pub fn nearest_edges(&self, graph: &Graph, lat: f64, lon: f64, k: usize) -> Vec<(EdgeIndex, f64)> {
+        let mut found: Vec<(EdgeIndex, f64)> = Vec::with_capacity(k + 1);
+        for (segment, distance_2) in self.segments.nearest_neighbor_iter_with_distance_2(&[lon, lat]) {
+            // A degree of longitude shrinks towards the poles, so a candidate this far away in
+            // degree space is at least this far in metres
+            let degrees = distance_2.sqrt();
+            let max_lat = (lat.abs() + degrees).min(89.0);
+            let lower_bound = degrees * MIN_METRES_PER_DEGREE * max_lat.to_radians().cos();
+            if found.len() == k && found.last().is_none_or(|&(_, worst)| lower_bound > worst) {
+                break;
+            }
+            let distance = segment_distance(graph, segment.data, lat, lon);
+            let at = found.partition_point(|&(_, d)| d <= distance);
+            if at < k {
+                found.insert(at, (segment.data, distance));
+                found.truncate(k);
+            }
+        }
+        found
+    }
+}
+
+fn indexed_segment(graph: &DiGraph<Node, Edge>, edge: EdgeIndex) -> IndexedSegment {
+    let (source, target) = graph.edge_endpoints(edge).expect("edge is in the graph");
+    let from = graph[source].point;
+    let to = graph[target].point;
+    GeomWithData::new(RLine::new([from.x(), from.y()], [to.x(), to.y()]), edge)
+}
+
+/// Geodesic distance in metres from the query point to the closest point of an edge.
+fn segment_distance(graph: &Graph, edge: EdgeIndex, lat: f64, lon: f64) -> f64 {
+    let query = Point::new(lon, lat);
+    closest_point_on_edge(graph, edge, query).geodesic_distance(&query)
+}
+
+/// Closest point of an edge's straight segment to `query`, in degree space.
+pub fn closest_point_on_edge(graph: &Graph, edge: EdgeIndex, query: Point<f64>) -> Point<f64> {
+    let (source, target) = graph
+        .graph
+        .edge_endpoints(edge)
+        .expect("edge from the spatial index exists in the graph");
+    let line = Line::new(graph.graph[source].point, graph.graph[target].point);
+    match line.closest_point(&query) {
+        Closest::Intersection(point) | Closest::SinglePoint(point) => point,
+        Closest::Indeterminate => graph.graph[source].point,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::Profile;
+    use crate::test_support::Fixture;
+
+    /// Every edge ranked by its distance to the query point, for comparison.
+    fn brute_force(graph: &Graph, lat: f64, lon: f64, k: usize) -> Vec<(EdgeIndex, f64)> {
+        let mut all = graph
+            .graph
+            .edge_indices()
+            .map(|edge| (edge, segment_distance(graph, edge, lat, lon)))
+            .collect::<Vec<_>>();
+        all.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        all.truncate(k);
+        all
+    }
+
+    fn distances(found: &[(EdgeIndex, f64)]) -> Vec<f64> {
+        found.iter().map(|&(_, distance)| distance).collect()
+    }
+
+    #[test]
+    fn finds_the_nearest_edges_in_metres() {
+        let mut fixture = Fixture::default();
+        for i in 0..10 {
+            let lon = 100.0 + i as f64 * 0.001;
+            fixture
+                .node(i * 2 + 1, lon, 13.0)
+                .node(i * 2 + 2, lon, 13.001)
+                .way(i + 1, &[i * 2 + 1, i * 2 + 2], &[("highway", "residential"), ("oneway", "yes")]);
+        }
+        let graph = fixture.graph(Profile::Car);
+
+        let found = graph.index.nearest_edges(&graph, 13.0005, 100.0042, 3);
+        let ways = found.iter().map(|&(edge, _)| graph.graph[edge].way_id.0).collect::<Vec<_>>();
+        assert_eq!(ways, vec![5, 6, 4]);
+        assert!((found[0].1 - 0.0002 * 108_500.0).abs() < 1.0, "{:?}", found);
+        assert_eq!(distances(&found), distances(&brute_force(&graph, 13.0005, 100.0042, 3)));
+        assert_eq!(graph.index.nearest_edges(&graph, 13.0005, 100.0042, 20).len(), 10);
+        assert!(graph.index.nearest_edges(&graph, 13.0005, 100.0042, 0).is_empty());
+    }
+
+    #[test]
+    fn ranks_by_metres_near_the_poles() {
+        // At 78°N a degree of longitude is about 23 km, so a road 0.009° east of the query is
+        // closer than a dozen roads 0.002° north of it, though further away in degrees
+        let (lat, lon) = (78.0, 15.0);
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, lon + 0.009, lat - 0.001)
+            .node(2, lon + 0.009, lat + 0.001)
+            .way(1, &[1, 2], &[("highway", "residential"), ("oneway", "yes")]);
+        for i in 0..12 {
+            let north = lat + 0.002 + i as f64 * 0.0001;
+            fixture
+                .node(i * 2 + 11, lon - 0.001, north)
+                .node(i * 2 + 12, lon + 0.001, north)
+                .way(i + 10, &[i * 2 + 11, i * 2 + 12], &[("highway", "residential"), ("oneway", "yes")]);
+        }
+        let graph = fixture.graph(Profile::Car);
+
+        let found = graph.index.nearest_edges(&graph, lat, lon, 2);
+        assert_eq!(graph.graph[found[0].0].way_id.0, 1, "{:?}", found);
+        assert!(found[0].1 < 215.0 && found[1].1 > 220.0, "{:?}", found);
+        assert_eq!(distances(&found), distances(&brute_force(&graph, lat, lon, 2)));
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::path::Path;
+
+use base::error::Error as BaseError;
+use base::utils::open_file;
+use osmpbfreader::Tags;
+
+use crate::profile::Profile;
+
+const MPH_TO_KMH: f64 = 1.609344;
+
+/// Speed assumed when neither `maxspeed` nor the highway class gives one, in km/h.
+const FALLBACK_SPEED_KMH: f64 = 30.0;
+
+/// Default travel speeds (km/h) per `highway` class, used when a way has no usable `maxspeed`.
+///
+/// The defaults follow Thai national limits and can be overridden from a JSON object
+/// such as `{"primary": 70, "residential": 25}`.
+#[derive(Debug, Clone)]
+pub struct SpeedTable {
+    speeds: HashMap<String, f64>,
+}
+
+impl Default for SpeedTable {
+    fn default() -> Self {
+        let speeds = [
+            ("motorway", 110.0),
+            ("motorway_link", 60.0),
+            ("trunk", 90.0),
+            ("trunk_link", 50.0),
+            ("primary", 80.0),
+            ("primary_link", 40.0),
+            ("secondary", 60.0),
+            ("secondary_link", 40.0),
+            ("tertiary", 50.0),
+            ("tertiary_link", 30.0),
+            ("unclassified", 40.0),
+            ("road", 40.0),
+            ("residential", 30.0),
+            ("living_street", 10.0),
+            ("service", 20.0),
+            ("track", 15.0),
+        ]
+        .into_iter()
+        .map(|(highway, speed)| (highway.to_string(), speed))
+        .collect();
+        SpeedTable { speeds }
+    }
+}
+
+impl SpeedTable {
+    /// Loads the defaults and overrides them with the entries of a JSON speed file.
+    /// Every override must be a positive speed.
+    pub fn from_file(path: &Path) -> Result<Self, BaseError> {
+        let invalid = |message: String| BaseError::InvalidSpeedTable {
+            path: path.to_path_buf(),
+            message,
+        };
+        let overrides: HashMap<String, f64> =
+            serde_json::from_reader(BufReader::new(open_file(path)?)).map_err(|e| invalid(e.to_string()))?;
+        if let Some((highway, speed)) = overrides.iter().find(|(_, speed)| **speed <= 0.0) {
+            return Err(invalid(format!("speed for {} must be positive, got {}", highway, speed)));
+        }
+        let mut table = SpeedTable::default();
+        table.speeds.extend(overrides);
+        Ok(table)
+    }
+
+    pub fn highway_speed(&self, highway: Option<&str>) -> f64 {
+        highway
+            .and_then(|h| self.speeds.get(h))
+            .copied()
+            .unwrap_or(FALLBACK_SPEED_KMH)
+    }
+
+    /// Travel speed in km/h for one direction of a way under the given profile.
+    pub fn way_speed(&self, tags: &Tags, profile: Profile, forward: bool) -> f64 {
+        let directional = if forward { "maxspeed:forward" } else { "maxspeed:backward" };
+        let maxspeed = tags
+            .get(directional)
+            .or_else(|| tags.get("maxspeed"))
+            .and_then(|v| parse_maxspeed(v));
+        let highway = tags.get("highway").map(|v| v.as_str());
+        let speed = maxspeed.unwrap_or_else(|| self.highway_speed(highway));
+        speed.min(profile.max_speed())
+    }
+}
+
+/// Parses a `maxspeed` value into km/h.
+///
+/// Handles plain numbers, `mph` suffixes and implicit country values such as `TH:urban`.
+pub fn parse_maxspeed(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if let Some(implicit) = implicit_maxspeed(value) {
+        return Some(implicit);
+    }
+    // Conditional or multi-valued tags use the first value
+    let value = value.split(';').next()?.trim();
+    let kmh = match value.strip_suffix("mph") {
+        Some(mph) => mph.trim().parse::<f64>().ok()? * MPH_TO_KMH,
+        None => value.strip_suffix("km/h").unwrap_or(value).trim().parse::<f64>().ok()?,
+    };
+    (kmh.is_finite() && kmh > 0.0).then_some(kmh)
+}
+
+fn implicit_maxspeed(value: &str) -> Option<f64> {
+    let speed = match value {
+        "walk" => 5.0,
+        "TH:living_street" => 10.0,
+        "TH:urban" => 80.0,
+        "TH:rural" => 90.0,
+        "TH:trunk" => 90.0,
+        "TH:motorway" => 120.0,
+        "GB:nsl_single" => 60.0 * MPH_TO_KMH,
+        "GB:nsl_dual" | "GB:motorway" => 70.0 * MPH_TO_KMH,
+        "DE:urban" | "FR:urban" | "IT:urban" | "RU:urban" => 50.0,
+        "DE:rural" | "RU:rural" => 100.0,
+        "FR:rural" | "IT:rural" => 80.0,
+        _ => {
+            // Unknown `XX:urban` / `XX:rural` zones get a conservative guess
+            let (_, zone) = value.split_once(':')?;
+            match zone {
+                "urban" => 50.0,
+                "rural" => 80.0,
+                "motorway" => 110.0,
+                "living_street" => 10.0,
+                _ => return None,
+            }
+        }
+    };
+    Some(speed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_maxspeed_values() {
+        assert_eq!(parse_maxspeed("50"), Some(50.0));
+        assert_eq!(parse_maxspeed(" 60 km/h "), Some(60.0));
+        assert_eq!(parse_maxspeed("30 mph"), Some(30.0 * MPH_TO_KMH));
+        assert_eq!(parse_maxspeed("20mph"), Some(20.0 * MPH_TO_KMH));
+        assert_eq!(parse_maxspeed("80;60"), Some(80.0));
+        assert_eq!(parse_maxspeed("TH:urban"), Some(80.0));
+        assert_eq!(parse_maxspeed("XX:rural"), Some(80.0));
+        assert_eq!(parse_maxspeed("walk"), Some(5.0));
+    }
+
+    #[test]
+    fn rejects_unusable_maxspeed_values() {
+        for value in ["0", "-30", "0 mph", "-5 mph", "none", "signals", "NaN", "inf mph", "XX:unknown", ""] {
+            assert_eq!(parse_maxspeed(value), None, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn speed_file_overrides_defaults() {
+        let path = std::env::temp_dir().join(format!("speeds_{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"residential": 25, "busway": 40.5}"#).unwrap();
+        let table = SpeedTable::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(table.highway_speed(Some("residential")), 25.0);
+        assert_eq!(table.highway_speed(Some("busway")), 40.5);
+        assert_eq!(table.highway_speed(Some("primary")), 80.0);
+        assert_eq!(table.highway_speed(None), FALLBACK_SPEED_KMH);
+    }
+
+    #[test]
+    fn speed_file_rejects_non_positive_speeds() {
+        for (name, json) in [("zero", r#"{"primary": 0}"#), ("negative", r#"{"track": -10}"#), ("text", r#"{"track": "fast"}"#)] {
+            let path = std::env::temp_dir().join(format!("speeds_{}_{}.json", name, std::process::id()));
+            std::fs::write(&path, json).unwrap();
+            let result = SpeedTable::from_file(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert!(matches!(result, Err(BaseError::InvalidSpeedTable { .. })), "{}", json);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! In-memory OSM fixtures for unit tests, built into graphs through `build_graph`.
+
+use base::model::osm_model::Osm;
+use indicatif::ProgressStyle;
+use osmpbfreader::{Node, NodeId, OsmId, Ref, Relation, RelationId, Tags, Way, WayId};
+
+use crate::profile::Profile;
+use crate::speed::SpeedTable;
+use crate::{build_graph, Graph};
+
+pub fn tags(pairs: &[(&str, &str)]) -> Tags {
+    let mut tags = Tags::new();
+    for (key, value) in pairs {
+        tags.insert((*key).into(), (*value).into());
+    }
+    tags
+}
+
+#[derive(Default)]
+pub struct Fixture {
+    pub osm: Osm,
+}
+
+impl Fixture {
+    pub fn node(&mut self, id: i64, lon: f64, lat: f64) -> &mut Self {
+        self.osm.add_node(Node {
+            id: NodeId(id),
+            tags: Tags::new(),
+            decimicro_lat: (lat * 1e7).round() as i32,
+            decimicro_lon: (lon * 1e7).round() as i32,
+        });
+        self
+    }
+
+    pub fn way(&mut self, id: i64, nodes: &[i64], pairs: &[(&str, &str)]) -> &mut Self {
+        self.osm.add_way(Way {
+            id: WayId(id),
+            tags: tags(pairs),
+            nodes: nodes.iter().map(|&id| NodeId(id)).collect(),
+        });
+        self
+    }
+
+    pub fn relation(&mut self, id: i64, members: &[(OsmId, &str)], pairs: &[(&str, &str)]) -> &mut Self {
+        self.osm.add_relation(Relation {
+            id: RelationId(id),
+            tags: tags(pairs),
+            refs: members
+                .iter()
+                .map(|&(member, role)| Ref {
+                    member,
+                    role: role.into(),
+                })
+                .collect(),
+        });
+        self
+    }
+
+    /// Graph over the highway ways and restriction relations, in id order like a PBF.
+    pub fn graph(&self, profile: Profile) -> Graph {
+        let mut ways = self
+            .osm
+            .ways
+            .values()
+            .filter(|way| way.tags.contains_key("highway"))
+            .cloned()
+            .collect::<Vec<_>>();
+        ways.sort_by_key(|way| way.id);
+        let mut relations = self
+            .osm
+            .relations
+            .values()
+            .filter(|relation| relation.tags.get("type").map(|v| v.as_str()) == Some("restriction"))
+            .cloned()
+            .collect::<Vec<_>>();
+        relations.sort_by_key(|relation| relation.id);
+        build_graph(
+            &self.osm,
+            &ways,
+            &relations,
+            profile,
+            &SpeedTable::default(),
+            false,
+            &ProgressStyle::default_bar(),
+        )
+        .expect("only strict builds fail")
+    }
+}
@@ -0,0 +1,338 @@
+//! Incremental graph updates from OsmChange (`.osc` / `.osc.gz`) files and replication feeds.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use base::model::node_location_model::NodeCacheConfig;
+use base::model::osm_change_model::{ChangeSummary, OsmChange};
+use base::model::osm_model::Osm;
+use base::model::pg_model::PgImportConfig;
+use base::model::replication_model::{ReplicationClient, ReplicationConfig};
+use indicatif::ProgressStyle;
+use osmpbfreader::{NodeId, Relation, Way, WayId};
+use petgraph::graph::EdgeIndex;
+use petgraph::Direction;
+use tracing::{debug, info};
+
+use crate::profile::Profile;
+use crate::restriction::TurnRestrictions;
+use crate::speed::SpeedTable;
+use crate::{add_way_edges, build_graph, clip_options, load_speeds, open_input, Args, BaseError, Graph, NodeSource};
+
+/// Loads the PBF into an `Osm` store, builds one graph per profile and then applies every
+/// `--apply-change` file in order, followed by any missing diffs from `--replication-url`,
+/// updating the graphs incrementally. The final store is written out with `--write-pbf`
+/// and imported into PostGIS with `--import-pg`.
+pub fn read_updated_graphs(
+    input: &Path,
+    args: &Args,
+    progress_style: &ProgressStyle,
+) -> Result<Vec<(Profile, Graph)>, Box<dyn std::error::Error>> {
+    info!("Reading OSM file: {}", input.display());
+    let file = open_input(input, args)?;
+    let node_cache = args.node_cache.as_ref().map(|path| NodeCacheConfig {
+        memory_limit: args.node_cache_memory * 1024 * 1024,
+        spill_dir: path.clone(),
+    });
+    let clip = clip_options(args)?;
+    let mut osm = Osm::read_osm_source(file, node_cache, clip.as_ref())?;
+    let speeds = load_speeds(args)?;
+
+    let ways = highway_ways(&osm);
+    let relations = restriction_relations(&osm);
+    let mut graphs = args
+        .profile
+        .iter()
+        .map(|&profile| {
+            let graph = build_graph(&osm, &ways, &relations, profile, &speeds, args.strict, progress_style)?;
+            Ok((profile, graph))
+        })
+        .collect::<Result<Vec<_>, BaseError>>()?;
+    drop(ways);
+
+    for path in &args.apply_change {
+        let change = OsmChange::from_path(path)?;
+        let summary = osm.apply_change(&change)?;
+        info!(
+            "Applied {}: {} created, {} modified, {} deleted",
+            path.display(),
+            summary.created,
+            summary.modified,
+            summary.deleted
+        );
+        update_graphs(&mut graphs, &osm, &summary, &speeds);
+    }
+
+    let mut replicated = None;
+    if let (Some(base_url), Some(state_path)) = (&args.replication_url, &args.replication_state) {
+        let client = ReplicationClient::new(ReplicationConfig {
+            base_url: base_url.clone(),
+            state_path: state_path.clone(),
+        });
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let state = runtime.block_on(client.update(&mut osm, |sequence, osm, summary| {
+            debug!("Updating graphs for replication sequence {}", sequence);
+            update_graphs(&mut graphs, osm, summary, &speeds);
+        }))?;
+        if let Some(state) = state {
+            info!("Caught up to replication sequence {} from {}", state.sequence, base_url);
+            replicated = Some((client, state));
+        }
+    }
+
+    if let Some(path) = &args.write_pbf {
+        osm.write_pbf(path)?;
+    }
+    // Only advance the replication state once the data it describes is on disk
+    if let Some((client, state)) = replicated {
+        client.save_state(&state)?;
+    }
+    if let Some(connection) = &args.import_pg {
+        let config = PgImportConfig {
+            connection: connection.clone(),
+            schema: args.pg_schema.clone(),
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        runtime.block_on(osm.import_pg(&config))?;
+    }
+    Ok(graphs)
+}
+
+fn update_graphs(graphs: &mut [(Profile, Graph)], osm: &Osm, summary: &ChangeSummary, speeds: &SpeedTable) {
+    let relations = (!summary.relations.is_empty()).then(|| restriction_relations(osm));
+    for (profile, graph) in graphs {
+        graph.apply_change(osm, summary, *profile, speeds, relations.as_deref());
+    }
+}
+
+/// Highway ways in id order, as collected from a PBF.
+fn highway_ways(osm: &Osm) -> Vec<Way> {
+    let mut ways = osm
+        .ways
+        .values()
+        .filter(|way| way.tags.contains_key("highway"))
+        .cloned()
+        .collect::<Vec<_>>();
+    ways.sort_by_key(|way| way.id);
+    ways
+}
+
+/// Turn restriction relations in id order, as collected from a PBF.
+fn restriction_relations(osm: &Osm) -> Vec<Relation> {
+    let mut relations = osm
+        .relations
+        .values()
+        .filter(|relation| relation.tags.get("type").map(|v| v.as_str()) == Some("restriction"))
+        .cloned()
+        .collect::<Vec<_>>();
+    relations.sort_by_key(|relation| relation.id);
+    relations
+}
+
+impl Graph {
+    /// Rebuilds the edges of every way touched by `summary` from the already updated `osm`
+    /// store, leaving the rest of the graph alone and updating the spatial index in place.
+    /// The result matches `build_graph` over the updated store, up to the order of nodes and
+    /// edges and a `max_speed` that is never lowered.
+    ///
+    /// `restrictions` replaces the turn restrictions when relations changed.
+    pub fn apply_change(
+        &mut self,
+        osm: &Osm,
+        summary: &ChangeSummary,
+        profile: Profile,
+        speeds: &SpeedTable,
+        restrictions: Option<&[Relation]>,
+    ) {
+        // Edited ways, plus every way passing through a moved or deleted node
+        let mut affected: HashSet<WayId> = summary.ways.iter().map(|&id| WayId(id)).collect();
+        for &id in &summary.nodes {
+            if let Some(&idx) = self.node_indices.get(&NodeId(id)) {
+                for direction in [Direction::Outgoing, Direction::Incoming] {
+                    affected.extend(self.graph.edges_directed(idx, direction).map(|e| e.weight().way_id));
+                }
+            }
+        }
+
+        // Drop their edges, remembering nodes that may no longer belong to any way
+        let mut touched: HashSet<NodeId> = summary.nodes.iter().map(|&id| NodeId(id)).collect();
+        let mut removed = Vec::new();
+        for edge in self.graph.edge_indices() {
+            let weight = &self.graph[edge];
+            if affected.contains(&weight.way_id) {
+                touched.insert(weight.source);
+                touched.insert(weight.target);
+                removed.push(edge);
+            }
+        }
+        // Highest index first, so an edge moved into a freed index is never one still to remove
+        for edge in removed.into_iter().rev() {
+            self.remove_edge(edge);
+        }
+
+        // Moved nodes only had edges of affected ways, which are re-indexed below
+        for &id in &summary.nodes {
+            if let (Some(&idx), Some(node)) = (self.node_indices.get(&NodeId(id)), osm.get_node(NodeId(id))) {
+                self.graph[idx] = node;
+            }
+        }
+
+        let mut ways = affected
+            .iter()
+            .filter_map(|id| osm.get_way_by_id(id.0))
+            .filter(|way| way.tags.contains_key("highway") && profile.allows_way(&way.tags))
+            .collect::<Vec<_>>();
+        ways.sort_by_key(|way| way.id);
+        let first_new_edge = self.graph.edge_count();
+        let mut kept = HashSet::new();
+        for way in ways {
+            for &node_id in &way.nodes {
+                kept.insert(node_id);
+                if !self.node_indices.contains_key(&node_id) {
+                    if let Some(node) = osm.get_node(node_id) {
+                        self.add_node(node);
+                    }
+                }
+            }
+            // `add_edge` only ever raises `max_speed`, which keeps it an upper bound
+            add_way_edges(self, osm, way, profile, speeds);
+        }
+        for edge in first_new_edge..self.graph.edge_count() {
+            self.index.insert_edge(&self.graph, EdgeIndex::new(edge));
+        }
+
+        for id in touched.difference(&kept) {
+            let orphaned = self
+                .node_indices
+                .get(id)
+                .is_some_and(|&idx| self.graph.neighbors_undirected(idx).next().is_none());
+            if orphaned {
+                self.remove_node(*id);
+            }
+        }
+
+        if let Some(relations) = restrictions {
+            self.restrictions = TurnRestrictions::from_relations(relations, profile);
+        }
+        info!(
+            "Updated {} graph: {} ways rebuilt, now {} nodes and {} edges",
+            profile.as_str(),
+            affected.len(),
+            self.graph.node_count(),
+            self.graph.edge_count()
+        );
+    }
+
+    fn remove_node(&mut self, id: NodeId) {
+        // Only nodes without edges are removed, so no indexed segment refers to them
+        if let Some(idx) = self.node_indices.remove(&id) {
+            self.graph.remove_node(idx);
+            // petgraph moves the last node into the freed index
+            if let Some(moved) = self.graph.node_weight(idx) {
+                self.node_indices.insert(moved.id, idx);
+            }
+        }
+    }
+
+    fn remove_edge(&mut self, edge: EdgeIndex) {
+        let last = EdgeIndex::new(self.graph.edge_count() - 1);
+        self.index.remove_edge(&self.graph, edge);
+        if last != edge {
+            self.index.remove_edge(&self.graph, last);
+        }
+        self.graph.remove_edge(edge);
+        // As with nodes, the last edge takes over the freed index
+        if last != edge {
+            self.index.insert_edge(&self.graph, edge);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spatial::SpatialIndex;
+    use crate::test_support::Fixture;
+
+    const CHANGE: &str = r#"<osmChange version="0.6">
+  <modify>
+    <node id="2" lat="13.0005" lon="100.001"/>
+    <way id="10"><nd ref="1"/><nd ref="2"/><tag k="highway" v="residential"/></way>
+    <way id="12"><nd ref="4"/><nd ref="5"/><tag k="highway" v="primary"/><tag k="oneway" v="yes"/></way>
+  </modify>
+  <delete>
+    <way id="11"/>
+  </delete>
+  <create>
+    <node id="6" lat="13.002" lon="100.004"/>
+    <way id="13"><nd ref="5"/><nd ref="6"/><tag k="highway" v="residential"/></way>
+  </create>
+</osmChange>"#;
+
+    fn nodes(graph: &Graph) -> Vec<(i64, u64, u64)> {
+        let mut nodes = graph
+            .graph
+            .node_weights()
+            .map(|n| (n.id.0, n.point.x().to_bits(), n.point.y().to_bits()))
+            .collect::<Vec<_>>();
+        nodes.sort();
+        nodes
+    }
+
+    fn edges(graph: &Graph) -> Vec<(i64, i64, i64, u64, u64, Option<String>)> {
+        let mut edges = graph
+            .graph
+            .edge_weights()
+            .map(|e| {
+                let (distance, duration) = (e.distance.to_bits(), e.duration.to_bits());
+                (e.source.0, e.target.0, e.way_id.0, distance, duration, e.highway_type.clone())
+            })
+            .collect::<Vec<_>>();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn apply_change_matches_a_fresh_build() {
+        let mut fixture = Fixture::default();
+        fixture
+            .node(1, 100.000, 13.000)
+            .node(2, 100.001, 13.000)
+            .node(3, 100.002, 13.000)
+            .node(4, 100.003, 13.001)
+            .node(5, 100.004, 13.001)
+            .way(10, &[1, 2, 3], &[("highway", "residential")])
+            .way(11, &[3, 4], &[("highway", "residential")])
+            .way(12, &[4, 5], &[("highway", "primary")]);
+        let mut graph = fixture.graph(Profile::Car);
+
+        let change = OsmChange::from_reader(CHANGE.as_bytes()).unwrap();
+        let summary = fixture.osm.apply_change(&change).unwrap();
+        graph.apply_change(&fixture.osm, &summary, Profile::Car, &SpeedTable::default(), None);
+        let fresh = fixture.graph(Profile::Car);
+
+        // Node 3 is no longer on any way, node 6 is new
+        assert_eq!(nodes(&graph), nodes(&fresh));
+        assert!(!graph.node_indices.contains_key(&NodeId(3)));
+        assert_eq!(edges(&graph), edges(&fresh));
+        assert!(graph.max_speed >= fresh.max_speed);
+        for (id, &idx) in &graph.node_indices {
+            assert_eq!(graph.graph[idx].id, *id);
+        }
+
+        // The updated index answers like one rebuilt from scratch
+        let rebuilt = SpatialIndex::build(&graph);
+        for node in graph.graph.node_weights() {
+            let (lat, lon) = (node.point.y(), node.point.x());
+            let sorted = |mut found: Vec<(EdgeIndex, f64)>| {
+                found.sort_by_key(|&(edge, _)| edge);
+                found
+            };
+            let edge_count = graph.graph.edge_count();
+            assert_eq!(
+                sorted(graph.index.nearest_edges(&graph, lat, lon, edge_count)),
+                sorted(rebuilt.nearest_edges(&graph, lat, lon, edge_count))
+            );
+        }
+    }
+}